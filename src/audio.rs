@@ -0,0 +1,381 @@
+pub(crate) mod audio {
+    //! Audio channels, preloaded sounds and rate limiting.
+    //!
+    //! Music, sound effects and menu sounds each play on their own kira channel, so their
+    //! volumes can be set and muted separately. What music plays is up to the music director.
+    //!
+    //! Gameplay code doesn't play sounds directly: it sends a [`PlaySound`], and `play_sounds`
+    //! drops sounds that were already played too recently, so thirty thumps in one frame come out
+    //! as one.
+    //!
+    //! Sounds with a position are spatial: every frame, `update_spatial_audio` pans and attenuates
+    //! each [`AudioEmitter`]'s instances from where it is relative to the [`AudioListener`] on the
+    //! camera. bevy_kira_audio's own `AudioEmitter` does the same, but it sets instance volumes
+    //! without the channel volume, so muting sound effects wouldn't mute them.
+
+    use std::collections::HashMap;
+    use bevy::prelude::*;
+    use bevy_kira_audio::{AudioChannel, AudioControl, AudioInstance, AudioSource, AudioTween, PlaybackState};
+
+    use crate::loading::loading::LoadingAssets;
+    use crate::settings::settings::Settings;
+
+    // Spatial sounds are at full volume up to this distance...
+    const REFERENCE_DISTANCE:f32 = 5.0;
+    // ...and silent past this one
+    const MAX_DISTANCE:f32 = 40.0;
+    // How far to the side a sound fully to the left or right is panned, from 0 to 0.5
+    const PAN_STRENGTH:f32 = 0.4;
+    const AMBIENT_VOLUME:f32 = 0.5;
+
+    /// Audio channel for background music
+    #[derive(Resource)]
+    pub(crate) struct Music;
+
+    /// Audio channel for gameplay sound effects
+    #[derive(Resource)]
+    pub(crate) struct Sfx;
+
+    /// Audio channel for menu sounds
+    #[derive(Resource)]
+    pub(crate) struct Ui;
+
+    #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+    pub(crate) enum Sound {
+        Jump,
+        Thump,
+        ImpactHard,
+        ImpactMetal,
+        ImpactIce,
+        ImpactRubber,
+        Boing,
+        RollStone,
+        RollMetal,
+        RollIce,
+        Pickup,
+        Click,
+        Hum,
+    }
+
+    impl Sound {
+        /// Shortest time between two plays of this sound, in seconds
+        fn min_interval(&self) -> f64 {
+            match self {
+                Sound::Jump => 0.1,
+                Sound::Thump => 0.08,
+                Sound::ImpactHard => 0.08,
+                Sound::ImpactMetal => 0.08,
+                Sound::ImpactIce => 0.08,
+                Sound::ImpactRubber => 0.08,
+                Sound::Boing => 0.15,
+                // Loops, only started once
+                Sound::RollStone | Sound::RollMetal | Sound::RollIce => 0.0,
+                Sound::Pickup => 0.05,
+                Sound::Click => 0.05,
+                Sound::Hum => 0.0,
+            }
+        }
+
+        /// Whether the music gets quieter while this plays, so it can be heard
+        pub(crate) fn ducks_music(&self) -> bool {
+            matches!(self, Sound::Pickup | Sound::ImpactHard)
+        }
+    }
+
+    /// Handles to every sound effect, loaded once at startup
+    #[derive(Resource)]
+    pub(crate) struct SoundBank {
+        jump: Handle<AudioSource>,
+        thump: Handle<AudioSource>,
+        impact_hard: Handle<AudioSource>,
+        impact_metal: Handle<AudioSource>,
+        impact_ice: Handle<AudioSource>,
+        impact_rubber: Handle<AudioSource>,
+        boing: Handle<AudioSource>,
+        roll_stone: Handle<AudioSource>,
+        roll_metal: Handle<AudioSource>,
+        roll_ice: Handle<AudioSource>,
+        pickup: Handle<AudioSource>,
+        click: Handle<AudioSource>,
+        hum: Handle<AudioSource>,
+    }
+
+    impl SoundBank {
+        pub(crate) fn get(&self, sound: Sound) -> Handle<AudioSource> {
+            match sound {
+                Sound::Jump => self.jump.clone(),
+                Sound::Thump => self.thump.clone(),
+                Sound::ImpactHard => self.impact_hard.clone(),
+                Sound::ImpactMetal => self.impact_metal.clone(),
+                Sound::ImpactIce => self.impact_ice.clone(),
+                Sound::ImpactRubber => self.impact_rubber.clone(),
+                Sound::Boing => self.boing.clone(),
+                Sound::RollStone => self.roll_stone.clone(),
+                Sound::RollMetal => self.roll_metal.clone(),
+                Sound::RollIce => self.roll_ice.clone(),
+                Sound::Pickup => self.pickup.clone(),
+                Sound::Click => self.click.clone(),
+                Sound::Hum => self.hum.clone(),
+            }
+        }
+    }
+
+    /// Send this to play a sound
+    #[derive(Event)]
+    pub(crate) struct PlaySound {
+        pub(crate) sound: Sound,
+        /// Multiplier on the channel volume
+        pub(crate) volume: f32,
+        /// 1 is normal speed. Faster is higher pitched.
+        pub(crate) playback_rate: f64,
+        /// Where the sound comes from. Sounds without a position play the same everywhere.
+        pub(crate) position: Option<Vec3>,
+    }
+
+    impl PlaySound {
+        pub(crate) fn new(sound: Sound) -> PlaySound {
+            PlaySound { sound, volume: 1.0, playback_rate: 1.0, position: None }
+        }
+
+        pub(crate) fn at(sound: Sound, position: Vec3) -> PlaySound {
+            PlaySound { sound, volume: 1.0, playback_rate: 1.0, position: Some(position) }
+        }
+    }
+
+    /// Hears spatial sounds. Goes on the camera.
+    #[derive(Component)]
+    pub(crate) struct AudioListener;
+
+    struct EmitterInstance {
+        sound: Sound,
+        handle: Handle<AudioInstance>,
+        /// Volume before attenuation
+        volume: f32,
+        /// Frames since it was played. Instances only exist once their sound has loaded, so
+        /// until one has started, a missing instance doesn't mean it has finished.
+        age: u32,
+        started: bool,
+    }
+
+    impl EmitterInstance {
+        fn new(sound: Sound, handle: Handle<AudioInstance>, volume: f32) -> EmitterInstance {
+            EmitterInstance { sound, handle, volume, age: 0, started: false }
+        }
+    }
+
+    // Give up on instances that still haven't started after this many frames
+    const MAX_START_FRAMES:u32 = 120;
+
+    /// Entity that spatial sounds come from
+    #[derive(Component)]
+    pub(crate) struct AudioEmitter {
+        instances: Vec<EmitterInstance>,
+        /// Despawn the entity once nothing is playing. Used for one-shot sounds.
+        despawn_when_done: bool,
+    }
+
+    impl AudioEmitter {
+        /// Loops every one of `sounds` from the emitter, at `volume` before attenuation
+        pub(crate) fn looping(sounds: &[Sound], volume: f32, bank: &SoundBank, sfx: &AudioChannel<Sfx>) -> AudioEmitter {
+            let instances = sounds.iter().map(|sound| {
+                // Starts silent, update_spatial_audio sets the volume
+                let instance = sfx.play(bank.get(*sound)).looped().with_volume(0.0).handle();
+                EmitterInstance::new(*sound, instance, volume)
+            }).collect();
+            AudioEmitter { instances, despawn_when_done: false }
+        }
+
+        /// Volumes before attenuation of the sounds still playing. Instances that stopped or never
+        /// started are dropped, so look sounds up by what they are, not by position.
+        pub(crate) fn volumes_mut(&mut self) -> impl Iterator<Item = (Sound, &mut f32)> {
+            self.instances.iter_mut().map(|instance| (instance.sound, &mut instance.volume))
+        }
+
+        /// Emitter whose `sounds` have all started, without playing anything
+        #[cfg(test)]
+        pub(crate) fn started(sounds: &[Sound]) -> AudioEmitter {
+            let instances = sounds.iter().map(|sound| EmitterInstance {
+                started: true,
+                ..EmitterInstance::new(*sound, Handle::default(), 0.0)
+            }).collect();
+            AudioEmitter { instances, despawn_when_done: false }
+        }
+    }
+
+    /// Loops a sound from this entity for as long as it exists
+    #[derive(Component)]
+    pub(crate) struct AmbientSound(pub(crate) Sound);
+
+    /// When each sound was last played, in seconds since startup
+    #[derive(Resource, Default)]
+    pub(crate) struct SoundLimiter {
+        last_played: HashMap<Sound, f64>,
+    }
+
+    pub(crate) fn load_sound_bank(
+        mut commands: Commands,
+        asset_server: Res<AssetServer>,
+        mut loading: ResMut<LoadingAssets>,
+    ) {
+        let bank = SoundBank {
+            jump: asset_server.load("jumping.wav"),
+            thump: asset_server.load("thump.wav"),
+            impact_hard: asset_server.load("impact_hard.wav"),
+            impact_metal: asset_server.load("impact_metal.wav"),
+            impact_ice: asset_server.load("impact_ice.wav"),
+            impact_rubber: asset_server.load("impact_rubber.wav"),
+            boing: asset_server.load("boing.wav"),
+            roll_stone: asset_server.load("roll_stone.wav"),
+            roll_metal: asset_server.load("roll_metal.wav"),
+            roll_ice: asset_server.load("roll_ice.wav"),
+            pickup: asset_server.load("pickup.wav"),
+            click: asset_server.load("click.wav"),
+            hum: asset_server.load("hum.wav"),
+        };
+        for handle in [
+            &bank.jump, &bank.thump, &bank.impact_hard, &bank.impact_metal, &bank.impact_ice, &bank.impact_rubber, &bank.boing,
+            &bank.roll_stone, &bank.roll_metal, &bank.roll_ice, &bank.pickup, &bank.click, &bank.hum,
+        ] {
+            loading.handles.push(handle.clone_untyped());
+        }
+        commands.insert_resource(bank);
+    }
+
+    /**
+     * Attenuation and panning for a sound at `position`, as heard by `listener`
+     */
+    fn spatial_mix(listener: &GlobalTransform, position: Vec3) -> (f32, f64) {
+        let offset = position - listener.translation();
+        let distance = offset.length();
+
+        let attenuation = 1.0 - ((distance - REFERENCE_DISTANCE) / (MAX_DISTANCE - REFERENCE_DISTANCE)).clamp(0.0, 1.0);
+        // 0 is fully left, 1 fully right
+        let side = if distance > 0.0 { listener.right().dot(offset / distance) } else { 0.0 };
+        let panning = 0.5 + PAN_STRENGTH * side;
+
+        (attenuation, panning as f64)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn play_sounds(
+        mut commands: Commands,
+        time: Res<Time>,
+        mut play_sound: EventReader<PlaySound>,
+        mut limiter: ResMut<SoundLimiter>,
+        bank: Res<SoundBank>,
+        settings: Res<Settings>,
+        sfx: Res<AudioChannel<Sfx>>,
+        ui: Res<AudioChannel<Ui>>,
+        listeners: Query<&GlobalTransform, With<AudioListener>>,
+    ) {
+        let now = time.raw_elapsed_seconds_f64();
+        let listener = listeners.get_single().ok();
+
+        for ev in play_sound.iter() {
+            let ready = limiter.last_played.get(&ev.sound).map_or(true, |last| now - last >= ev.sound.min_interval());
+            if !ready {
+                continue
+            }
+            limiter.last_played.insert(ev.sound, now);
+
+            // A sound's own volume replaces the channel's, so they're multiplied here
+            let (channel, channel_volume) = if ev.sound == Sound::Click {
+                (&*ui, channel_volume(&settings, settings.ui_volume, settings.ui_muted))
+            } else {
+                (&*sfx, channel_volume(&settings, settings.sfx_volume, settings.sfx_muted))
+            };
+
+            match (ev.position, listener) {
+                (Some(position), Some(listener)) => {
+                    let (attenuation, panning) = spatial_mix(listener, position);
+                    let instance = channel.play(bank.get(ev.sound))
+                        .with_volume(channel_volume * (ev.volume * attenuation) as f64)
+                        .with_panning(panning)
+                        .with_playback_rate(ev.playback_rate)
+                        .handle();
+                    commands.spawn(TransformBundle::from_transform(Transform::from_translation(position)))
+                        .insert(AudioEmitter { instances: vec![EmitterInstance::new(ev.sound, instance, ev.volume)], despawn_when_done: true })
+                        .insert(Name::new(format!("{:?} sound", ev.sound)));
+                },
+                _ => {
+                    channel.play(bank.get(ev.sound))
+                        .with_volume(channel_volume * ev.volume as f64)
+                        .with_playback_rate(ev.playback_rate);
+                },
+            }
+        }
+    }
+
+    /**
+     * Starts looping the sound of every new ambient emitter
+     */
+    pub(crate) fn start_ambient_sounds(
+        mut commands: Commands,
+        bank: Res<SoundBank>,
+        sfx: Res<AudioChannel<Sfx>>,
+        ambient: Query<(Entity, &AmbientSound), Added<AmbientSound>>,
+    ) {
+        for (entity, sound) in &ambient {
+            commands.entity(entity).insert(AudioEmitter::looping(&[sound.0], AMBIENT_VOLUME, &bank, &sfx));
+        }
+    }
+
+    pub(crate) fn update_spatial_audio(
+        mut commands: Commands,
+        settings: Res<Settings>,
+        listeners: Query<&GlobalTransform, With<AudioListener>>,
+        mut emitters: Query<(Entity, &mut AudioEmitter, &GlobalTransform)>,
+        mut audio_instances: ResMut<Assets<AudioInstance>>,
+    ) {
+        let Ok(listener) = listeners.get_single() else { return };
+        let channel_volume = channel_volume(&settings, settings.sfx_volume, settings.sfx_muted);
+
+        for (entity, mut emitter, transform) in &mut emitters {
+            let (attenuation, panning) = spatial_mix(listener, transform.translation());
+
+            emitter.instances.retain_mut(|emitter_instance| {
+                emitter_instance.age += 1;
+                match audio_instances.get_mut(&emitter_instance.handle) {
+                    Some(instance) if instance.state() == PlaybackState::Stopped => false,
+                    Some(instance) => {
+                        emitter_instance.started = true;
+                        instance.set_volume(channel_volume * (emitter_instance.volume * attenuation) as f64, AudioTween::default());
+                        instance.set_panning(panning, AudioTween::default());
+                        true
+                    },
+                    None => !emitter_instance.started && emitter_instance.age < MAX_START_FRAMES,
+                }
+            });
+
+            if emitter.despawn_when_done && emitter.instances.is_empty() {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+
+    /// Ambient loops and sounds still ringing out stop while the game is paused
+    pub(crate) fn pause_sfx(sfx: Res<AudioChannel<Sfx>>) {
+        sfx.pause();
+    }
+
+    pub(crate) fn resume_sfx(sfx: Res<AudioChannel<Sfx>>) {
+        sfx.resume();
+    }
+
+    pub(crate) fn channel_volume(settings: &Settings, volume: f32, muted: bool) -> f64 {
+        if muted { 0.0 } else { (settings.master_volume * volume) as f64 }
+    }
+
+    pub(crate) fn apply_audio_settings(
+        settings: Res<Settings>,
+        sfx: Res<AudioChannel<Sfx>>,
+        ui: Res<AudioChannel<Ui>>,
+    ) {
+        if !settings.is_changed() {
+            return
+        }
+
+        sfx.set_volume(channel_volume(&settings, settings.sfx_volume, settings.sfx_muted));
+        ui.set_volume(channel_volume(&settings, settings.ui_volume, settings.ui_muted));
+    }
+}
@@ -0,0 +1,146 @@
+pub(crate) mod checkpoints {
+    //! Checkpoints, kill volumes and respawning.
+    //!
+    //! Touching a [`Checkpoint`] moves the [`RespawnPoint`] there. Falling below the level's
+    //! [`KillPlane`] or touching a [`KillVolume`] sends the player back to it with no velocity,
+    //! behind a short fade from black.
+
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+
+    use crate::collisions::collisions::{CollisionRole, PlayerTouched};
+    use crate::setup_world::setup_objects::Controlling;
+
+    const FADE_SECONDS:f32 = 0.4;
+
+    /// Sensor that moves the respawn point to `spawn` when the player touches it
+    #[derive(Component)]
+    pub(crate) struct Checkpoint {
+        /// Position in the level's checkpoint list
+        pub(crate) index: usize,
+        pub(crate) spawn: Vec3,
+    }
+
+    /// Sensor that kills the player when touched. Anything with `CollisionRole::Hazard` does that
+    /// too, this just marks the invisible ones that come from the level file.
+    #[derive(Component)]
+    pub(crate) struct KillVolume;
+
+    /// The player is killed when they fall below this height
+    #[derive(Resource)]
+    pub(crate) struct KillPlane(pub(crate) f32);
+
+    /// Where the player comes back after dying
+    #[derive(Resource)]
+    pub(crate) struct RespawnPoint(pub(crate) Vec3);
+
+    /// Sent when the player touches a checkpoint that isn't the current respawn point
+    #[derive(Event)]
+    pub(crate) struct CheckpointReached {
+        pub(crate) index: usize,
+    }
+
+    /// Send this to kill the player and bring them back at the respawn point
+    #[derive(Event, Default)]
+    pub(crate) struct KillPlayer;
+
+    /// Sent after the player has been moved back to the respawn point
+    #[derive(Event)]
+    pub(crate) struct PlayerRespawned {
+        pub(crate) position: Vec3,
+    }
+
+    // Full screen black overlay that fades out after a respawn
+    #[derive(Component)]
+    pub(crate) struct RespawnFade {
+        timer: Timer,
+    }
+
+    pub(crate) fn setup_respawn_fade(mut commands: Commands) {
+        let mut timer = Timer::from_seconds(FADE_SECONDS, TimerMode::Once);
+        // Start finished, so nothing is shown until the first respawn
+        timer.tick(timer.duration());
+
+        commands.spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..default()
+            },
+            background_color: Color::NONE.into(),
+            z_index: ZIndex::Global(100),
+            ..default()
+        })  .insert(RespawnFade { timer })
+            .insert(Name::new("Respawn fade"));
+    }
+
+    pub(crate) fn checkpoint_collisions(
+        mut touched: EventReader<PlayerTouched>,
+        checkpoints: Query<&Checkpoint>,
+        mut respawn_point: ResMut<RespawnPoint>,
+        mut checkpoint_reached: EventWriter<CheckpointReached>,
+        mut kill_player: EventWriter<KillPlayer>,
+    ) {
+        for touch in touched.iter() {
+            let other = touch.other;
+
+            if let Ok(checkpoint) = checkpoints.get(other) {
+                if respawn_point.0 != checkpoint.spawn {
+                    info!("reached checkpoint {}", checkpoint.index);
+                    respawn_point.0 = checkpoint.spawn;
+                    checkpoint_reached.send(CheckpointReached { index: checkpoint.index });
+                }
+            }
+
+            if touch.kind == CollisionRole::Hazard {
+                kill_player.send_default();
+            }
+        }
+    }
+
+    pub(crate) fn kill_plane(
+        kill_plane: Res<KillPlane>,
+        players: Query<&Transform, With<Controlling>>,
+        mut kill_player: EventWriter<KillPlayer>,
+    ) {
+        for transform in &players {
+            if transform.translation.y <= kill_plane.0 {
+                kill_player.send_default();
+            }
+        }
+    }
+
+    pub(crate) fn respawn_player(
+        mut kill_player: EventReader<KillPlayer>,
+        respawn_point: Res<RespawnPoint>,
+        mut players: Query<(&mut Transform, &mut Velocity), With<Controlling>>,
+        mut fades: Query<&mut RespawnFade>,
+        mut player_respawned: EventWriter<PlayerRespawned>,
+    ) {
+        if kill_player.is_empty() {
+            return
+        }
+        // Several things can kill the player in the same frame, but they only respawn once
+        kill_player.clear();
+
+        for (mut transform, mut velocity) in &mut players {
+            transform.translation = respawn_point.0;
+            transform.rotation = Quat::IDENTITY;
+            *velocity = Velocity::zero();
+        }
+
+        for mut fade in &mut fades {
+            fade.timer.reset();
+        }
+
+        player_respawned.send(PlayerRespawned { position: respawn_point.0 });
+    }
+
+    pub(crate) fn fade_in(time: Res<Time>, mut fades: Query<(&mut RespawnFade, &mut BackgroundColor)>) {
+        for (mut fade, mut background) in &mut fades {
+            fade.timer.tick(time.delta());
+            background.0 = Color::rgba(0.0, 0.0, 0.0, fade.timer.percent_left());
+        }
+    }
+}
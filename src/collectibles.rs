@@ -0,0 +1,228 @@
+pub(crate) mod collectibles {
+    //! Coins and orbs the player can pick up.
+    //!
+    //! Collectibles come from the level file, or from glTF nodes whose names start with `Coin`
+    //! or `Orb`. Every collectible has an id, and the ids that have been picked up are saved in
+    //! `saves/<level name>.ron` so they stay collected between runs.
+
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::PathBuf;
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+    use serde::{Serialize, Deserialize};
+
+    use crate::audio::audio::{PlaySound, Sound};
+    use crate::collisions::collisions::{CollisionRole, PlayerTouched};
+    use crate::hud::hud::HudElement;
+    use crate::level::level::CurrentLevel;
+
+    const SAVES_DIR:&str = "saves";
+    const SPIN_SPEED:f32 = 2.0;
+
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum CollectibleKind {
+        Coin,
+        Orb,
+    }
+
+    /// Whether a glTF node is a collectible, from its name
+    pub(crate) fn is_collectible_node(name: &str) -> bool {
+        CollectibleKind::from_node_name(name).is_some()
+    }
+
+    impl CollectibleKind {
+        fn from_node_name(name: &str) -> Option<CollectibleKind> {
+            if name.starts_with("Coin") {
+                Some(CollectibleKind::Coin)
+            } else if name.starts_with("Orb") {
+                Some(CollectibleKind::Orb)
+            } else {
+                None
+            }
+        }
+
+        fn radius(&self) -> f32 {
+            match self {
+                CollectibleKind::Coin => 0.5,
+                CollectibleKind::Orb => 0.3,
+            }
+        }
+
+        pub(crate) fn color(&self) -> Color {
+            match self {
+                CollectibleKind::Coin => Color::GOLD,
+                CollectibleKind::Orb => Color::CYAN,
+            }
+        }
+    }
+
+    #[derive(Component)]
+    pub(crate) struct Collectible {
+        pub(crate) id: String,
+        pub(crate) kind: CollectibleKind,
+    }
+
+    /// Ids of everything that has been collected in the current level
+    #[derive(Resource, Default, Serialize, Deserialize)]
+    pub(crate) struct CollectedItems {
+        pub(crate) ids: HashSet<String>,
+    }
+
+    /// Sent when the player picks something up
+    #[derive(Event)]
+    pub(crate) struct CollectiblePickedUp {
+        pub(crate) position: Vec3,
+        pub(crate) kind: CollectibleKind,
+    }
+
+    #[derive(Component)]
+    pub(crate) struct CollectibleCounter;
+
+    fn save_path(level_name: &str) -> PathBuf {
+        PathBuf::from(SAVES_DIR).join(format!("{}.ron", level_name))
+    }
+
+    pub(crate) fn load_collected(level_name: &str) -> CollectedItems {
+        match fs::read_to_string(save_path(level_name)) {
+            Ok(text) => ron::from_str(&text).unwrap_or_default(),
+            Err(_) => CollectedItems::default(),
+        }
+    }
+
+    fn save_collected(level_name: &str, collected: &CollectedItems) {
+        let path = save_path(level_name);
+        let serialized = ron::to_string(collected).unwrap();
+
+        let written = fs::create_dir_all(SAVES_DIR).and_then(|_| fs::write(&path, serialized));
+        if let Err(why) = written {
+            println!("WARNING! FAILED TO SAVE COLLECTED ITEMS TO {}: {}", path.display(), why);
+        }
+    }
+
+    /**
+     * Spawns a collectible, unless it has already been collected
+     */
+    pub(crate) fn spawn_collectible(
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        collected: &CollectedItems,
+        id: String,
+        kind: CollectibleKind,
+        translation: Vec3,
+    ) {
+        if collected.ids.contains(&id) {
+            return
+        }
+
+        let (mesh, material) = match kind {
+            CollectibleKind::Coin => (
+                Mesh::from(shape::Cylinder { radius: kind.radius(), height: 0.1, resolution: 16, segments: 1 }),
+                StandardMaterial { base_color: kind.color(), emissive: Color::rgb(0.4, 0.3, 0.0), metallic: 1.0, ..default() },
+            ),
+            CollectibleKind::Orb => (
+                Mesh::from(shape::UVSphere { radius: kind.radius(), sectors: 10, stacks: 10 }),
+                StandardMaterial { base_color: kind.color(), emissive: kind.color(), ..default() },
+            ),
+        };
+
+        commands.spawn(PbrBundle {
+            mesh: meshes.add(mesh),
+            material: materials.add(material),
+            // Coins stand on their edge
+            transform: Transform::from_translation(translation)
+                .with_rotation(if kind == CollectibleKind::Coin { Quat::from_rotation_x(std::f32::consts::FRAC_PI_2) } else { Quat::IDENTITY }),
+            ..default()
+        })  .insert(collectible_physics(kind))
+            .insert(Collectible { id, kind })
+            .insert(Name::new(format!("{:?}", kind)));
+    }
+
+    fn collectible_physics(kind: CollectibleKind) -> (Collider, Sensor, ActiveEvents, CollisionRole) {
+        (Collider::ball(kind.radius()), Sensor, ActiveEvents::COLLISION_EVENTS, CollisionRole::Pickup)
+    }
+
+    /**
+     * Turns glTF nodes named Coin... or Orb... into collectibles. The node name is used as the id.
+     *
+     * Ones that were already collected are hidden rather than despawned, since the scene's
+     * colliders may still be getting built this frame.
+     */
+    pub(crate) fn tag_gltf_collectibles(
+        mut commands: Commands,
+        nodes: Query<(Entity, &Name), (Added<Name>, With<Parent>, Without<Collectible>)>,
+        collected: Res<CollectedItems>,
+    ) {
+        for (entity, name) in &nodes {
+            let Some(kind) = CollectibleKind::from_node_name(name.as_str()) else { continue };
+
+            if collected.ids.contains(name.as_str()) {
+                commands.entity(entity).insert(Visibility::Hidden);
+                continue
+            }
+
+            commands.entity(entity)
+                .insert(collectible_physics(kind))
+                .insert(Collectible { id: name.to_string(), kind });
+        }
+    }
+
+    pub(crate) fn pick_up_collectibles(
+        mut commands: Commands,
+        mut touched: EventReader<PlayerTouched>,
+        collectibles: Query<(&Collectible, &GlobalTransform)>,
+        mut collected: ResMut<CollectedItems>,
+        level: Res<CurrentLevel>,
+        mut play_sound: EventWriter<PlaySound>,
+        mut picked_up: EventWriter<CollectiblePickedUp>,
+    ) {
+        for touch in touched.iter().filter(|touch| touch.kind == CollisionRole::Pickup) {
+            let other = touch.other;
+            let Ok((collectible, transform)) = collectibles.get(other) else { continue };
+
+            // Both colliders of a pair can show up in the same frame
+            if !collected.ids.insert(collectible.id.clone()) {
+                continue
+            }
+
+            play_sound.send(PlaySound::at(Sound::Pickup, transform.translation()));
+            commands.entity(other).despawn_recursive();
+            save_collected(&level.0.name, &collected);
+            picked_up.send(CollectiblePickedUp { position: transform.translation(), kind: collectible.kind });
+        }
+    }
+
+    pub(crate) fn spin_collectibles(time: Res<Time>, mut collectibles: Query<&mut Transform, With<Collectible>>) {
+        for mut transform in &mut collectibles {
+            transform.rotate_y(SPIN_SPEED * time.delta_seconds());
+        }
+    }
+
+    pub(crate) fn setup_counter(mut commands: Commands) {
+        commands.spawn(TextBundle::from_section("", TextStyle {
+            font_size: 28.0,
+            color: Color::GOLD,
+            ..default()
+        }).with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }))
+            .insert(CollectibleCounter)
+            .insert(HudElement::Collectibles)
+            .insert(Name::new("Collectible counter"));
+    }
+
+    pub(crate) fn update_counter(
+        collected: Res<CollectedItems>,
+        remaining: Query<(), With<Collectible>>,
+        mut counters: Query<&mut Text, With<CollectibleCounter>>,
+    ) {
+        let total = collected.ids.len() + remaining.iter().count();
+        for mut text in &mut counters {
+            text.sections[0].value = if total == 0 { String::new() } else { format!("Collected {}/{}", collected.ids.len(), total) };
+        }
+    }
+}
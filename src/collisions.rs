@@ -0,0 +1,81 @@
+pub(crate) mod collisions {
+    //! Routes Rapier's collision events by what was hit.
+    //!
+    //! Rapier reports every collision in the world as a pair of entities. [`dispatch_collisions`]
+    //! looks up the [`CollisionRole`] of both sides and turns the collisions that involve the
+    //! player into [`PlayerTouched`] and [`PlayerStoppedTouching`] events, so gameplay systems
+    //! only see the collisions they care about.
+
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+    use bevy_rapier3d::rapier::geometry::CollisionEventFlags;
+
+    /// What an entity is, as far as collisions are concerned
+    #[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+    pub(crate) enum CollisionRole {
+        Player,
+        /// Kills the player
+        Hazard,
+        /// Gets collected on touch
+        Pickup,
+        /// Solid ground and walls. Colliders without a role are treated as platforms.
+        Platform,
+        /// Sensors that make something happen, like checkpoints and goals.
+        /// Sensors without a role are treated as triggers.
+        Trigger,
+    }
+
+    /// Sent when the player starts touching something
+    #[derive(Event, Clone, Copy, Debug)]
+    pub(crate) struct PlayerTouched {
+        pub(crate) player: Entity,
+        pub(crate) other: Entity,
+        pub(crate) kind: CollisionRole,
+    }
+
+    /// Sent when the player stops touching something
+    #[derive(Event, Clone, Copy, Debug)]
+    pub(crate) struct PlayerStoppedTouching {
+        pub(crate) player: Entity,
+        pub(crate) other: Entity,
+        pub(crate) kind: CollisionRole,
+    }
+
+    fn role_of(roles: &Query<&CollisionRole>, entity: Entity, flags: CollisionEventFlags) -> CollisionRole {
+        match roles.get(entity) {
+            Ok(role) => *role,
+            Err(_) if flags.contains(CollisionEventFlags::SENSOR) => CollisionRole::Trigger,
+            Err(_) => CollisionRole::Platform,
+        }
+    }
+
+    pub(crate) fn dispatch_collisions(
+        mut collision_events: EventReader<CollisionEvent>,
+        roles: Query<&CollisionRole>,
+        mut touched: EventWriter<PlayerTouched>,
+        mut stopped_touching: EventWriter<PlayerStoppedTouching>,
+    ) {
+        for event in collision_events.iter() {
+            let (a, b, flags, started) = match event {
+                CollisionEvent::Started(a, b, flags) => (*a, *b, *flags, true),
+                CollisionEvent::Stopped(a, b, flags) => (*a, *b, *flags, false),
+            };
+
+            let role_a = role_of(&roles, a, flags);
+            let role_b = role_of(&roles, b, flags);
+
+            let (player, other, kind) = match (role_a, role_b) {
+                (CollisionRole::Player, kind) => (a, b, kind),
+                (kind, CollisionRole::Player) => (b, a, kind),
+                // Nothing cares about collisions that don't involve the player yet
+                _ => continue,
+            };
+
+            if started {
+                touched.send(PlayerTouched { player, other, kind });
+            } else {
+                stopped_touching.send(PlayerStoppedTouching { player, other, kind });
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+pub(crate) mod display {
+    //! The game window: building it from the settings and command line, and toggling fullscreen.
+    //!
+    //! Window arguments override `settings.ron` for that run only. When the settings are saved,
+    //! anything an argument set goes back to its value from `settings.ron`, unless it was changed
+    //! in the settings menu.
+
+    use bevy::prelude::*;
+    use bevy::window::{MonitorSelection, WindowPosition, WindowResolution};
+
+    use crate::settings::settings::{DisplayMode, GraphicsQuality, PresentModeSetting, Settings};
+
+    pub(crate) const WINDOW_TITLE:&str = "Rust is the future of programming!";
+
+    fn parse_pair<T: std::str::FromStr>(value: &str, separator: char) -> Option<(T, T)> {
+        let (a, b) = value.split_once(separator)?;
+        Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+    }
+
+    /**
+     * Applies window arguments to the settings:
+     * `resolution=1920x1080`, `position=100,50`, `monitor=1`, `windowed`, `borderless`,
+     * `fullscreen`, `present_mode=vsync|novsync|fifo|mailbox|immediate` and
+     * `quality=auto|low|medium|high|ultra|software`
+     */
+    pub(crate) fn apply_window_args(settings: &mut Settings, args: &[String]) {
+        for arg in args {
+            match arg.as_str() {
+                "windowed" => settings.display_mode = DisplayMode::Windowed,
+                "borderless" => settings.display_mode = DisplayMode::Borderless,
+                "fullscreen" => settings.display_mode = DisplayMode::Fullscreen,
+                _ => {},
+            }
+
+            if let Some(value) = arg.strip_prefix("resolution=") {
+                match parse_pair(value, 'x') {
+                    Some(resolution) => settings.resolution = resolution,
+                    None => println!("WARNING! IGNORING RESOLUTION {}, EXPECTED WIDTHxHEIGHT", value),
+                }
+            } else if let Some(value) = arg.strip_prefix("position=") {
+                match parse_pair(value, ',') {
+                    Some(position) => settings.position = Some(position),
+                    None => println!("WARNING! IGNORING POSITION {}, EXPECTED X,Y", value),
+                }
+            } else if let Some(value) = arg.strip_prefix("monitor=") {
+                match value.parse() {
+                    Ok(monitor) => settings.monitor = Some(monitor),
+                    Err(_) => println!("WARNING! IGNORING MONITOR {}, EXPECTED A NUMBER", value),
+                }
+            } else if let Some(value) = arg.strip_prefix("present_mode=") {
+                let present_mode = match value.to_lowercase().as_str() {
+                    "vsync" => Some(PresentModeSetting::Vsync),
+                    "novsync" => Some(PresentModeSetting::NoVsync),
+                    "fifo" => Some(PresentModeSetting::Fifo),
+                    "mailbox" => Some(PresentModeSetting::Mailbox),
+                    "immediate" => Some(PresentModeSetting::Immediate),
+                    _ => None,
+                };
+                match present_mode {
+                    Some(present_mode) => settings.present_mode = present_mode,
+                    None => println!("WARNING! UNKNOWN PRESENT MODE {}", value),
+                }
+            } else if let Some(value) = arg.strip_prefix("quality=") {
+                match GraphicsQuality::from_name(value) {
+                    Some(quality) => settings.graphics_quality = quality,
+                    None => println!("WARNING! UNKNOWN GRAPHICS QUALITY {}", value),
+                }
+            }
+        }
+    }
+
+    /**
+     * The primary window, as the settings describe it
+     */
+    pub(crate) fn primary_window(settings: &Settings) -> Window {
+        let monitor = match settings.monitor {
+            Some(index) => MonitorSelection::Index(index),
+            None => MonitorSelection::Primary,
+        };
+        // Fullscreen goes on whichever monitor the window starts on
+        let position = match settings.position {
+            Some((x, y)) => WindowPosition::At(IVec2::new(x, y)),
+            None => WindowPosition::Centered(monitor),
+        };
+
+        Window {
+            title: WINDOW_TITLE.to_string(),
+            present_mode: settings.present_mode.present_mode(),
+            resolution: WindowResolution::new(settings.resolution.0, settings.resolution.1),
+            mode: settings.display_mode.window_mode(),
+            position,
+            ..default()
+        }
+    }
+
+    /// Mode to go back to when leaving fullscreen with the toggle key
+    #[derive(Resource)]
+    pub(crate) struct LastFullscreenMode(pub(crate) DisplayMode);
+
+    /**
+     * F11 or Alt+Enter switches between windowed and fullscreen
+     */
+    pub(crate) fn toggle_fullscreen(
+        keyboard_input: Res<Input<KeyCode>>,
+        mut settings: ResMut<Settings>,
+        mut last_fullscreen: ResMut<LastFullscreenMode>,
+    ) {
+        let alt = keyboard_input.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]);
+        if !keyboard_input.just_pressed(KeyCode::F11) && !(alt && keyboard_input.just_pressed(KeyCode::Return)) {
+            return
+        }
+
+        settings.display_mode = match settings.display_mode {
+            DisplayMode::Windowed => last_fullscreen.0,
+            fullscreen => {
+                last_fullscreen.0 = fullscreen;
+                DisplayMode::Windowed
+            },
+        };
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn args(args: &[&str]) -> Vec<String> {
+            args.iter().map(|arg| arg.to_string()).collect()
+        }
+
+        #[test]
+        fn window_args_override_settings() {
+            let mut settings = Settings::default();
+            apply_window_args(&mut settings, &args(&["rust-game", "resolution=1920x1080", "position=100,-50", "monitor=1", "borderless", "present_mode=Immediate", "quality=low"]));
+
+            assert_eq!(settings.resolution, (1920.0, 1080.0));
+            assert_eq!(settings.position, Some((100, -50)));
+            assert_eq!(settings.monitor, Some(1));
+            assert_eq!(settings.display_mode, DisplayMode::Borderless);
+            assert_eq!(settings.present_mode, PresentModeSetting::Immediate);
+            assert_eq!(settings.graphics_quality, GraphicsQuality::Low);
+        }
+
+        #[test]
+        fn bad_window_args_are_ignored() {
+            let mut settings = Settings::default();
+            let defaults = Settings::default();
+            apply_window_args(&mut settings, &args(&["resolution=big", "position=1", "monitor=second", "present_mode=fast", "quality=best"]));
+
+            assert_eq!(settings.resolution, defaults.resolution);
+            assert_eq!(settings.position, defaults.position);
+            assert_eq!(settings.monitor, defaults.monitor);
+            assert_eq!(settings.present_mode, defaults.present_mode);
+            assert_eq!(settings.graphics_quality, defaults.graphics_quality);
+        }
+
+        #[test]
+        fn last_window_mode_wins() {
+            let mut settings = Settings::default();
+            apply_window_args(&mut settings, &args(&["fullscreen", "windowed"]));
+            assert_eq!(settings.display_mode, DisplayMode::Windowed);
+        }
+    }
+}
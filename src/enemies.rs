@@ -0,0 +1,168 @@
+pub(crate) mod enemies {
+    //! Enemies that patrol, and chase the player when they see them.
+    //!
+    //! An enemy walks between its patrol points until the player is close enough and in line of
+    //! sight. Then it chases them along the navmesh, and goes back to patrolling once it loses
+    //! them. Touching an enemy kills the player.
+
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+    use serde::{Serialize, Deserialize};
+
+    use crate::collisions::collisions::CollisionRole;
+    use crate::navmesh::navmesh::NavMesh;
+    use crate::surfaces::surfaces::impact_events;
+    use crate::setup_world::setup_objects::Controlling;
+
+    // Seconds between recalculating a chase path
+    const REPATH_INTERVAL:f32 = 0.5;
+    // How close an enemy has to get to a point on its path before moving on to the next one
+    const ARRIVE_DISTANCE:f32 = 0.75;
+    // Enemies give up once the player is this much further away than their detection range
+    const LOSE_INTEREST_FACTOR:f32 = 1.5;
+    const HALF_HEIGHT:f32 = 0.5;
+    const RADIUS:f32 = 0.4;
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct EnemyDef {
+        pub(crate) translation: Vec3,
+        /// Points to walk between, in order. An enemy without any stands still until it sees the player.
+        #[serde(default)]
+        pub(crate) patrol: Vec<Vec3>,
+        #[serde(default = "default_speed")]
+        pub(crate) speed: f32,
+        #[serde(default = "default_detection_range")]
+        pub(crate) detection_range: f32,
+    }
+
+    fn default_speed() -> f32 { 3.0 }
+    fn default_detection_range() -> f32 { 15.0 }
+
+    #[derive(PartialEq, Clone, Copy, Debug)]
+    pub(crate) enum EnemyState {
+        Patrolling,
+        Chasing,
+    }
+
+    #[derive(Component)]
+    pub(crate) struct Enemy {
+        pub(crate) state: EnemyState,
+        patrol: Vec<Vec3>,
+        next_patrol_point: usize,
+        speed: f32,
+        detection_range: f32,
+        /// Points left to walk through to reach the current target
+        path: Vec<Vec3>,
+        repath: Timer,
+    }
+
+    pub(crate) fn spawn_enemy(
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        enemy: &EnemyDef,
+    ) {
+        commands.spawn(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Capsule { radius: RADIUS, depth: 2.0 * HALF_HEIGHT, ..default() })),
+            material: materials.add(StandardMaterial { base_color: Color::MAROON, emissive: Color::rgb(0.3, 0.0, 0.0), ..default() }),
+            transform: Transform::from_translation(enemy.translation),
+            ..default()
+        })  .insert(RigidBody::Dynamic)
+            .insert(Collider::capsule_y(HALF_HEIGHT, RADIUS))
+            .insert(LockedAxes::ROTATION_LOCKED)
+            .insert(Velocity::zero())
+            .insert(impact_events())
+            .insert(CollisionRole::Hazard)
+            .insert(Enemy {
+                state: EnemyState::Patrolling,
+                patrol: enemy.patrol.clone(),
+                next_patrol_point: 0,
+                speed: enemy.speed,
+                detection_range: enemy.detection_range,
+                path: vec![],
+                repath: Timer::from_seconds(REPATH_INTERVAL, TimerMode::Repeating),
+            })
+            .insert(Name::new("Enemy"));
+    }
+
+    /**
+     * Decides whether each enemy is patrolling or chasing, and plans its path
+     */
+    pub(crate) fn enemy_senses(
+        time: Res<Time>,
+        rapier_context: Res<RapierContext>,
+        navmesh: Option<Res<NavMesh>>,
+        mut enemies: Query<(Entity, &mut Enemy, &Transform)>,
+        players: Query<(Entity, &Transform), With<Controlling>>,
+    ) {
+        let Ok((player, player_transform)) = players.get_single() else { return };
+        let player_position = player_transform.translation;
+
+        for (entity, mut enemy, transform) in &mut enemies {
+            let position = transform.translation;
+            let to_player = player_position - position;
+            let distance = to_player.length();
+
+            // No direction to look in if the player is right at the enemy's center
+            let sees_player = distance <= enemy.detection_range && to_player.try_normalize().is_some_and(|direction| rapier_context.cast_ray(
+                position,
+                direction,
+                distance,
+                true,
+                QueryFilter::default().exclude_sensors().exclude_collider(entity),
+            ).map_or(false, |(hit, _)| hit == player));
+
+            let previous_state = enemy.state;
+            enemy.state = match enemy.state {
+                EnemyState::Patrolling if sees_player => EnemyState::Chasing,
+                EnemyState::Chasing if distance > enemy.detection_range * LOSE_INTEREST_FACTOR => EnemyState::Patrolling,
+                state => state,
+            };
+
+            enemy.repath.tick(time.delta());
+            let state_changed = enemy.state != previous_state;
+
+            let target = match enemy.state {
+                EnemyState::Chasing => Some(player_position),
+                EnemyState::Patrolling => enemy.patrol.get(enemy.next_patrol_point).copied(),
+            };
+            let Some(target) = target else {
+                enemy.path.clear();
+                continue
+            };
+
+            if state_changed || enemy.path.is_empty() || (enemy.state == EnemyState::Chasing && enemy.repath.just_finished()) {
+                // Without a navmesh (or a way through it), head straight for the target
+                enemy.path = navmesh.as_ref()
+                    .and_then(|navmesh| navmesh.find_path(position, target))
+                    .unwrap_or_else(|| vec![target]);
+            }
+        }
+    }
+
+    pub(crate) fn move_enemies(mut enemies: Query<(&mut Enemy, &Transform, &mut Velocity)>) {
+        for (mut enemy, transform, mut velocity) in &mut enemies {
+            let position = transform.translation;
+
+            // Skip past points that have been reached
+            while let Some(next) = enemy.path.first() {
+                let offset = *next - position;
+                if Vec2::new(offset.x, offset.z).length() > ARRIVE_DISTANCE {
+                    break
+                }
+                enemy.path.remove(0);
+                if enemy.path.is_empty() && enemy.state == EnemyState::Patrolling && !enemy.patrol.is_empty() {
+                    enemy.next_patrol_point = (enemy.next_patrol_point + 1) % enemy.patrol.len();
+                }
+            }
+
+            // Walk horizontally, and let gravity handle the rest
+            let direction = match enemy.path.first() {
+                Some(next) => Vec3::new(next.x - position.x, 0.0, next.z - position.z).normalize_or_zero(),
+                None => Vec3::ZERO,
+            };
+            velocity.linvel.x = direction.x * enemy.speed;
+            velocity.linvel.z = direction.z * enemy.speed;
+        }
+    }
+}
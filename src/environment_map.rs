@@ -0,0 +1,442 @@
+pub(crate) mod environment_map {
+    //! Image-based lighting made from the skybox.
+    //!
+    //! Once the skybox is a cubemap, a background task filters it into the two maps an
+    //! `EnvironmentMapLight` needs: a small diffuse irradiance map (from spherical harmonics) and a
+    //! specular map whose mip levels are blurrier the rougher the surface (GGX importance
+    //! sampling). Both are stored as `Rgb9e5Ufloat`, which keeps HDR skyboxes bright.
+    //!
+    //! Filtering takes a few seconds, so the results are cached in `environment_cache.bin`, keyed
+    //! on a hash of the skybox's pixels, like colliders are cached in `cache.bin`.
+
+    use std::collections::hash_map::DefaultHasher;
+    use std::f32::consts::PI;
+    use std::fs;
+    use std::hash::{Hash, Hasher};
+    use bevy::{
+        pbr::EnvironmentMapLight,
+        prelude::*,
+        render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension},
+        tasks::{AsyncComputeTaskPool, Task},
+    };
+    use futures_lite::future;
+    use serde::{Serialize, Deserialize};
+
+    use crate::skybox::skybox::{face_direction, pack_rgb9e5, Cubemap};
+
+    const CACHE_PATH:&str = "environment_cache.bin";
+    // Only the newest environment maps are kept, so editing a skybox doesn't grow the cache forever
+    const MAX_CACHED_MAPS:usize = 4;
+    const DIFFUSE_SIZE:u32 = 32;
+    const SPECULAR_SIZE:u32 = 128;
+    // One mip level per halving, down to 1x1
+    const SPECULAR_MIPS:u32 = SPECULAR_SIZE.trailing_zeros() + 1;
+    const SPECULAR_SAMPLES:u32 = 64;
+    // Spherical harmonics are projected from this size, any bigger is just slower
+    const SH_SOURCE_SIZE:u32 = 32;
+
+    #[derive(Serialize, Deserialize, Clone)]
+    struct CachedEnvironmentMap {
+        source_hash: u64,
+        diffuse: Vec<u8>,
+        specular: Vec<u8>,
+    }
+
+    /// The cubemap being filtered, and the one that was filtered last
+    #[derive(Resource, Default)]
+    pub(crate) struct EnvironmentMapGeneration {
+        /// `Cubemap::version` of the last cubemap that was filtered
+        version: u32,
+        task: Option<Task<Option<CachedEnvironmentMap>>>,
+    }
+
+    /// A cubemap's mip 0 as linear colors, face after face
+    #[derive(Clone)]
+    struct CubeFaces {
+        size: u32,
+        texels: Vec<Vec3>,
+    }
+
+    fn srgb_to_linear(value: u8) -> f32 {
+        let value = value as f32 / 255.0;
+        if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+    }
+
+    fn f16_to_f32(bits: u16) -> f32 {
+        let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = ((bits >> 10) & 0x1f) as i32;
+        let mantissa = (bits & 0x3ff) as f32;
+        match exponent {
+            0 => sign * mantissa * 2f32.powi(-24),
+            31 => sign * f32::INFINITY,
+            _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+        }
+    }
+
+    fn unpack_rgb9e5(packed: u32) -> Vec3 {
+        let scale = 2f32.powi((packed >> 27) as i32 - 24);
+        Vec3::new((packed & 0x1ff) as f32, ((packed >> 9) & 0x1ff) as f32, ((packed >> 18) & 0x1ff) as f32) * scale
+    }
+
+    impl CubeFaces {
+        /// Reads the first mip of every face. Only uncompressed formats are supported.
+        fn from_image(image: &Image) -> Option<CubeFaces> {
+            let size = image.texture_descriptor.size;
+            if size.depth_or_array_layers != 6 || size.width != size.height {
+                return None
+            }
+            let bytes_per_texel = match image.texture_descriptor.format {
+                TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm | TextureFormat::Rgb9e5Ufloat => 4,
+                TextureFormat::Rgba16Float => 8,
+                TextureFormat::Rgba32Float => 16,
+                _ => return None,
+            };
+            // Each face has all of its mip levels before the next face starts
+            let face_stride = image.data.len() / 6;
+
+            let mut texels = Vec::with_capacity(6 * (size.width * size.width) as usize);
+            for face in 0..6 {
+                let face_data = &image.data[face * face_stride..];
+                for texel in face_data.chunks_exact(bytes_per_texel).take((size.width * size.width) as usize) {
+                    let word = |i: usize| u32::from_le_bytes([texel[4 * i], texel[4 * i + 1], texel[4 * i + 2], texel[4 * i + 3]]);
+                    let half = |i: usize| f16_to_f32(u16::from_le_bytes([texel[2 * i], texel[2 * i + 1]]));
+                    texels.push(match image.texture_descriptor.format {
+                        TextureFormat::Rgba8UnormSrgb => Vec3::new(srgb_to_linear(texel[0]), srgb_to_linear(texel[1]), srgb_to_linear(texel[2])),
+                        TextureFormat::Rgba8Unorm => Vec3::new(texel[0] as f32, texel[1] as f32, texel[2] as f32) / 255.0,
+                        TextureFormat::Rgb9e5Ufloat => unpack_rgb9e5(word(0)),
+                        TextureFormat::Rgba16Float => Vec3::new(half(0), half(1), half(2)),
+                        _ => Vec3::new(f32::from_bits(word(0)), f32::from_bits(word(1)), f32::from_bits(word(2))),
+                    });
+                }
+            }
+            Some(CubeFaces { size: size.width, texels })
+        }
+
+        fn texel(&self, face: usize, x: u32, y: u32) -> Vec3 {
+            self.texels[face * (self.size * self.size) as usize + (y * self.size + x) as usize]
+        }
+
+        /// Half the size, each texel the average of four
+        fn downsample(&self) -> CubeFaces {
+            let size = (self.size / 2).max(1);
+            let mut texels = Vec::with_capacity(6 * (size * size) as usize);
+            for face in 0..6 {
+                for y in 0..size {
+                    for x in 0..size {
+                        let (x0, y0) = ((2 * x).min(self.size - 1), (2 * y).min(self.size - 1));
+                        let (x1, y1) = ((2 * x + 1).min(self.size - 1), (2 * y + 1).min(self.size - 1));
+                        texels.push((self.texel(face, x0, y0) + self.texel(face, x1, y0) + self.texel(face, x0, y1) + self.texel(face, x1, y1)) / 4.0);
+                    }
+                }
+            }
+            CubeFaces { size, texels }
+        }
+
+        /// Bilinear sample in direction `dir`. Doesn't blend across face edges.
+        fn sample(&self, dir: Vec3) -> Vec3 {
+            let (face, u, v) = face_uv(dir);
+            let x = ((u + 1.0) / 2.0 * self.size as f32 - 0.5).clamp(0.0, (self.size - 1) as f32);
+            let y = ((v + 1.0) / 2.0 * self.size as f32 - 0.5).clamp(0.0, (self.size - 1) as f32);
+            let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+            let (x1, y1) = ((x0 + 1).min(self.size - 1), (y0 + 1).min(self.size - 1));
+            let (tx, ty) = (x.fract(), y.fract());
+
+            let upper = self.texel(face, x0, y0).lerp(self.texel(face, x1, y0), tx);
+            let lower = self.texel(face, x0, y1).lerp(self.texel(face, x1, y1), tx);
+            upper.lerp(lower, ty)
+        }
+    }
+
+    /// The face a direction points at, and where on it, with `u` and `v` from -1 to 1. The
+    /// inverse of `face_direction`.
+    fn face_uv(dir: Vec3) -> (usize, f32, f32) {
+        let abs = dir.abs();
+        if abs.x >= abs.y && abs.x >= abs.z {
+            if dir.x > 0.0 { (0, -dir.z / abs.x, -dir.y / abs.x) } else { (1, dir.z / abs.x, -dir.y / abs.x) }
+        } else if abs.y >= abs.z {
+            if dir.y > 0.0 { (2, dir.x / abs.y, dir.z / abs.y) } else { (3, dir.x / abs.y, -dir.z / abs.y) }
+        } else if dir.z > 0.0 {
+            (4, dir.x / abs.z, -dir.y / abs.z)
+        } else {
+            (5, -dir.x / abs.z, -dir.y / abs.z)
+        }
+    }
+
+    /// Direction through the middle of a texel, and the solid angle it covers
+    fn texel_direction(face: usize, x: u32, y: u32, size: u32) -> (Vec3, f32) {
+        let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+        let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+        let texel_area = (2.0 / size as f32).powi(2);
+        (face_direction(face, u, v).normalize(), texel_area / (1.0 + u * u + v * v).powf(1.5))
+    }
+
+    fn sh_basis(dir: Vec3) -> [f32; 9] {
+        [
+            0.282095,
+            0.488603 * dir.y,
+            0.488603 * dir.z,
+            0.488603 * dir.x,
+            1.092548 * dir.x * dir.y,
+            1.092548 * dir.y * dir.z,
+            0.315392 * (3.0 * dir.z * dir.z - 1.0),
+            1.092548 * dir.x * dir.z,
+            0.546274 * (dir.x * dir.x - dir.y * dir.y),
+        ]
+    }
+
+    /// Lambertian irradiance over pi, from spherical harmonics of the skybox
+    fn diffuse_map(source: &CubeFaces) -> Vec<u8> {
+        let mut coefficients = [Vec3::ZERO; 9];
+        for face in 0..6 {
+            for y in 0..source.size {
+                for x in 0..source.size {
+                    let (dir, solid_angle) = texel_direction(face, x, y, source.size);
+                    for (coefficient, basis) in coefficients.iter_mut().zip(sh_basis(dir)) {
+                        *coefficient += source.texel(face, x, y) * basis * solid_angle;
+                    }
+                }
+            }
+        }
+        // Convolving with a cosine lobe scales each band, then dividing by pi gives what
+        // EnvironmentMapLight expects
+        let bands = [1.0, 2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 0.25, 0.25, 0.25, 0.25, 0.25];
+
+        let mut data = Vec::with_capacity(4 * 6 * (DIFFUSE_SIZE * DIFFUSE_SIZE) as usize);
+        for face in 0..6 {
+            for y in 0..DIFFUSE_SIZE {
+                for x in 0..DIFFUSE_SIZE {
+                    let (dir, _) = texel_direction(face, x, y, DIFFUSE_SIZE);
+                    let irradiance = coefficients.iter().zip(sh_basis(dir)).zip(bands)
+                        .fold(Vec3::ZERO, |sum, ((coefficient, basis), band)| sum + *coefficient * basis * band);
+                    data.extend_from_slice(&pack_rgb9e5(irradiance.max(Vec3::ZERO)).to_le_bytes());
+                }
+            }
+        }
+        data
+    }
+
+    fn hammersley(i: u32, count: u32) -> Vec2 {
+        Vec2::new(i as f32 / count as f32, i.reverse_bits() as f32 * 2.328_306_4e-10)
+    }
+
+    /// Samples `chain` at a fractional mip level, blending the two nearest
+    fn sample_level(chain: &[CubeFaces], dir: Vec3, level: f32) -> Vec3 {
+        let level = level.clamp(0.0, (chain.len() - 1) as f32);
+        let lower = level.floor() as usize;
+        let upper = (lower + 1).min(chain.len() - 1);
+        chain[lower].sample(dir).lerp(chain[upper].sample(dir), level.fract())
+    }
+
+    /// GGX prefiltered radiance for `roughness`, looking straight along the normal
+    fn prefilter(chain: &[CubeFaces], normal: Vec3, roughness: f32) -> Vec3 {
+        let alpha = roughness * roughness;
+        let (tangent, bitangent) = normal.any_orthonormal_pair();
+        // Solid angle of one texel of the full size source
+        let texel_solid_angle = 4.0 * PI / (6.0 * (chain[0].size * chain[0].size) as f32);
+
+        let mut color = Vec3::ZERO;
+        let mut weight = 0.0;
+        for i in 0..SPECULAR_SAMPLES {
+            let xi = hammersley(i, SPECULAR_SAMPLES);
+            let phi = 2.0 * PI * xi.x;
+            let cos_theta = ((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y)).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let half = (tangent * phi.cos() * sin_theta + bitangent * phi.sin() * sin_theta + normal * cos_theta).normalize();
+            let light = 2.0 * normal.dot(half) * half - normal;
+            let n_dot_l = normal.dot(light);
+            if n_dot_l <= 0.0 {
+                continue
+            }
+
+            // Sampling a blurrier mip for rarer directions stops bright spots from sparkling
+            let distribution = alpha * alpha / (PI * (cos_theta * cos_theta * (alpha * alpha - 1.0) + 1.0).powi(2));
+            let pdf = distribution / 4.0;
+            let sample_solid_angle = 1.0 / (SPECULAR_SAMPLES as f32 * pdf + 0.0001);
+            let level = 0.5 * (sample_solid_angle / texel_solid_angle).log2() + 1.0;
+
+            color += sample_level(chain, light, level) * n_dot_l;
+            weight += n_dot_l;
+        }
+        color / weight.max(0.0001)
+    }
+
+    /// Every mip level of the specular map, face after face
+    fn specular_map(chain: &[CubeFaces]) -> Vec<u8> {
+        // The sharpest level is the source scaled to fit
+        let base_level = (chain[0].size as f32 / SPECULAR_SIZE as f32).log2().max(0.0);
+
+        let mut data = vec![];
+        for face in 0..6 {
+            for mip in 0..SPECULAR_MIPS {
+                let size = SPECULAR_SIZE >> mip;
+                let roughness = mip as f32 / (SPECULAR_MIPS - 1) as f32;
+                for y in 0..size {
+                    for x in 0..size {
+                        let (dir, _) = texel_direction(face, x, y, size);
+                        let color = if mip == 0 { sample_level(chain, dir, base_level) } else { prefilter(chain, dir, roughness) };
+                        data.extend_from_slice(&pack_rgb9e5(color).to_le_bytes());
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    fn hash_image(image: &Image) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        image.data.hash(&mut hasher);
+        image.texture_descriptor.size.width.hash(&mut hasher);
+        format!("{:?}", image.texture_descriptor.format).hash(&mut hasher);
+        // Changing the output sizes invalidates the cache
+        (DIFFUSE_SIZE, SPECULAR_SIZE, SPECULAR_SAMPLES).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn load_cache() -> Vec<CachedEnvironmentMap> {
+        fs::read(CACHE_PATH).ok()
+            .and_then(|bytes| bincode::deserialize(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    fn add_to_cache(item: CachedEnvironmentMap) {
+        let mut cache = load_cache();
+        push_newest(&mut cache, item);
+        match fs::write(CACHE_PATH, bincode::serialize(&cache).unwrap()) {
+            Ok(_) => println!("Cached new environment map into {}", CACHE_PATH),
+            Err(why) => println!("WARNING! FAILED TO WRITE ENVIRONMENT MAP CACHE TO {}: {}", CACHE_PATH, why),
+        }
+    }
+
+    /// Adds `item` as the newest entry, dropping the oldest ones past `MAX_CACHED_MAPS`
+    fn push_newest(cache: &mut Vec<CachedEnvironmentMap>, item: CachedEnvironmentMap) {
+        cache.retain(|cached| cached.source_hash != item.source_hash);
+        cache.push(item);
+        let excess = cache.len().saturating_sub(MAX_CACHED_MAPS);
+        cache.drain(..excess);
+    }
+
+    /**
+     * Gets the environment maps for a skybox from the cache, or filters them if they aren't there
+     */
+    fn filter_skybox(image: Image) -> Option<CachedEnvironmentMap> {
+        let source_hash = hash_image(&image);
+        if let Some(cached) = load_cache().into_iter().find(|cached| cached.source_hash == source_hash) {
+            info!("Found cached environment map");
+            return Some(cached)
+        }
+
+        let Some(source) = CubeFaces::from_image(&image) else {
+            println!("WARNING! CAN'T MAKE AN ENVIRONMENT MAP FROM A {:?} SKYBOX", image.texture_descriptor.format);
+            return None
+        };
+        info!("Filtering environment map from a {}px skybox", source.size);
+
+        let mut chain = vec![source];
+        while chain.last().unwrap().size > 1 {
+            let next = chain.last().unwrap().downsample();
+            chain.push(next);
+        }
+        let sh_source = chain.iter().find(|level| level.size <= SH_SOURCE_SIZE).unwrap_or(&chain[0]);
+
+        let filtered = CachedEnvironmentMap { source_hash, diffuse: diffuse_map(sh_source), specular: specular_map(&chain) };
+        add_to_cache(filtered.clone());
+        Some(filtered)
+    }
+
+    fn cube_image(size: u32, mip_levels: u32, data: Vec<u8>) -> Image {
+        let mut image = Image::new_fill(
+            Extent3d { width: size, height: size, depth_or_array_layers: 6 },
+            TextureDimension::D2,
+            &[0; 4],
+            TextureFormat::Rgb9e5Ufloat,
+        );
+        // Image::new only expects one mip level
+        image.data = data;
+        image.texture_descriptor.mip_level_count = mip_levels;
+        image.texture_view_descriptor = Some(TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::Cube),
+            ..default()
+        });
+        image
+    }
+
+    /**
+     * Filters the skybox whenever a new one is put on the camera (or reloaded), and gives the camera the result
+     */
+    pub(crate) fn generate_environment_map(
+        mut commands: Commands,
+        cubemap: Res<Cubemap>,
+        mut generation: ResMut<EnvironmentMapGeneration>,
+        mut images: ResMut<Assets<Image>>,
+        cameras: Query<Entity, With<Camera3d>>,
+    ) {
+        if cubemap.is_loaded && generation.version != cubemap.version {
+            if let Some(image) = cubemap.image_handle.as_ref().and_then(|handle| images.get(handle)) {
+                let image = image.clone();
+                generation.version = cubemap.version;
+                generation.task = Some(AsyncComputeTaskPool::get().spawn(async move { filter_skybox(image) }));
+            }
+        }
+
+        let Some(task) = &mut generation.task else { return };
+        let Some(filtered) = future::block_on(future::poll_once(task)) else { return };
+        generation.task = None;
+        let Some(filtered) = filtered else { return };
+
+        let environment_map = EnvironmentMapLight {
+            diffuse_map: images.add(cube_image(DIFFUSE_SIZE, 1, filtered.diffuse)),
+            specular_map: images.add(cube_image(SPECULAR_SIZE, SPECULAR_MIPS, filtered.specular)),
+        };
+        for camera in &cameras {
+            commands.entity(camera).insert(environment_map.clone());
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn cached(source_hash: u64) -> CachedEnvironmentMap {
+            CachedEnvironmentMap { source_hash, diffuse: vec![], specular: vec![] }
+        }
+
+        fn hashes(cache: &[CachedEnvironmentMap]) -> Vec<u64> {
+            cache.iter().map(|cached| cached.source_hash).collect()
+        }
+
+        #[test]
+        fn cache_keeps_only_the_newest_maps() {
+            let mut cache = vec![];
+            for hash in 0..MAX_CACHED_MAPS as u64 + 2 {
+                push_newest(&mut cache, cached(hash));
+            }
+            assert_eq!(hashes(&cache), (2..MAX_CACHED_MAPS as u64 + 2).collect::<Vec<_>>());
+
+            // Filtering the same skybox again moves it to the end instead of adding a copy
+            push_newest(&mut cache, cached(2));
+            assert_eq!(cache.len(), MAX_CACHED_MAPS);
+            assert_eq!(hashes(&cache).last(), Some(&2));
+        }
+
+        #[test]
+        fn rgb9e5_round_trips() {
+            for color in [Vec3::ZERO, Vec3::ONE, Vec3::new(0.25, 4.0, 0.001), Vec3::new(1000.0, 3.5, 20.0)] {
+                let unpacked = unpack_rgb9e5(pack_rgb9e5(color));
+                // Every channel shares the brightest one's exponent, with 9 bits of mantissa
+                let tolerance = color.max_element() / 256.0;
+                assert!((unpacked - color).abs().max_element() <= tolerance, "{} came back as {}", color, unpacked);
+            }
+        }
+
+        #[test]
+        fn face_uv_inverts_face_direction() {
+            for face in 0..6 {
+                for (u, v) in [(0.0, 0.0), (0.5, -0.25), (-0.9, 0.9), (0.3, 0.7)] {
+                    let (face_back, u_back, v_back) = face_uv(face_direction(face, u, v));
+                    assert_eq!(face_back, face);
+                    assert!((u_back - u).abs() < 1e-5 && (v_back - v).abs() < 1e-5, "face {} ({}, {}) came back as ({}, {})", face, u, v, u_back, v_back);
+                }
+            }
+        }
+    }
+}
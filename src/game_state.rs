@@ -0,0 +1,87 @@
+pub(crate) mod game_state {
+    //! The game's top level states, and what changes when moving between them.
+    //!
+    //! Gameplay systems only run in [`GameState::Playing`]. Physics is paused and the cursor is
+    //! released in every other state.
+
+    use bevy::{prelude::*, window::CursorGrabMode};
+    use bevy_rapier3d::prelude::*;
+
+    #[derive(States, Default, Debug, Clone, Copy, Eq, PartialEq, Hash)]
+    pub(crate) enum GameState {
+        #[default]
+        Loading,
+        MainMenu,
+        Playing,
+        Paused,
+        Settings,
+        Results,
+    }
+
+    /// Whether the cursor should be grabbed while playing. It isn't in debug mode, so the
+    /// inspector can be used.
+    #[derive(Resource)]
+    pub(crate) struct GrabCursor(pub(crate) bool);
+
+    pub(crate) fn resume_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+        rapier_config.physics_pipeline_active = true;
+    }
+
+    pub(crate) fn pause_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+        rapier_config.physics_pipeline_active = false;
+    }
+
+    pub(crate) fn grab_cursor(mut windows: Query<&mut Window>, grab: Res<GrabCursor>) {
+        if !grab.0 {
+            return
+        }
+        for mut window in &mut windows {
+            window.cursor.visible = false;
+            window.cursor.grab_mode = CursorGrabMode::Locked;
+        }
+    }
+
+    pub(crate) fn release_cursor(mut windows: Query<&mut Window>) {
+        for mut window in &mut windows {
+            window.cursor.visible = true;
+            window.cursor.grab_mode = CursorGrabMode::None;
+        }
+    }
+
+    // Cursor crab function shamelessly stolen from bevy-cheatbook.github.io
+    // Grabs the cursor again when clicking back into the window
+    pub(crate) fn cursor_grab_system(
+        windows: Query<&mut Window>,
+        mouse: Res<Input<MouseButton>>,
+        grab: Res<GrabCursor>,
+    ) {
+        if mouse.just_pressed(MouseButton::Left) {
+            grab_cursor(windows, grab);
+        }
+    }
+
+    pub(crate) fn pause_on_escape(key: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+        if key.just_pressed(KeyCode::Escape) {
+            next_state.set(GameState::Paused);
+        }
+    }
+
+    pub(crate) fn resume_on_escape(key: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+        if key.just_pressed(KeyCode::Escape) {
+            next_state.set(GameState::Playing);
+        }
+    }
+
+    pub(crate) fn back_to_pause_on_escape(key: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+        if key.just_pressed(KeyCode::Escape) {
+            next_state.set(GameState::Paused);
+        }
+    }
+
+    /// Despawns every entity with the component `T`, for cleaning up a screen when leaving its state
+    pub(crate) fn despawn_screen<T: Component>(mut commands: Commands, screens: Query<Entity, With<T>>) {
+        for entity in &screens {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
@@ -0,0 +1,231 @@
+pub(crate) mod hazards {
+    //! Turrets, spinning blades and crushers.
+    //!
+    //! Everything here has `CollisionRole::Hazard`, so touching it kills the player and sends them
+    //! back to the last checkpoint. Blades and crushers are kinematic platforms with a hazard role;
+    //! turrets turn towards the player (see `PointingAtPlayer`) and shoot when they can see them.
+
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+    use serde::{Serialize, Deserialize};
+
+    use crate::collisions::collisions::CollisionRole;
+    use crate::platforms::platforms::{Easing, MovingPlatform, PlatformPath};
+    use crate::setup_world::setup_objects::{Controlling, PointingAtPlayer};
+    use crate::surfaces::surfaces::SurfaceMaterial;
+
+    const PROJECTILE_RADIUS:f32 = 0.2;
+    const PROJECTILE_LIFETIME:f32 = 5.0;
+    // Turrets only fire when they are aimed this close to the player, in radians
+    const FIRING_CONE:f32 = 0.15;
+    const TURRET_RADIUS:f32 = 0.6;
+
+    /// A hazard, as placed in a level file
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) enum HazardDef {
+        Turret {
+            translation: Vec3,
+            /// Degrees per second
+            #[serde(default = "default_turn_rate")]
+            turn_rate: f32,
+            /// How far the turret can see
+            #[serde(default = "default_range")]
+            range: f32,
+            /// Seconds between shots
+            #[serde(default = "default_fire_interval")]
+            fire_interval: f32,
+            #[serde(default = "default_projectile_speed")]
+            projectile_speed: f32,
+        },
+        Blade {
+            translation: Vec3,
+            #[serde(default = "default_blade_radius")]
+            radius: f32,
+            /// Degrees per second around the vertical axis
+            #[serde(default = "default_spin_speed")]
+            spin_speed: f32,
+        },
+        Crusher {
+            translation: Vec3,
+            half_extents: Vec3,
+            /// How far down the crusher slams
+            drop: f32,
+            #[serde(default = "default_crusher_speed")]
+            speed: f32,
+            /// Seconds spent waiting at the top and bottom
+            #[serde(default = "default_crusher_pause")]
+            pause: f32,
+        },
+    }
+
+    fn default_turn_rate() -> f32 { 90.0 }
+    fn default_range() -> f32 { 40.0 }
+    fn default_fire_interval() -> f32 { 2.0 }
+    fn default_projectile_speed() -> f32 { 15.0 }
+    fn default_blade_radius() -> f32 { 2.0 }
+    fn default_spin_speed() -> f32 { 360.0 }
+    fn default_crusher_speed() -> f32 { 6.0 }
+    fn default_crusher_pause() -> f32 { 1.0 }
+
+    #[derive(Component)]
+    pub(crate) struct Turret {
+        pub(crate) range: f32,
+        pub(crate) projectile_speed: f32,
+        pub(crate) cooldown: Timer,
+        /// Whether the last line of sight check reached the player
+        pub(crate) sees_player: bool,
+    }
+
+    #[derive(Component)]
+    pub(crate) struct Projectile {
+        lifetime: Timer,
+    }
+
+    pub(crate) fn spawn_hazard(
+        commands: &mut Commands,
+        meshes: &mut Assets<Mesh>,
+        materials: &mut Assets<StandardMaterial>,
+        hazard: &HazardDef,
+    ) {
+        let hazard_material = materials.add(StandardMaterial {
+            base_color: Color::DARK_GRAY,
+            emissive: Color::rgb(0.6, 0.0, 0.0),
+            metallic: 1.0,
+            ..default()
+        });
+
+        match hazard {
+            HazardDef::Turret { translation, turn_rate, range, fire_interval, projectile_speed } => {
+                commands.spawn(PbrBundle {
+                    mesh: meshes.add(Mesh::from(shape::UVSphere { radius: TURRET_RADIUS, sectors: 16, stacks: 16 })),
+                    material: hazard_material.clone(),
+                    transform: Transform::from_translation(*translation),
+                    ..default()
+                })  .insert(Collider::ball(TURRET_RADIUS))
+                    .insert(CollisionRole::Platform)
+                    .insert(SurfaceMaterial::Metal)
+                    .insert(PointingAtPlayer { turn_rate: turn_rate.to_radians() })
+                    .insert(Turret {
+                        range: *range,
+                        projectile_speed: *projectile_speed,
+                        cooldown: Timer::from_seconds(*fire_interval, TimerMode::Once),
+                        sees_player: false,
+                    })
+                    .insert(Name::new("Turret"))
+                    .with_children(|parent| {
+                        // Barrel, pointing forwards (-z)
+                        parent.spawn(PbrBundle {
+                            mesh: meshes.add(Mesh::from(shape::Box::new(0.2, 0.2, 1.0))),
+                            material: hazard_material,
+                            transform: Transform::from_xyz(0.0, 0.0, -0.6),
+                            ..default()
+                        });
+                    });
+            },
+            HazardDef::Blade { translation, radius, spin_speed } => {
+                commands.spawn(PbrBundle {
+                    mesh: meshes.add(Mesh::from(shape::Box::new(2.0 * radius, 0.1, 0.4))),
+                    material: hazard_material,
+                    transform: Transform::from_translation(*translation),
+                    ..default()
+                })  .insert(Collider::cuboid(*radius, 0.05, 0.2))
+                    .insert(RigidBody::KinematicPositionBased)
+                    .insert(MovingPlatform::new(*translation, None, Vec3::Y * spin_speed.to_radians()))
+                    .insert(CollisionRole::Hazard)
+                    .insert(SurfaceMaterial::Metal)
+                    .insert(Name::new("Blade"));
+            },
+            HazardDef::Crusher { translation, half_extents, drop, speed, pause } => {
+                let path = PlatformPath::Waypoints {
+                    points: vec![Vec3::ZERO, Vec3::NEG_Y * *drop],
+                    speed: *speed,
+                    pause: *pause,
+                    easing: Easing::EaseIn,
+                    looped: false,
+                };
+                commands.spawn(PbrBundle {
+                    mesh: meshes.add(Mesh::from(shape::Box::new(2.0 * half_extents.x, 2.0 * half_extents.y, 2.0 * half_extents.z))),
+                    material: hazard_material,
+                    transform: Transform::from_translation(*translation),
+                    ..default()
+                })  .insert(Collider::cuboid(half_extents.x, half_extents.y, half_extents.z))
+                    .insert(RigidBody::KinematicPositionBased)
+                    .insert(MovingPlatform::new(*translation, Some(path), Vec3::ZERO))
+                    .insert(CollisionRole::Hazard)
+                    .insert(SurfaceMaterial::Metal)
+                    .insert(Name::new("Crusher"));
+            },
+        }
+    }
+
+    pub(crate) fn fire_turrets(
+        mut commands: Commands,
+        time: Res<Time>,
+        rapier_context: Res<RapierContext>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut materials: ResMut<Assets<StandardMaterial>>,
+        mut turrets: Query<(Entity, &mut Turret, &GlobalTransform)>,
+        players: Query<(Entity, &GlobalTransform), With<Controlling>>,
+    ) {
+        let Ok((player, player_transform)) = players.get_single() else { return };
+        let player_position = player_transform.translation();
+
+        for (entity, mut turret, transform) in &mut turrets {
+            turret.cooldown.tick(time.delta());
+
+            let position = transform.translation();
+            let to_player = player_position - position;
+            let distance = to_player.length();
+
+            // Line of sight: the first thing a ray towards the player hits has to be the player.
+            // There's no direction to cast in if the player is right on top of the turret.
+            turret.sees_player = distance <= turret.range && to_player.try_normalize().is_some_and(|direction| rapier_context.cast_ray(
+                position,
+                direction,
+                distance,
+                true,
+                QueryFilter::default().exclude_sensors().exclude_collider(entity),
+            ).map_or(false, |(hit, _)| hit == player));
+
+            let forward = transform.forward();
+            let aimed = forward.angle_between(to_player) <= FIRING_CONE;
+
+            if turret.sees_player && aimed && turret.cooldown.finished() {
+                turret.cooldown.reset();
+                commands.spawn(PbrBundle {
+                    mesh: meshes.add(Mesh::from(shape::UVSphere { radius: PROJECTILE_RADIUS, sectors: 8, stacks: 8 })),
+                    material: materials.add(StandardMaterial { base_color: Color::RED, emissive: Color::RED, ..default() }),
+                    // Start just outside the turret, so it doesn't hit it
+                    transform: Transform::from_translation(position + forward * (TURRET_RADIUS + 2.0 * PROJECTILE_RADIUS)),
+                    ..default()
+                })  .insert(RigidBody::Dynamic)
+                    .insert(Collider::ball(PROJECTILE_RADIUS))
+                    .insert(GravityScale(0.0))
+                    .insert(Ccd::enabled())
+                    .insert(Velocity::linear(forward * turret.projectile_speed))
+                    .insert(CollisionRole::Hazard)
+                    .insert(Projectile { lifetime: Timer::from_seconds(PROJECTILE_LIFETIME, TimerMode::Once) })
+                    .insert(Name::new("Projectile"));
+            }
+        }
+    }
+
+    /**
+     * Removes projectiles that hit something or have been flying for too long
+     */
+    pub(crate) fn expire_projectiles(
+        mut commands: Commands,
+        time: Res<Time>,
+        rapier_context: Res<RapierContext>,
+        mut projectiles: Query<(Entity, &mut Projectile)>,
+    ) {
+        for (entity, mut projectile) in &mut projectiles {
+            projectile.lifetime.tick(time.delta());
+            let hit_something = rapier_context.contacts_with(entity).any(|contact| contact.has_any_active_contacts());
+
+            if projectile.lifetime.finished() || hit_something {
+                commands.entity(entity).despawn_recursive();
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+pub(crate) mod hud {
+    //! On screen HUD: frame time graph, horizontal speed, run timer, collectible count and whether
+    //! the player can dash.
+    //!
+    //! Every element has a [`HudElement`] and is shown or hidden by [`HudSettings`], which are
+    //! part of the player's settings. The collectible counter is spawned by the collectibles
+    //! module, but is switched on and off here like the rest.
+
+    use std::collections::VecDeque;
+    use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+    use serde::{Serialize, Deserialize};
+
+    use crate::settings::settings::Settings;
+    use crate::setup_world::setup_objects::{Controlling, ControllingButWithInfo};
+    use crate::speedrun::speedrun::{format_time, RunFinished, RunState, RunTimer};
+
+    // Number of frames shown in the graph
+    const GRAPH_FRAMES:usize = 90;
+    // Frame time that fills the graph's height, in milliseconds
+    const GRAPH_MAX_MS:f32 = 50.0;
+    const GRAPH_HEIGHT:f32 = 60.0;
+    // Frames slower than this are drawn in red
+    const SLOW_FRAME_MS:f32 = 1000.0 / 30.0;
+
+    /// Which HUD elements are shown
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    #[serde(default)]
+    pub(crate) struct HudSettings {
+        pub(crate) fps_graph: bool,
+        pub(crate) speed: bool,
+        pub(crate) timer: bool,
+        pub(crate) collectibles: bool,
+        pub(crate) dash: bool,
+    }
+
+    impl Default for HudSettings {
+        fn default() -> Self {
+            HudSettings { fps_graph: false, speed: true, timer: true, collectibles: true, dash: true }
+        }
+    }
+
+    #[derive(Component, Clone, Copy, PartialEq, Debug)]
+    pub(crate) enum HudElement {
+        FpsGraph,
+        Speed,
+        Timer,
+        Collectibles,
+        Dash,
+    }
+
+    impl HudElement {
+        fn enabled(&self, settings: &HudSettings) -> bool {
+            match self {
+                HudElement::FpsGraph => settings.fps_graph,
+                HudElement::Speed => settings.speed,
+                HudElement::Timer => settings.timer,
+                HudElement::Collectibles => settings.collectibles,
+                HudElement::Dash => settings.dash,
+            }
+        }
+    }
+
+    #[derive(Component)]
+    pub(crate) struct FpsText;
+
+    /// One bar of the frame time graph. Bar 0 is the oldest frame.
+    #[derive(Component)]
+    pub(crate) struct GraphBar(usize);
+
+    #[derive(Component)]
+    pub(crate) struct SpeedText;
+
+    #[derive(Component)]
+    pub(crate) struct TimerText;
+
+    #[derive(Component)]
+    pub(crate) struct DashBar;
+
+    /// Frame times in milliseconds, newest last
+    #[derive(Resource, Default)]
+    pub(crate) struct FrameTimes(VecDeque<f32>);
+
+    fn hud_text(value: &str, font_size: f32) -> TextBundle {
+        TextBundle::from_section(value, TextStyle {
+            font_size,
+            color: Color::WHITE,
+            ..default()
+        })
+    }
+
+    pub(crate) fn setup_hud(mut commands: Commands) {
+        // Frame time graph, top right
+        commands.spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                right: Val::Px(10.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::FlexEnd,
+                ..default()
+            },
+            ..default()
+        })  .insert(HudElement::FpsGraph)
+            .insert(Name::new("Frame time graph"))
+            .with_children(|parent| {
+                parent.spawn(hud_text("", 18.0)).insert(FpsText);
+                parent.spawn(NodeBundle {
+                    style: Style {
+                        height: Val::Px(GRAPH_HEIGHT),
+                        align_items: AlignItems::FlexEnd,
+                        ..default()
+                    },
+                    background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+                    ..default()
+                }).with_children(|graph| {
+                    for i in 0..GRAPH_FRAMES {
+                        graph.spawn(NodeBundle {
+                            style: Style {
+                                width: Val::Px(2.0),
+                                height: Val::Px(0.0),
+                                ..default()
+                            },
+                            background_color: Color::GREEN.into(),
+                            ..default()
+                        }).insert(GraphBar(i));
+                    }
+                });
+            });
+
+        // Timer, top center
+        commands.spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.0),
+                width: Val::Percent(100.0),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })  .insert(HudElement::Timer)
+            .insert(Name::new("Run timer"))
+            .with_children(|parent| {
+                parent.spawn(hud_text("", 36.0)).insert(TimerText);
+            });
+
+        // Speed and dash indicator, bottom left
+        commands.spawn(hud_text("", 28.0).with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(40.0),
+            left: Val::Px(10.0),
+            ..default()
+        }))
+            .insert(SpeedText)
+            .insert(HudElement::Speed)
+            .insert(Name::new("Speed"));
+
+        commands.spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.0),
+                left: Val::Px(10.0),
+                width: Val::Px(150.0),
+                height: Val::Px(16.0),
+                padding: UiRect::all(Val::Px(2.0)),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+            ..default()
+        })  .insert(HudElement::Dash)
+            .insert(Name::new("Dash indicator"))
+            .with_children(|parent| {
+                parent.spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    background_color: Color::CYAN.into(),
+                    ..default()
+                }).insert(DashBar);
+            });
+    }
+
+    pub(crate) fn apply_hud_settings(settings: Res<Settings>, mut elements: Query<(&HudElement, &mut Visibility)>) {
+        // Also runs for elements spawned since the settings last changed
+        for (element, mut visibility) in &mut elements {
+            let wanted = if element.enabled(&settings.hud) { Visibility::Inherited } else { Visibility::Hidden };
+            if *visibility != wanted {
+                *visibility = wanted;
+            }
+        }
+    }
+
+    pub(crate) fn toggle_fps_graph(keyboard_input: Res<Input<KeyCode>>, mut settings: ResMut<Settings>) {
+        if keyboard_input.just_pressed(KeyCode::F3) {
+            settings.hud.fps_graph = !settings.hud.fps_graph;
+        }
+    }
+
+    pub(crate) fn update_fps_graph(
+        time: Res<Time>,
+        diagnostics: Res<DiagnosticsStore>,
+        mut frame_times: ResMut<FrameTimes>,
+        mut texts: Query<&mut Text, With<FpsText>>,
+        mut bars: Query<(&GraphBar, &mut Style, &mut BackgroundColor)>,
+    ) {
+        frame_times.0.push_back(time.delta_seconds() * 1000.0);
+        while frame_times.0.len() > GRAPH_FRAMES {
+            frame_times.0.pop_front();
+        }
+
+        let fps = diagnostics.get(FrameTimeDiagnosticsPlugin::FPS).and_then(|fps| fps.smoothed());
+        let frame_time = diagnostics.get(FrameTimeDiagnosticsPlugin::FRAME_TIME).and_then(|ms| ms.smoothed());
+        for mut text in &mut texts {
+            text.sections[0].value = match (fps, frame_time) {
+                (Some(fps), Some(frame_time)) => format!("{:.0} fps ({:.1} ms)", fps, frame_time),
+                _ => String::new(),
+            };
+        }
+
+        // Right align the history, so the newest frame is always the last bar
+        let offset = GRAPH_FRAMES - frame_times.0.len();
+        for (bar, mut style, mut background) in &mut bars {
+            let ms = if bar.0 >= offset { frame_times.0[bar.0 - offset] } else { 0.0 };
+            style.height = Val::Px(GRAPH_HEIGHT * (ms / GRAPH_MAX_MS).min(1.0));
+            background.0 = if ms > SLOW_FRAME_MS { Color::RED } else { Color::GREEN };
+        }
+    }
+
+    pub(crate) fn update_hud(
+        timer: Res<RunTimer>,
+        mut run_finished: EventReader<RunFinished>,
+        players: Query<(&Velocity, &ControllingButWithInfo), With<Controlling>>,
+        mut timer_texts: Query<&mut Text, (With<TimerText>, Without<SpeedText>)>,
+        mut speed_texts: Query<&mut Text, (With<SpeedText>, Without<TimerText>)>,
+        mut dash_bars: Query<&mut BackgroundColor, With<DashBar>>,
+    ) {
+        for mut text in &mut timer_texts {
+            text.sections[0].value = format_time(timer.elapsed);
+            if timer.state == RunState::Running {
+                text.sections[0].style.color = Color::WHITE;
+            }
+        }
+        // The final time stays up in gold when it's a new personal best
+        for finished in run_finished.iter() {
+            for mut text in &mut timer_texts {
+                text.sections[0].value = format_time(finished.time);
+                text.sections[0].style.color = if finished.new_best { Color::GOLD } else { Color::WHITE };
+            }
+        }
+
+        let Ok((velocity, player_info)) = players.get_single() else { return };
+
+        let horizontal_speed = Vec2::new(velocity.linvel.x, velocity.linvel.z).length();
+        for mut text in &mut speed_texts {
+            text.sections[0].value = format!("{:.1} m/s", horizontal_speed);
+        }
+
+        for mut background in &mut dash_bars {
+            background.0 = if player_info.can_dash { Color::CYAN } else { Color::GRAY };
+        }
+    }
+}
@@ -0,0 +1,516 @@
+pub(crate) mod level {
+    //! Data-driven level definitions.
+    //!
+    //! A level is a RON file describing every primitive, light and glTF scene in the world, plus
+    //! where the player starts. The default level is embedded in the binary, and a different one
+    //! can be picked at runtime with the `level=path/to/level.ron` argument, so new levels don't
+    //! need a recompile.
+
+    use std::fs;
+    use bevy::gltf::Gltf;
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+    use serde::{Serialize, Deserialize};
+
+    use crate::audio::audio::{AmbientSound, Sound};
+    use crate::collectibles::collectibles::{CollectedItems, CollectibleKind, spawn_collectible};
+    use crate::checkpoints::checkpoints::{Checkpoint, KillPlane, KillVolume, RespawnPoint};
+    use crate::collisions::collisions::CollisionRole;
+    use crate::enemies::enemies::{EnemyDef, spawn_enemy};
+    use crate::hazards::hazards::{HazardDef, spawn_hazard};
+    use crate::loading::loading::LoadingAssets;
+    use crate::music::music::Soundtrack;
+    use crate::navmesh::navmesh::PendingNavMeshes;
+    use crate::particles::particles::{orb_motes, ParticleEmitter};
+    use crate::sky::sky::{SkyDef, Sun};
+    use crate::speedrun::speedrun::Goal;
+    use crate::procgen::procgen::{PlatformGenerator, WorldSeed, generate_platforms};
+    use crate::platforms::platforms::{MovingPlatform, PlatformPath};
+    use crate::surfaces::surfaces::SurfaceMaterial;
+
+    const DEFAULT_LEVEL: &str = include_str!("../levels/default.ron");
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct LevelDefinition {
+        pub(crate) name: String,
+        pub(crate) player_spawn: Vec3,
+        /// Seed for everything random in the level. Overridden by the `seed=N` argument.
+        #[serde(default)]
+        pub(crate) seed: Option<u64>,
+        #[serde(default)]
+        pub(crate) primitives: Vec<PrimitiveDef>,
+        #[serde(default)]
+        pub(crate) procedural_platforms: Option<PlatformGenerator>,
+        #[serde(default)]
+        pub(crate) lights: Vec<LightOrbDef>,
+        #[serde(default)]
+        pub(crate) sun: Option<SunDef>,
+        #[serde(default)]
+        pub(crate) scenes: Vec<SceneDef>,
+        /// The player dies when they fall below this height
+        #[serde(default = "default_kill_plane")]
+        pub(crate) kill_plane: f32,
+        #[serde(default)]
+        pub(crate) checkpoints: Vec<CheckpointDef>,
+        #[serde(default)]
+        pub(crate) kill_volumes: Vec<KillVolumeDef>,
+        /// Touching the goal ends the run
+        #[serde(default)]
+        pub(crate) goal: Option<GoalDef>,
+        #[serde(default)]
+        pub(crate) collectibles: Vec<CollectibleDef>,
+        /// Turrets, blades and crushers
+        #[serde(default)]
+        pub(crate) hazards: Vec<HazardDef>,
+        #[serde(default)]
+        pub(crate) enemies: Vec<EnemyDef>,
+
+        #[serde(default)]
+        pub(crate) soundtrack: Soundtrack,
+        /// A KTX2 cubemap, an image with the six faces stacked vertically, or an equirectangular
+        /// image (twice as wide as it is tall)
+        #[serde(default = "default_skybox")]
+        pub(crate) skybox: String,
+        /// A procedural sky with a day/night cycle that moves the sun
+        #[serde(default)]
+        pub(crate) sky: Option<SkyDef>,
+    }
+
+    /// A box or ball, optionally rendered, optionally solid
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct PrimitiveDef {
+        pub(crate) name: String,
+        pub(crate) shape: ShapeDef,
+        pub(crate) translation: Vec3,
+        /// Euler angles (XYZ) in degrees
+        #[serde(default)]
+        pub(crate) rotation: Vec3,
+        /// Primitives without a material are invisible (walls, for example)
+        #[serde(default)]
+        pub(crate) material: Option<MaterialDef>,
+        #[serde(default = "default_true")]
+        pub(crate) collider: bool,
+        /// Makes the primitive a kinematic platform following this path
+        #[serde(default)]
+        pub(crate) path: Option<PlatformPath>,
+        /// Angular velocity (XYZ) in degrees per second
+        #[serde(default)]
+        pub(crate) spin: Vec3,
+        /// Stone if not set
+        #[serde(default)]
+        pub(crate) surface: Option<SurfaceMaterial>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+    pub(crate) enum ShapeDef {
+        Cuboid { half_extents: Vec3 },
+        Ball { radius: f32 },
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct MaterialDef {
+        pub(crate) base_color: Color,
+        #[serde(default = "default_emissive")]
+        pub(crate) emissive: Color,
+        #[serde(default)]
+        pub(crate) metallic: f32,
+        #[serde(default = "default_reflectance")]
+        pub(crate) reflectance: f32,
+    }
+
+    /// A glowing sphere with a point light inside
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct LightOrbDef {
+        pub(crate) position: Vec3,
+        pub(crate) hue: f32,
+        #[serde(default = "default_orb_intensity")]
+        pub(crate) intensity: f32,
+        #[serde(default = "default_orb_radius")]
+        pub(crate) radius: f32,
+        #[serde(default = "default_true")]
+        pub(crate) shadows: bool,
+        /// Plays a quiet hum that can be heard nearby
+        #[serde(default)]
+        pub(crate) hum: bool,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct SunDef {
+        pub(crate) illuminance: f32,
+        #[serde(default)]
+        pub(crate) translation: Vec3,
+        /// Euler angles (XYZ) in degrees
+        #[serde(default)]
+        pub(crate) rotation: Vec3,
+        #[serde(default = "default_true")]
+        pub(crate) shadows: bool,
+    }
+
+    /// A glTF scene. If `hitboxes` is set, colliders are generated for every mesh in it.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct SceneDef {
+        pub(crate) name: String,
+        pub(crate) path: String,
+        #[serde(default)]
+        pub(crate) translation: Vec3,
+        /// Euler angles (XYZ) in degrees
+        #[serde(default)]
+        pub(crate) rotation: Vec3,
+        #[serde(default = "default_scale")]
+        pub(crate) scale: Vec3,
+        #[serde(default = "default_true")]
+        pub(crate) hitboxes: bool,
+    }
+
+    /// Touching the box around `translation` makes `spawn` the new respawn point
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct CheckpointDef {
+        pub(crate) translation: Vec3,
+        #[serde(default = "default_checkpoint_extents")]
+        pub(crate) half_extents: Vec3,
+        /// Defaults to the middle of the checkpoint
+        #[serde(default)]
+        pub(crate) spawn: Option<Vec3>,
+    }
+
+    /// An invisible box that kills the player
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct KillVolumeDef {
+        pub(crate) translation: Vec3,
+        pub(crate) half_extents: Vec3,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct GoalDef {
+        pub(crate) translation: Vec3,
+        #[serde(default = "default_checkpoint_extents")]
+        pub(crate) half_extents: Vec3,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct CollectibleDef {
+        pub(crate) kind: CollectibleKind,
+        pub(crate) translation: Vec3,
+        /// Used to remember that it was collected. Defaults to its position in the list.
+        #[serde(default)]
+        pub(crate) id: Option<String>,
+    }
+
+    fn default_true() -> bool { true }
+    fn default_kill_plane() -> f32 { -100.0 }
+    fn default_checkpoint_extents() -> Vec3 { Vec3::new(1.5, 1.5, 1.5) }
+    fn default_scale() -> Vec3 { Vec3::ONE }
+    fn default_emissive() -> Color { Color::BLACK }
+    fn default_reflectance() -> f32 { 0.5 }
+    fn default_skybox() -> String { "textures/space-skybox-k.png".to_string() }
+    fn default_orb_intensity() -> f32 { 10.0 }
+    fn default_orb_radius() -> f32 { 1.0 }
+
+    fn euler_degrees(rotation: Vec3) -> Quat {
+        Quat::from_euler(EulerRot::XYZ, rotation.x.to_radians(), rotation.y.to_radians(), rotation.z.to_radians())
+    }
+
+    /// The level that is currently loaded
+    #[derive(Resource)]
+    pub(crate) struct CurrentLevel(pub(crate) LevelDefinition);
+
+    /// The glTF file a scene was loaded from, so its material names can be looked up
+    #[derive(Component)]
+    pub(crate) struct SceneSource(pub(crate) Handle<Gltf>);
+
+    /**
+     * Loads a level from a RON file. If there is no path, or the file can't be read or parsed,
+     * the level embedded in the binary is used instead.
+     */
+    pub(crate) fn load_level(path: Option<&str>) -> LevelDefinition {
+        if let Some(path) = path {
+            match fs::read_to_string(path) {
+                Ok(text) => match ron::from_str(&text) {
+                    Ok(level) => return level,
+                    Err(why) => println!("couldn't parse level {}: {}", path, why),
+                },
+                Err(why) => println!("couldn't open level {}: {}", path, why),
+            }
+            println!("Falling back to the default level");
+        }
+
+        ron::from_str(DEFAULT_LEVEL).expect("The embedded default level is invalid")
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn spawn_level(
+        mut commands: Commands,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut materials: ResMut<Assets<StandardMaterial>>,
+        asset_server: Res<AssetServer>,
+        level: Res<CurrentLevel>,
+        seed: Res<WorldSeed>,
+        collected: Res<CollectedItems>,
+        mut loading: ResMut<LoadingAssets>,
+        mut pending_navmeshes: ResMut<PendingNavMeshes>,
+    ) {
+        let level = &level.0;
+        info!("spawning level {}", level.name);
+
+        commands.insert_resource(KillPlane(level.kill_plane));
+        commands.insert_resource(RespawnPoint(level.player_spawn));
+
+        for primitive in &level.primitives {
+            let transform = Transform::from_translation(primitive.translation)
+                .with_rotation(euler_degrees(primitive.rotation));
+
+            let mut entity = match &primitive.material {
+                Some(material) => commands.spawn(PbrBundle {
+                    mesh: meshes.add(primitive_mesh(primitive.shape)),
+                    material: materials.add(StandardMaterial {
+                        base_color: material.base_color,
+                        emissive: material.emissive,
+                        metallic: material.metallic,
+                        reflectance: material.reflectance,
+                        alpha_mode: if material.base_color.a() < 1.0 { AlphaMode::Blend } else { AlphaMode::Opaque },
+                        ..default()
+                    }),
+                    transform,
+                    ..default()
+                }),
+                None => commands.spawn(TransformBundle::from(transform)),
+            };
+
+            entity.insert(Name::new(primitive.name.clone()));
+
+            if primitive.collider {
+                entity.insert(primitive_collider(primitive.shape))
+                    .insert(CollisionRole::Platform);
+            }
+            if let Some(surface) = primitive.surface {
+                entity.insert(surface);
+            }
+            if primitive.path.is_some() || primitive.spin != Vec3::ZERO {
+                let spin = Vec3::new(primitive.spin.x.to_radians(), primitive.spin.y.to_radians(), primitive.spin.z.to_radians());
+                entity.insert(RigidBody::KinematicPositionBased)
+                    .insert(MovingPlatform::new(primitive.translation, primitive.path.clone(), spin));
+            } else if primitive.collider {
+                // Walls and floors that stay put are part of the navmesh, moving platforms aren't
+                pending_navmeshes.add_mesh(&primitive_mesh(primitive.shape), &transform);
+            }
+        }
+
+        if let Some(generator) = &level.procedural_platforms {
+            let platforms = generate_platforms(&seed, generator).unwrap_or_else(|error| {
+                println!("WARNING! NO REACHABLE PLATFORM CHAIN IN {} ATTEMPTS, USING THE LAST ONE", error.attempts);
+                error.last_attempt
+            });
+            let count = platforms.len();
+
+            for (i, platform) in platforms.into_iter().enumerate() {
+                let shape = ShapeDef::Cuboid { half_extents: platform.half_extents };
+                let transform = Transform::from_translation(platform.center);
+                let mesh = primitive_mesh(shape);
+                pending_navmeshes.add_mesh(&mesh, &transform);
+                commands.spawn(PbrBundle {
+                    mesh: meshes.add(mesh),
+                    material: materials.add(Color::rgb(1.0, i as f32 / count as f32, 0.0).into()),
+                    transform,
+                    ..default()
+                })
+                    .insert(primitive_collider(shape))
+                    .insert(CollisionRole::Platform)
+                    .insert(Name::new("floating platform"));
+            }
+        }
+
+        for orb in &level.lights {
+            let mut entity = commands.spawn(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::UVSphere { radius: 0.5, sectors: 10, stacks:10  })),
+                material: materials.add(Color::hsla(orb.hue, 100.0, 0.05, 0.7).into()),
+                transform: Transform::from_translation(orb.position),
+                ..default()
+            });
+            if orb.hum {
+                entity.insert(AmbientSound(Sound::Hum));
+            }
+            entity.insert(ParticleEmitter::new(orb_motes(orb.hue), 3.0, Vec3::Y))
+                .insert(Name::new("Light"))
+                .with_children(|parent|{
+                parent.spawn(PointLightBundle {
+                    point_light: PointLight {
+                        intensity: orb.intensity,
+                        radius: orb.radius,
+                        shadows_enabled: orb.shadows,
+                        color: Color::hsla(orb.hue, 100.0, 0.05, 0.0001),
+                        ..default()
+                    },
+                    ..default()
+                });
+            });
+        }
+
+        if let Some(sun) = &level.sun {
+            commands.spawn(DirectionalLightBundle {
+                directional_light: DirectionalLight {
+                    illuminance: sun.illuminance,
+                    shadows_enabled: sun.shadows,
+                    ..default()
+                },
+                transform: Transform::from_translation(sun.translation)
+                    .with_rotation(euler_degrees(sun.rotation)),
+                ..default()
+            })  .insert(Sun { illuminance: sun.illuminance })
+                .insert(Name::new("Sun"));
+        }
+
+        for (index, checkpoint) in level.checkpoints.iter().enumerate() {
+            let half_extents = checkpoint.half_extents;
+            commands.spawn(TransformBundle::from(Transform::from_translation(checkpoint.translation)))
+                .insert(VisibilityBundle::default())
+                .insert(Collider::cuboid(half_extents.x, half_extents.y, half_extents.z))
+                .insert(Sensor)
+                .insert(CollisionRole::Trigger)
+                .insert(Checkpoint { index, spawn: checkpoint.spawn.unwrap_or(checkpoint.translation) })
+                .insert(Name::new("Checkpoint"))
+                .with_children(|parent| {
+                    // Glowing pad on the floor of the checkpoint
+                    parent.spawn(PbrBundle {
+                        mesh: meshes.add(Mesh::from(shape::Box::new(2.0 * half_extents.x, 0.05, 2.0 * half_extents.z))),
+                        material: materials.add(StandardMaterial {
+                            base_color: Color::rgba(0.0, 1.0, 1.0, 0.3),
+                            emissive: Color::CYAN,
+                            alpha_mode: AlphaMode::Blend,
+                            ..default()
+                        }),
+                        transform: Transform::from_xyz(0.0, -half_extents.y, 0.0),
+                        ..default()
+                    });
+                });
+        }
+
+        for volume in &level.kill_volumes {
+            let half_extents = volume.half_extents;
+            commands.spawn(TransformBundle::from(Transform::from_translation(volume.translation)))
+                .insert(Collider::cuboid(half_extents.x, half_extents.y, half_extents.z))
+                .insert(Sensor)
+                .insert(CollisionRole::Hazard)
+                .insert(KillVolume)
+                .insert(Name::new("Kill volume"));
+        }
+
+        if let Some(goal) = &level.goal {
+            let half_extents = goal.half_extents;
+            commands.spawn(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Box::new(2.0 * half_extents.x, 2.0 * half_extents.y, 2.0 * half_extents.z))),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::rgba(1.0, 0.8, 0.0, 0.2),
+                    emissive: Color::GOLD,
+                    alpha_mode: AlphaMode::Blend,
+                    ..default()
+                }),
+                transform: Transform::from_translation(goal.translation),
+                ..default()
+            })  .insert(Collider::cuboid(half_extents.x, half_extents.y, half_extents.z))
+                .insert(Sensor)
+                .insert(CollisionRole::Trigger)
+                .insert(Goal)
+                .insert(Name::new("Goal"));
+        }
+
+        for (index, collectible) in level.collectibles.iter().enumerate() {
+            let id = collectible.id.clone().unwrap_or_else(|| format!("collectible-{}", index));
+            spawn_collectible(&mut commands, &mut meshes, &mut materials, &collected, id, collectible.kind, collectible.translation);
+        }
+
+        for hazard in &level.hazards {
+            spawn_hazard(&mut commands, &mut meshes, &mut materials, hazard);
+        }
+
+        for enemy in &level.enemies {
+            spawn_enemy(&mut commands, &mut meshes, &mut materials, enemy);
+        }
+
+        for scene in &level.scenes {
+            let handle: Handle<Scene> = asset_server.load(&scene.path);
+            loading.handles.push(handle.clone_untyped());
+            let mut entity = commands.spawn(SceneBundle {
+                scene: handle,
+                transform: Transform::from_translation(scene.translation)
+                    .with_rotation(euler_degrees(scene.rotation))
+                    .with_scale(scene.scale),
+                ..default()
+            });
+            entity.insert(Name::new(scene.name.clone()));
+
+            if scene.hitboxes {
+                // The glTF file itself, without the #Scene0 label
+                let file = scene.path.split('#').next().unwrap_or(&scene.path);
+                entity.insert(crate::MakeHitboxes)
+                    .insert(SceneSource(asset_server.load(file)));
+            }
+        }
+    }
+
+    fn primitive_mesh(shape: ShapeDef) -> Mesh {
+        match shape {
+            ShapeDef::Cuboid { half_extents } => Mesh::from(shape::Box::new(
+                2.0 * half_extents.x,
+                2.0 * half_extents.y,
+                2.0 * half_extents.z,
+            )),
+            ShapeDef::Ball { radius } => Mesh::from(shape::UVSphere { radius, sectors: 10, stacks: 10 }),
+        }
+    }
+
+    fn primitive_collider(shape: ShapeDef) -> Collider {
+        match shape {
+            ShapeDef::Cuboid { half_extents } => Collider::cuboid(half_extents.x, half_extents.y, half_extents.z),
+            ShapeDef::Ball { radius } => Collider::ball(radius),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn default_level_parses() {
+            let level: LevelDefinition = ron::from_str(DEFAULT_LEVEL).unwrap();
+            assert_eq!(level.name, "default");
+            assert!(!level.primitives.is_empty());
+        }
+
+        #[test]
+        fn missing_fields_use_defaults() {
+            let level: LevelDefinition = ron::from_str(r#"(
+                name: "minimal",
+                player_spawn: (0.0, 1.0, 0.0),
+                primitives: [
+                    (name: "Floor", shape: Cuboid(half_extents: (10.0, 0.5, 10.0)), translation: (0.0, -0.5, 0.0)),
+                ],
+                scenes: [
+                    (name: "Ruins", path: "ruins.glb#Scene0"),
+                ],
+            )"#).unwrap();
+
+            assert_eq!(level.kill_plane, default_kill_plane());
+            assert_eq!(level.skybox, default_skybox());
+            assert!(level.seed.is_none() && level.goal.is_none() && level.lights.is_empty());
+
+            let floor = &level.primitives[0];
+            assert!(floor.collider);
+            assert!(floor.material.is_none() && floor.path.is_none() && floor.surface.is_none());
+            assert_eq!(floor.rotation, Vec3::ZERO);
+
+            let scene = &level.scenes[0];
+            assert_eq!(scene.scale, Vec3::ONE);
+            assert!(scene.hitboxes);
+        }
+
+        #[test]
+        fn unreadable_levels_fall_back_to_the_default() {
+            assert_eq!(load_level(Some("levels/does-not-exist.ron")).name, "default");
+
+            let path = std::env::temp_dir().join("rust-game-broken-level.ron");
+            fs::write(&path, "(name: \"broken\"").unwrap();
+            assert_eq!(load_level(path.to_str()).name, "default");
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+pub(crate) mod loading {
+    //! The loading screen shown in [`GameState::Loading`].
+    //!
+    //! Systems that load something the level can't do without add its handle to
+    //! [`LoadingAssets`]. Loading is finished once all of those are loaded, the skybox has been
+    //! turned into a cubemap, and every scene marked with `MakeHitboxes` has its colliders and
+    //! navmesh (or failed to load). Only then is the player's rigid body enabled, so they can't fall through a level
+    //! that isn't there yet.
+
+    use bevy::asset::LoadState;
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+
+    use crate::game_state::game_state::GameState;
+    use crate::level::level::SceneSource;
+    use crate::navmesh::navmesh::PendingNavMeshes;
+    use crate::setup_world::setup_objects::Controlling;
+    use crate::skybox::skybox::Cubemap;
+    use crate::MakeHitboxes;
+
+    /// Handles that have to finish loading before the game can start
+    #[derive(Resource, Default)]
+    pub(crate) struct LoadingAssets {
+        pub(crate) handles: Vec<HandleUntyped>,
+    }
+
+    #[derive(Component)]
+    pub(crate) struct LoadingScreen;
+
+    #[derive(Component)]
+    pub(crate) struct LoadingBar;
+
+    #[derive(Component)]
+    pub(crate) struct LoadingText;
+
+    pub(crate) fn setup_loading_screen(mut commands: Commands) {
+        commands.spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(16.0),
+                ..default()
+            },
+            background_color: Color::BLACK.into(),
+            z_index: ZIndex::Global(50),
+            ..default()
+        })  .insert(LoadingScreen)
+            .insert(Name::new("Loading screen"))
+            .with_children(|parent| {
+                parent.spawn(TextBundle::from_section("Loading...", TextStyle {
+                    font_size: 32.0,
+                    color: Color::WHITE,
+                    ..default()
+                })).insert(LoadingText);
+
+                // Bar outline, with the fill growing inside it
+                parent.spawn(NodeBundle {
+                    style: Style {
+                        width: Val::Px(400.0),
+                        height: Val::Px(20.0),
+                        padding: UiRect::all(Val::Px(2.0)),
+                        ..default()
+                    },
+                    background_color: Color::DARK_GRAY.into(),
+                    ..default()
+                }).with_children(|outline| {
+                    outline.spawn(NodeBundle {
+                        style: Style {
+                            width: Val::Percent(0.0),
+                            height: Val::Percent(100.0),
+                            ..default()
+                        },
+                        background_color: Color::WHITE.into(),
+                        ..default()
+                    }).insert(LoadingBar);
+                });
+            });
+    }
+
+    /**
+     * Updates the progress bar, and moves on to the main menu once the world is ready
+     */
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn track_loading(
+        mut commands: Commands,
+        asset_server: Res<AssetServer>,
+        loading: Res<LoadingAssets>,
+        cubemap: Res<Cubemap>,
+        unbuilt_scenes: Query<Option<&SceneSource>, With<MakeHitboxes>>,
+        pending_navmeshes: Res<PendingNavMeshes>,
+        players: Query<Entity, With<Controlling>>,
+        mut bars: Query<&mut Style, With<LoadingBar>>,
+        mut texts: Query<&mut Text, With<LoadingText>>,
+        mut next_state: ResMut<NextState<GameState>>,
+    ) {
+        let loaded_assets = loading.handles.iter()
+            // Don't get stuck on a missing file, the game can still run without it
+            .filter(|handle| matches!(asset_server.get_load_state(handle.id()), LoadState::Loaded | LoadState::Failed))
+            .count();
+        let skybox_ready = cubemap.is_loaded
+            || cubemap.source.as_ref().is_some_and(|source| asset_server.get_load_state(source.id()) == LoadState::Failed);
+        // Scenes that failed to load won't get colliders, but shouldn't hold the game up either
+        let scenes_ready = unbuilt_scenes.iter()
+            .all(|source| source.is_some_and(|source| asset_server.get_load_state(source.0.id()) == LoadState::Failed));
+        let colliders_ready = scenes_ready && pending_navmeshes.built;
+
+        let done = loaded_assets + skybox_ready as usize + colliders_ready as usize;
+        let total = loading.handles.len() + 2;
+        let progress = done as f32 / total as f32;
+
+        for mut style in &mut bars {
+            style.width = Val::Percent(100.0 * progress);
+        }
+        for mut text in &mut texts {
+            text.sections[0].value = if loaded_assets < loading.handles.len() {
+                format!("Loading assets... {:.0}%", 100.0 * progress)
+            } else {
+                format!("Building colliders... {:.0}%", 100.0 * progress)
+            };
+        }
+
+        if done == total {
+            info!("finished loading");
+            for player in &players {
+                commands.entity(player).remove::<RigidBodyDisabled>();
+            }
+            next_state.set(GameState::MainMenu);
+        }
+    }
+}
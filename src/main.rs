@@ -1,14 +1,43 @@
-use bevy::{prelude::*, window::CursorGrabMode};
-use bevy_kira_audio::{AudioControl, AudioPlugin};
+use bevy::prelude::*;
+use bevy_kira_audio::{AudioApp, AudioPlugin};
 use smooth_bevy_cameras::{LookTransform, LookTransformPlugin};
 use bevy_rapier3d::prelude::*;
 use std::env;
+use std::time::Duration;
+use bevy::asset::ChangeWatcher;
+use bevy::render::RenderPlugin;
+use bevy::asset::LoadState;
+use bevy::scene::{SceneInstance, SceneSpawner};
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 
+use game_state::game_state::GameState;
+
+mod audio;
+mod checkpoints;
+mod collectibles;
+mod collisions;
 mod decomp_caching;
+mod display;
+mod enemies;
+mod environment_map;
+mod game_state;
+mod hazards;
+mod hud;
+mod level;
+mod loading;
+mod menus;
+mod settings;
 mod setup_world;
 mod movement;
-mod skyboxv2;
+mod music;
+mod navmesh;
+mod particles;
+mod platforms;
+mod procgen;
+mod sky;
+mod skybox;
+mod speedrun;
+mod surfaces;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -16,96 +45,235 @@ fn main() {
         hitboxes: args.contains(&"hitboxes".to_string()),
         show_fps: args.contains(&"show_fps".to_string()) || args.contains(&"fps".to_string()),
         debug: args.contains(&"debug".to_string()),
+        level: args.iter().find_map(|arg| arg.strip_prefix("level=")).map(String::from),
+        seed: args.iter().find_map(|arg| arg.strip_prefix("seed=")).and_then(|seed| seed.parse().ok()),
+    };
+
+    let loaded_settings = settings::settings::load_settings();
+    let mut settings = loaded_settings.clone();
+    if conf.show_fps {
+        settings.hud.fps_graph = true;
+    }
+    display::display::apply_window_args(&mut settings, &args);
+    let overrides = settings::settings::CommandLineOverrides::new(loaded_settings, &settings);
+    let window = display::display::primary_window(&settings);
+    // Software rasterizers get particles as a plain mesh
+    let particle_instancing = settings.graphics_quality != settings::settings::GraphicsQuality::Software;
+
+    let level = level::level::load_level(conf.level.as_deref());
+    let collected = collectibles::collectibles::load_collected(&level.name);
+    let seed = match conf.seed.or(level.seed) {
+        Some(seed) => procgen::procgen::WorldSeed(seed),
+        None => procgen::procgen::WorldSeed::random(),
     };
+    println!("World seed: {} (pass seed={} to play this layout again)", seed.0, seed.0);
 
 
     let mut app = App::new();
 
     app 
-        .add_plugins(DefaultPlugins)
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(window),
+            ..default()
+        }).set(AssetPlugin {
+            // Lets the skybox be edited while the game runs
+            watch_for_changes: ChangeWatcher::with_delay(Duration::from_millis(200)),
+            ..default()
+        }).set(RenderPlugin {
+            wgpu_settings: settings.graphics_quality.wgpu_settings(),
+        }))
         .add_plugins(AudioPlugin)
         .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
         .add_plugins(LookTransformPlugin)
         
         .insert_resource(ClearColor(Color::rgb(0.,0.,0.)))
+        .insert_resource(level::level::CurrentLevel(level))
+        .insert_resource(seed)
+        .insert_resource(collected)
+        .insert_resource(game_state::game_state::GrabCursor(!conf.debug))
+        .insert_resource(settings)
+        .insert_resource(overrides)
+        .add_audio_channel::<audio::audio::Music>()
+        .add_audio_channel::<audio::audio::Sfx>()
+        .add_audio_channel::<audio::audio::Ui>()
+        .add_event::<audio::audio::PlaySound>()
+        .init_resource::<audio::audio::SoundLimiter>()
+        .add_systems(Startup, audio::audio::load_sound_bank)
+        .add_systems(Update, (
+            audio::audio::apply_audio_settings,
+            audio::audio::play_sounds,
+            audio::audio::start_ambient_sounds,
+            audio::audio::update_spatial_audio,
+        ).chain())
+        .init_resource::<music::music::MusicDirector>()
+        .add_systems(Startup, music::music::preload_soundtrack)
+        .add_systems(Update, music::music::direct_music)
+
+        .add_state::<GameState>()
+        .add_systems(Startup, (game_state::game_state::pause_physics, audio::audio::pause_sfx))
+        .init_resource::<loading::loading::LoadingAssets>()
+        .add_systems(OnEnter(GameState::Loading), loading::loading::setup_loading_screen)
+        .add_systems(Update, loading::loading::track_loading.run_if(in_state(GameState::Loading)))
+        .add_systems(OnExit(GameState::Loading), game_state::game_state::despawn_screen::<loading::loading::LoadingScreen>)
+        .add_systems(OnEnter(GameState::Playing), (game_state::game_state::resume_physics, game_state::game_state::grab_cursor, audio::audio::resume_sfx))
+        .add_systems(OnExit(GameState::Playing), (game_state::game_state::pause_physics, game_state::game_state::release_cursor, audio::audio::pause_sfx))
+        .add_systems(Update, (
+            game_state::game_state::pause_on_escape,
+            game_state::game_state::cursor_grab_system,
+        ).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, game_state::game_state::resume_on_escape.run_if(in_state(GameState::Paused)))
+
+        .add_systems(OnEnter(GameState::MainMenu), menus::menus::setup_main_menu)
+        .add_systems(OnExit(GameState::MainMenu), game_state::game_state::despawn_screen::<menus::menus::MainMenuScreen>)
+        .add_systems(OnEnter(GameState::Paused), menus::menus::setup_pause_menu)
+        .add_systems(OnExit(GameState::Paused), game_state::game_state::despawn_screen::<menus::menus::PauseScreen>)
+        .add_systems(Update, menus::menus::menu_buttons)
+        .add_systems(OnEnter(GameState::Settings), menus::menus::setup_settings_menu)
+        .add_systems(OnExit(GameState::Settings), (
+            game_state::game_state::despawn_screen::<menus::menus::SettingsScreen>,
+            settings::settings::save_settings,
+        ))
+        .add_systems(Update, (
+            menus::menus::setting_buttons,
+            menus::menus::setting_sliders,
+            menus::menus::update_setting_values,
+            game_state::game_state::back_to_pause_on_escape,
+        ).chain().run_if(in_state(GameState::Settings)))
+        .add_systems(Update, (
+            settings::settings::tag_shadow_casters,
+            settings::settings::apply_graphics_quality,
+            settings::settings::apply_graphics_settings,
+            settings::settings::limit_shadow_lights,
+        ).chain())
+        .insert_resource(display::display::LastFullscreenMode(settings::settings::DisplayMode::Borderless))
+        .add_systems(Update, (
+            display::display::toggle_fullscreen,
+            settings::settings::apply_window_settings,
+        ).chain())
         
         
         .add_systems(Startup, setup_world::setup_objects::setup)
-        .add_systems(Startup, spawn_gltf_objects)
-        .add_systems(Startup, start_background_audio)
+        .add_systems(Startup, level::level::spawn_level)
+        .add_systems(Startup, checkpoints::checkpoints::setup_respawn_fade)
 
         
-        .add_systems(Update, doing_the_wave)
+        .add_systems(Update, (
+            platforms::platforms::move_platforms,
+            platforms::platforms::carry_riders,
+        ).chain().run_if(in_state(GameState::Playing)))
+        .add_event::<collisions::collisions::PlayerTouched>()
+        .add_event::<collisions::collisions::PlayerStoppedTouching>()
+        .add_systems(Update, collisions::collisions::dispatch_collisions)
+
         .add_systems(Update, movement::movement::gamepad_connections)
-        .add_systems(Update, movement::movement::controls)
+        .add_event::<movement::movement::MovementEvent>()
+        .add_systems(Update, movement::movement::controls.after(collisions::collisions::dispatch_collisions).run_if(in_state(GameState::Playing)))
         .add_systems(Update, move_camera_system)
 
-        
-        .add_systems(Update, setup_world::setup_objects::point_things_at_player)
-
-        .add_systems(Update, move_scene_entities)
+        .add_event::<checkpoints::checkpoints::CheckpointReached>()
+        .add_event::<checkpoints::checkpoints::KillPlayer>()
+        .add_event::<checkpoints::checkpoints::PlayerRespawned>()
+        .add_systems(Update, (
+            checkpoints::checkpoints::checkpoint_collisions,
+            checkpoints::checkpoints::kill_plane,
+        ).chain().after(collisions::collisions::dispatch_collisions).run_if(in_state(GameState::Playing)))
+        // Not limited to playing, so restarting from the results screen can move the player back
+        .add_systems(Update, (
+            checkpoints::checkpoints::respawn_player,
+            checkpoints::checkpoints::fade_in,
+        ).chain().after(checkpoints::checkpoints::kill_plane))
+
+        .init_resource::<speedrun::speedrun::RunTimer>()
+        .add_event::<speedrun::speedrun::RunFinished>()
+        .add_systems(Startup, speedrun::speedrun::load_personal_best)
+        .add_systems(Update, (
+            speedrun::speedrun::start_run_timer,
+            speedrun::speedrun::tick_run_timer,
+            speedrun::speedrun::record_splits,
+            speedrun::speedrun::goal_collisions,
+        ).chain().after(checkpoints::checkpoints::checkpoint_collisions).run_if(in_state(GameState::Playing)))
+        .add_systems(OnEnter(GameState::Results), speedrun::speedrun::show_results)
+        .add_systems(Update, speedrun::speedrun::run_again_on_r.run_if(in_state(GameState::Results)))
+        .add_systems(OnExit(GameState::Results), (
+            game_state::game_state::despawn_screen::<speedrun::speedrun::ResultsScreen>,
+            speedrun::speedrun::restart_run,
+        ))
+
+        .add_event::<collectibles::collectibles::CollectiblePickedUp>()
+        .add_systems(Startup, collectibles::collectibles::setup_counter)
+        .add_systems(Update, (
+            collectibles::collectibles::tag_gltf_collectibles,
+            collectibles::collectibles::pick_up_collectibles.run_if(in_state(GameState::Playing)),
+            collectibles::collectibles::spin_collectibles,
+            collectibles::collectibles::update_counter,
+        ).chain().after(collisions::collisions::dispatch_collisions))
 
-        .add_plugins(bevy::diagnostic::LogDiagnosticsPlugin::default())
         
+        .add_systems(Update, (
+            hazards::hazards::fire_turrets,
+            setup_world::setup_objects::point_things_at_player,
+        ).chain().run_if(in_state(GameState::Playing)))
+        .add_systems(Update, hazards::hazards::expire_projectiles.after(collisions::collisions::dispatch_collisions).run_if(in_state(GameState::Playing)))
+        .add_systems(Update, surfaces::surfaces::apply_surface_physics)
+        .add_systems(Update, (
+            surfaces::surfaces::impact_sounds,
+            surfaces::surfaces::start_rolling_sounds,
+            surfaces::surfaces::update_rolling_sounds,
+        ).run_if(in_state(GameState::Playing)))
+
+        .init_resource::<navmesh::navmesh::PendingNavMeshes>()
+        .add_systems(Update, (
+            navmesh::navmesh::build_navmesh,
+            move_scene_entities,
+        ).chain())
+        .add_systems(Update, (
+            enemies::enemies::enemy_senses,
+            enemies::enemies::move_enemies,
+        ).chain().run_if(in_state(GameState::Playing)))
+
+        .add_plugins(bevy::diagnostic::FrameTimeDiagnosticsPlugin::default())
+        .init_resource::<hud::hud::FrameTimes>()
+        .add_systems(Startup, hud::hud::setup_hud)
+        .add_systems(Update, (
+            hud::hud::toggle_fps_graph,
+            hud::hud::apply_hud_settings,
+            hud::hud::update_fps_graph,
+            hud::hud::update_hud,
+        ))
         
-        .add_systems(
-            Update,
-            (
-                //skyboxv2::skyboxv2::cycle_cubemap_asset,
-                skyboxv2::skyboxv2::asset_loaded,
-                //skyboxv2::skyboxv2::camera_controller,
-                //skyboxv2::skyboxv2::animate_light_direction,
-            ),
-        );
         
-
-        //.add_plugins(MaterialPlugin::<skybox::skybox::CubemapMaterial>::default())
-        //.add_systems(Startup, skybox::skybox::setup_cubebox)
-        //.add_systems(Update, skybox::skybox::cycle_cubemap_asset)
-        //.add_systems(Update, skybox::skybox::asset_loaded.after(skybox::skybox::cycle_cubemap_asset));
+        .add_plugins(skybox::skybox::SkyboxPlugin::default())
+        .add_plugins(particles::particles::ParticlePlugin { instancing: particle_instancing })
+        .add_systems(Update, environment_map::environment_map::generate_environment_map)
+        .init_resource::<environment_map::environment_map::EnvironmentMapGeneration>()
+
+        .add_plugins(MaterialPlugin::<sky::sky::SkyMaterial>::default())
+        .register_type::<sky::sky::TimeOfDay>()
+        .add_systems(Startup, (sky::sky::load_sky_shader, sky::sky::setup_sky))
+        .add_systems(Update, (
+            sky::sky::advance_time_of_day.run_if(in_state(GameState::Playing)),
+            sky::sky::update_sky,
+        ).chain().run_if(resource_exists::<sky::sky::TimeOfDay>()));
 
     if conf.hitboxes{
         app.add_plugins(RapierDebugRenderPlugin::default());
     }
     if conf.show_fps{
-        app.add_plugins(bevy::diagnostic::FrameTimeDiagnosticsPlugin::default());
+        app.add_plugins(bevy::diagnostic::LogDiagnosticsPlugin::default());
     }
     if conf.debug{
         app.add_plugins(WorldInspectorPlugin::new());
-    } else {
-        app.add_systems(Update, cursor_grab_system);
     }
 
     app.run();
 }
-// struct that indicates that item will move as sin wave
-
-fn start_background_audio(asset_server: Res<AssetServer>, audio: Res<bevy_kira_audio::Audio>) {
-    //bevy_kira_audio::AudioControl::play(&audio, asset_server.load("Glimpsing-Infinity-Asher-Fulero.mp3")).looped().with_volume(0.25);
-    audio.play(asset_server.load("Glimpsing-Infinity-Asher-Fulero.mp3")).looped().with_volume(0.25);
-
-    
-    println!("playing audio")
-}
 
 struct CliArgs{
     hitboxes:bool,
     show_fps:bool,
     debug:bool,
-}
-
-fn doing_the_wave(time: Res<Time>, mut query: Query<&mut Transform, With<setup_world::setup_objects::Moving>>){
-
-    for mut transform in &mut query{
-        let x_pos:f32 = (time.elapsed_seconds() / 5.0).sin() as f32;
-        transform.rotate_x(0.3*time.delta_seconds());
-        //let forward = transform.forward();
-        transform.translation = Vec3{
-            x: x_pos,
-            y: 0.5,
-            z: 1.5
-        };
-    }
+    level:Option<String>,
+    seed:Option<u64>,
 }
 
 fn move_camera_system(mut cameras: Query<&mut LookTransform>, players: Query<(&mut Transform, &mut setup_world::setup_objects::ControllingButWithInfo), With<setup_world::setup_objects::Controlling>>) {
@@ -121,81 +289,49 @@ fn move_camera_system(mut cameras: Query<&mut LookTransform>, players: Query<(&m
 
 
 
-// Cursor crab function shamelessly stolen from bevy-cheatbook.github.io
-fn cursor_grab_system(
-    mut windows: Query<&mut Window>,
-    mouse: Res<Input<MouseButton>>,
-    key: Res<Input<KeyCode>>,
-) {
-    let window = windows.get_single_mut();
-    match window {
-        Ok(mut window) =>{
-            if mouse.just_pressed(MouseButton::Left) {
-                window.cursor.visible = false;
-                window.cursor.grab_mode = CursorGrabMode::Locked;
-            }
-        
-            if key.just_pressed(KeyCode::Escape) {
-                window.cursor.visible = true;
-                window.cursor.grab_mode = CursorGrabMode::None;
-            }
-        },
-        Err(err) => println!("Grab didn't work bc of {err}")
-    }
-    
-}
-
-
-
-
-
-
-
+// Marks a scene whose meshes still need colliders generated for them
 #[derive(Component)]
-struct MakeHitboxes;
-
-fn spawn_gltf_objects(
-    mut commands: Commands,
-    ass: Res<AssetServer>,
-) {
-    let gltf_h = ass.load("11-18-22_full_asembly_metallic_test.glb#Scene0");
-
-    let scene = SceneBundle {
-        scene: gltf_h,
-        ..Default::default()
-    };
-    commands.spawn(scene).insert(MakeHitboxes)
-       .insert(Transform::from_scale(Vec3{x:0.2,y:0.2,z:0.2}).with_translation(Vec3{x:6.0,y:-50.0,z:0.0}))
-       .insert(Name::new("World"));
-
-    /*for i in 0..1 {
-        let gltf_h2 = ass.load("claw.gltf#Scene0");
-        let scene2 = SceneBundle {
-            scene: gltf_h2,
-            ..Default::default()
-        };
-        commands.spawn(scene2).insert(MakeHitboxes)
-            .insert(Transform::from_xyz(3.0 * i as f32 - 15.0,0.0,3.0 * i as f32 - 20.0));
-    }*/
-}
+pub(crate) struct MakeHitboxes;
 
 
 
+#[allow(clippy::too_many_arguments)]
 fn move_scene_entities( 
-    mut moved_scene: Query<(Entity, &mut Transform),With<MakeHitboxes>>,
+    mut moved_scene: Query<(Entity, &mut Transform, Option<&level::level::SceneSource>, Option<&SceneInstance>),With<MakeHitboxes>>,
+    scene_spawner: Res<SceneSpawner>,
+    asset_server: Res<AssetServer>,
     children: Query<&Children>,
     mesh_handles: Query<&Handle<Mesh>>,
+    names: Query<&Name>,
+    material_handles: Query<&Handle<StandardMaterial>>,
     mut commands: Commands,
     assets: Res<Assets<Mesh>>,
+    gltfs: Res<Assets<bevy::gltf::Gltf>>,
+    mut pending_navmeshes: ResMut<navmesh::navmesh::PendingNavMeshes>,
 ) {
-    if moved_scene.iter().len() >=1{
+    // Scenes are only walked once they've been spawned, and then only once
+    let mut ready_scenes = vec![];
+    for (moved_scene_entity, _, source, instance) in &moved_scene {
+        if let Some(source) = source.filter(|source| asset_server.get_load_state(source.0.id()) == LoadState::Failed) {
+            println!("WARNING! SCENE {:?} FAILED TO LOAD, IT WON'T HAVE ANY COLLIDERS", asset_server.get_handle_path(&source.0));
+            commands.entity(moved_scene_entity).remove::<MakeHitboxes>();
+            continue
+        }
+        if instance.is_some_and(|instance| scene_spawner.instance_is_ready(**instance)) {
+            ready_scenes.push(moved_scene_entity);
+        }
+    }
+
+    if !ready_scenes.is_empty() {
         let mut cache = decomp_caching::decomp_caching::load_cache();
         let children = children.into();
         let mut decompositions: Vec<(Entity, decomp_caching::decomp_caching::RenderedDecomp)> = vec![];
 
         
 
-        for (moved_scene_entity, _)  in &moved_scene {
+        for &moved_scene_entity in &ready_scenes {
+            let (_, _, source, _) = moved_scene.get(moved_scene_entity).unwrap();
+            let gltf = source.and_then(|source| gltfs.get(&source.0));
             iter_hierarchy(moved_scene_entity, &children, &mut {
                 
                 |entity| {
@@ -209,8 +345,16 @@ fn move_scene_entities(
                     
                 }*/
 
+                // Pickups only have their sensor, the player shouldn't bump into them or walk on them
+                if names.get(entity).is_ok_and(|name| collectibles::collectibles::is_collectible_node(name.as_str())) {
+                    return false
+                }
+
                 if let Ok(mesh_handle) = mesh_handles.get(entity) {
-                    let mesh = assets.get(mesh_handle).expect("Couldn't get mesh from handle");
+                    let Some(mesh) = assets.get(mesh_handle) else {
+                        println!("WARNING! COULDN'T GET MESH {:?}, IT WON'T HAVE A COLLIDER", mesh_handle);
+                        return true
+                    };
                     info!("meshing");
 
                     let mesh_collider = Collider::from_bevy_mesh(mesh,
@@ -223,6 +367,8 @@ fn move_scene_entities(
                     let vertices = trimesh.vertices().collect::<Vec<bevy::prelude::Vec3>>();
                     let indices = trimesh.indices();
 
+                    // Enemies walk on the same geometry the player collides with
+                    pending_navmeshes.meshes.push((entity, vertices.clone(), indices.to_vec()));
                     
 
 
@@ -231,17 +377,23 @@ fn move_scene_entities(
                         Some(decomp) => decompositions.push((entity,decomp)),
                         None => println!("couldn't decompose shape"),
                     }
+
+                    // Materials named after a surface ("Ice_floor") make the mesh that surface
+                    let material_name = material_handles.get(entity).ok().zip(gltf).and_then(|(material, gltf)| {
+                        gltf.named_materials.iter().find(|(_, handle)| *handle == material).map(|(name, _)| name)
+                    });
+                    if let Some(surface) = material_name.and_then(|name| surfaces::surfaces::SurfaceMaterial::from_material_name(name)) {
+                        commands.entity(entity).insert(surface);
+                    }
                     
 
                     //commands.entity(entity).insert(decomposition);
-
-                    //println!("removing entity {:?}",moved_scene_entity.id());
-                    //let x = commands.entity(moved_scene_entity.clone());
-                    commands.entity(moved_scene_entity.clone()).remove::<MakeHitboxes>();
                 }
-                
+                true
             }});
-            
+
+            // Even scenes without any meshes are done now
+            commands.entity(moved_scene_entity).remove::<MakeHitboxes>();
         }
 
         for (entity, rendered_decomp) in decompositions {
@@ -253,8 +405,10 @@ fn move_scene_entities(
         //This is a raelly horrible workaround for a glitch in Rapier
         //When the mesh is loaded, it doesn't scale correctly until its updated
         //So this forces an update for the object by moving it a very small amount
-        for (_, mut transform) in &mut moved_scene{
-            transform.translation.z += 0.1;
+        for &moved_scene_entity in &ready_scenes {
+            if let Ok((_, mut transform, _, _)) = moved_scene.get_mut(moved_scene_entity) {
+                transform.translation.z += 0.1;
+            }
         }
     }
     
@@ -263,8 +417,11 @@ fn move_scene_entities(
     
 }
 
-fn iter_hierarchy(entity: Entity, children_query: &Query<&Children>, f: &mut impl FnMut(Entity)) {
-    (f)(entity);
+/// Calls `f` on `entity` and its descendants. Children are skipped when `f` returns false.
+fn iter_hierarchy(entity: Entity, children_query: &Query<&Children>, f: &mut impl FnMut(Entity) -> bool) {
+    if !(f)(entity) {
+        return
+    }
     if let Ok(children) = children_query.get(entity) {
         for child in children.iter().copied() {
             iter_hierarchy(child, children_query, f);
@@ -0,0 +1,564 @@
+pub(crate) mod menus {
+    //! The main menu, pause menu and settings menu, and the buttons shared with the results screen.
+    //!
+    //! Each screen is spawned when its [`GameState`] is entered and despawned when it's left.
+    //! Buttons carry a [`MenuAction`] that `menu_buttons` carries out when they're clicked.
+    //! Settings controls change [`Settings`] directly, and the settings systems apply the change
+    //! straight away.
+
+    use bevy::app::AppExit;
+    use bevy::prelude::*;
+    use bevy::ui::RelativeCursorPosition;
+
+    use crate::audio::audio::{PlaySound, Sound};
+    use crate::game_state::game_state::GameState;
+    use crate::settings::settings::{next_resolution, GraphicsQuality, Settings};
+
+    const NORMAL_BUTTON:Color = Color::rgb(0.15, 0.15, 0.15);
+    const HOVERED_BUTTON:Color = Color::rgb(0.25, 0.25, 0.25);
+    const PRESSED_BUTTON:Color = Color::rgb(0.35, 0.75, 0.35);
+
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) enum MenuAction {
+        Play,
+        Resume,
+        RunAgain,
+        Settings,
+        /// Back from the settings menu to the pause menu
+        Back,
+        MainMenu,
+        Quit,
+    }
+
+    #[derive(Component)]
+    pub(crate) struct MenuButton(pub(crate) MenuAction);
+
+    #[derive(Component)]
+    pub(crate) struct MainMenuScreen;
+
+    #[derive(Component)]
+    pub(crate) struct PauseScreen;
+
+    #[derive(Component)]
+    pub(crate) struct SettingsScreen;
+
+    /// One row of the settings menu
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub(crate) enum SettingControl {
+        MasterVolume,
+        MusicVolume,
+        SfxVolume,
+        UiVolume,
+        MuteMusic,
+        MuteSfx,
+        MuteUi,
+        MouseSensitivity,
+        StickSensitivity,
+        InvertY,
+        Fov,
+        GraphicsQuality,
+        Bloom,
+        BloomIntensity,
+        Shadows,
+        Msaa,
+        Hdr,
+        PresentMode,
+        DisplayMode,
+        Resolution,
+        HudFpsGraph,
+        HudSpeed,
+        HudTimer,
+        HudCollectibles,
+        HudDash,
+    }
+
+    /** The settings menu's columns, each a list of titled groups, laid out side by side so the
+     * whole menu fits on a 720p screen */
+    const SETTING_COLUMNS: &[&[(&str, &[SettingControl])]] = &[
+        &[
+            ("Audio", &[
+                SettingControl::MasterVolume,
+                SettingControl::MusicVolume,
+                SettingControl::SfxVolume,
+                SettingControl::UiVolume,
+                SettingControl::MuteMusic,
+                SettingControl::MuteSfx,
+                SettingControl::MuteUi,
+            ]),
+            ("Controls", &[
+                SettingControl::MouseSensitivity,
+                SettingControl::StickSensitivity,
+                SettingControl::InvertY,
+            ]),
+        ],
+        &[
+            ("Graphics", &[
+                SettingControl::GraphicsQuality,
+                SettingControl::Fov,
+                SettingControl::Bloom,
+                SettingControl::BloomIntensity,
+                SettingControl::Shadows,
+                SettingControl::Msaa,
+                SettingControl::Hdr,
+            ]),
+        ],
+        &[
+            ("Window", &[
+                SettingControl::PresentMode,
+                SettingControl::DisplayMode,
+                SettingControl::Resolution,
+            ]),
+            ("HUD", &[
+                SettingControl::HudFpsGraph,
+                SettingControl::HudSpeed,
+                SettingControl::HudTimer,
+                SettingControl::HudCollectibles,
+                SettingControl::HudDash,
+            ]),
+        ],
+    ];
+
+    impl SettingControl {
+        fn label(&self) -> &'static str {
+            match self {
+                SettingControl::MasterVolume => "Master volume",
+                SettingControl::MusicVolume => "Music volume",
+                SettingControl::SfxVolume => "Effects volume",
+                SettingControl::UiVolume => "Menu volume",
+                SettingControl::MuteMusic => "Mute music",
+                SettingControl::MuteSfx => "Mute effects",
+                SettingControl::MuteUi => "Mute menus",
+                SettingControl::MouseSensitivity => "Mouse sensitivity",
+                SettingControl::StickSensitivity => "Stick sensitivity",
+                SettingControl::InvertY => "Invert Y",
+                SettingControl::Fov => "Field of view",
+                SettingControl::GraphicsQuality => "Graphics quality",
+                SettingControl::Bloom => "Bloom",
+                SettingControl::BloomIntensity => "Bloom intensity",
+                SettingControl::Shadows => "Shadows",
+                SettingControl::Msaa => "Anti-aliasing",
+                SettingControl::Hdr => "HDR",
+                SettingControl::PresentMode => "Present mode",
+                SettingControl::DisplayMode => "Window mode",
+                SettingControl::Resolution => "Resolution",
+                SettingControl::HudFpsGraph => "Show frame times",
+                SettingControl::HudSpeed => "Show speed",
+                SettingControl::HudTimer => "Show timer",
+                SettingControl::HudCollectibles => "Show collectibles",
+                SettingControl::HudDash => "Show dash indicator",
+            }
+        }
+
+        /// Minimum and maximum, for the controls that are sliders
+        fn range(&self) -> Option<(f32, f32)> {
+            match self {
+                SettingControl::MasterVolume | SettingControl::MusicVolume | SettingControl::SfxVolume | SettingControl::UiVolume => Some((0.0, 1.0)),
+                SettingControl::MouseSensitivity | SettingControl::StickSensitivity => Some((0.1, 3.0)),
+                SettingControl::Fov => Some((30.0, 110.0)),
+                SettingControl::BloomIntensity => Some((0.0, 1.0)),
+                _ => None,
+            }
+        }
+
+        fn slider_value<'a>(&self, settings: &'a mut Settings) -> Option<&'a mut f32> {
+            match self {
+                SettingControl::MasterVolume => Some(&mut settings.master_volume),
+                SettingControl::MusicVolume => Some(&mut settings.music_volume),
+                SettingControl::SfxVolume => Some(&mut settings.sfx_volume),
+                SettingControl::UiVolume => Some(&mut settings.ui_volume),
+                SettingControl::MouseSensitivity => Some(&mut settings.mouse_sensitivity),
+                SettingControl::StickSensitivity => Some(&mut settings.stick_sensitivity),
+                SettingControl::Fov => Some(&mut settings.fov),
+                SettingControl::BloomIntensity => Some(&mut settings.bloom_intensity),
+                _ => None,
+            }
+        }
+
+        fn current_slider_value(&self, settings: &Settings) -> Option<f32> {
+            match self {
+                SettingControl::MasterVolume => Some(settings.master_volume),
+                SettingControl::MusicVolume => Some(settings.music_volume),
+                SettingControl::SfxVolume => Some(settings.sfx_volume),
+                SettingControl::UiVolume => Some(settings.ui_volume),
+                SettingControl::MouseSensitivity => Some(settings.mouse_sensitivity),
+                SettingControl::StickSensitivity => Some(settings.stick_sensitivity),
+                SettingControl::Fov => Some(settings.fov),
+                SettingControl::BloomIntensity => Some(settings.bloom_intensity),
+                _ => None,
+            }
+        }
+
+        fn flag<'a>(&self, settings: &'a mut Settings) -> Option<&'a mut bool> {
+            match self {
+                SettingControl::MuteMusic => Some(&mut settings.music_muted),
+                SettingControl::MuteSfx => Some(&mut settings.sfx_muted),
+                SettingControl::MuteUi => Some(&mut settings.ui_muted),
+                SettingControl::InvertY => Some(&mut settings.invert_y),
+                SettingControl::Bloom => Some(&mut settings.bloom),
+                SettingControl::Shadows => Some(&mut settings.shadows),
+                SettingControl::Hdr => Some(&mut settings.hdr),
+                SettingControl::HudFpsGraph => Some(&mut settings.hud.fps_graph),
+                SettingControl::HudSpeed => Some(&mut settings.hud.speed),
+                SettingControl::HudTimer => Some(&mut settings.hud.timer),
+                SettingControl::HudCollectibles => Some(&mut settings.hud.collectibles),
+                SettingControl::HudDash => Some(&mut settings.hud.dash),
+                _ => None,
+            }
+        }
+
+        fn current_flag(&self, settings: &Settings) -> Option<bool> {
+            match self {
+                SettingControl::MuteMusic => Some(settings.music_muted),
+                SettingControl::MuteSfx => Some(settings.sfx_muted),
+                SettingControl::MuteUi => Some(settings.ui_muted),
+                SettingControl::InvertY => Some(settings.invert_y),
+                SettingControl::Bloom => Some(settings.bloom),
+                SettingControl::Shadows => Some(settings.shadows),
+                SettingControl::Hdr => Some(settings.hdr),
+                SettingControl::HudFpsGraph => Some(settings.hud.fps_graph),
+                SettingControl::HudSpeed => Some(settings.hud.speed),
+                SettingControl::HudTimer => Some(settings.hud.timer),
+                SettingControl::HudCollectibles => Some(settings.hud.collectibles),
+                SettingControl::HudDash => Some(settings.hud.dash),
+                _ => None,
+            }
+        }
+
+        /// Whether changing this moves the graphics quality off its preset
+        fn is_graphics_preset_part(&self) -> bool {
+            matches!(self, SettingControl::Bloom | SettingControl::Shadows | SettingControl::Msaa | SettingControl::Hdr)
+        }
+
+        /// Flips a toggle, or moves a choice on to the next option
+        fn press(&self, settings: &mut Settings) {
+            if self.is_graphics_preset_part() {
+                settings.graphics_quality = GraphicsQuality::Custom;
+            }
+            if let Some(flag) = self.flag(settings) {
+                *flag = !*flag;
+                return
+            }
+            match self {
+                SettingControl::GraphicsQuality => settings.graphics_quality = settings.graphics_quality.next(),
+                SettingControl::Msaa => settings.msaa_samples = if settings.msaa_samples > 1 { 1 } else { 4 },
+                SettingControl::PresentMode => settings.present_mode = settings.present_mode.next(),
+                SettingControl::DisplayMode => settings.display_mode = settings.display_mode.next(),
+                SettingControl::Resolution => settings.resolution = next_resolution(settings.resolution),
+                _ => {},
+            }
+        }
+
+        fn value_text(&self, settings: &Settings) -> String {
+            if let Some(flag) = self.current_flag(settings) {
+                return if flag { "On".to_string() } else { "Off".to_string() }
+            }
+            match self {
+                SettingControl::Fov => format!("{:.0}°", settings.fov),
+                SettingControl::GraphicsQuality => format!("{:?}", settings.graphics_quality),
+                SettingControl::Msaa if settings.msaa_samples > 1 => format!("{}x MSAA", settings.msaa_samples),
+                SettingControl::Msaa => "Off".to_string(),
+                SettingControl::PresentMode => format!("{:?}", settings.present_mode),
+                SettingControl::DisplayMode => format!("{:?}", settings.display_mode),
+                SettingControl::Resolution => format!("{}x{}", settings.resolution.0, settings.resolution.1),
+                control => match control.current_slider_value(settings) {
+                    Some(value) => format!("{:.0}%", 100.0 * value),
+                    None => String::new(),
+                },
+            }
+        }
+
+        /// How far along its slider the value is, from 0 to 1
+        fn slider_fraction(&self, settings: &Settings) -> f32 {
+            let Some((min, max)) = self.range() else { return 0.0 };
+            match self.current_slider_value(settings) {
+                Some(value) => ((value - min) / (max - min)).clamp(0.0, 1.0),
+                None => 0.0,
+            }
+        }
+    }
+
+    /// Slider for a setting. Clicking or dragging along it sets the value.
+    #[derive(Component)]
+    pub(crate) struct SettingSlider(SettingControl);
+
+    #[derive(Component)]
+    pub(crate) struct SettingSliderFill(SettingControl);
+
+    /// Button that toggles a setting, or cycles through its options
+    #[derive(Component)]
+    pub(crate) struct SettingButton(SettingControl);
+
+    #[derive(Component)]
+    pub(crate) struct SettingValueText(SettingControl);
+
+    /**
+     * Full screen node that menu contents are placed in, centered in a column
+     */
+    pub(crate) fn screen_root(background: Color) -> NodeBundle {
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(8.0),
+                ..default()
+            },
+            background_color: background.into(),
+            z_index: ZIndex::Global(10),
+            ..default()
+        }
+    }
+
+    pub(crate) fn spawn_title(parent: &mut ChildBuilder, title: &str) {
+        parent.spawn(TextBundle::from_section(title, TextStyle {
+            font_size: 64.0,
+            color: Color::WHITE,
+            ..default()
+        }).with_style(Style {
+            margin: UiRect::bottom(Val::Px(24.0)),
+            ..default()
+        }));
+    }
+
+    pub(crate) fn spawn_button(parent: &mut ChildBuilder, label: &str, action: MenuAction) {
+        parent.spawn(ButtonBundle {
+            style: Style {
+                width: Val::Px(260.0),
+                height: Val::Px(56.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            background_color: NORMAL_BUTTON.into(),
+            ..default()
+        })  .insert(MenuButton(action))
+            .with_children(|button| {
+                button.spawn(TextBundle::from_section(label, TextStyle {
+                    font_size: 32.0,
+                    color: Color::WHITE,
+                    ..default()
+                }));
+            });
+    }
+
+    pub(crate) fn setup_main_menu(mut commands: Commands) {
+        commands.spawn(screen_root(Color::rgba(0.0, 0.0, 0.0, 0.8)))
+            .insert(MainMenuScreen)
+            .insert(Name::new("Main menu"))
+            .with_children(|parent| {
+                spawn_title(parent, "Rust Game");
+                spawn_button(parent, "Play", MenuAction::Play);
+                spawn_button(parent, "Quit", MenuAction::Quit);
+            });
+    }
+
+    pub(crate) fn setup_pause_menu(mut commands: Commands) {
+        commands.spawn(screen_root(Color::rgba(0.0, 0.0, 0.0, 0.6)))
+            .insert(PauseScreen)
+            .insert(Name::new("Pause menu"))
+            .with_children(|parent| {
+                spawn_title(parent, "Paused");
+                spawn_button(parent, "Resume", MenuAction::Resume);
+                spawn_button(parent, "Settings", MenuAction::Settings);
+                spawn_button(parent, "Main menu", MenuAction::MainMenu);
+                spawn_button(parent, "Quit", MenuAction::Quit);
+            });
+    }
+
+    pub(crate) fn menu_buttons(
+        mut buttons: Query<(&Interaction, &MenuButton, &mut BackgroundColor), Changed<Interaction>>,
+        mut next_state: ResMut<NextState<GameState>>,
+        mut exit: EventWriter<AppExit>,
+        mut play_sound: EventWriter<PlaySound>,
+    ) {
+        for (interaction, button, mut background) in &mut buttons {
+            match interaction {
+                Interaction::Pressed => {
+                    background.0 = PRESSED_BUTTON;
+                    play_sound.send(PlaySound::new(Sound::Click));
+                    match button.0 {
+                        // Leaving the results screen resets the run, so running again is just playing
+                        MenuAction::Play | MenuAction::Resume | MenuAction::RunAgain => next_state.set(GameState::Playing),
+                        MenuAction::Settings => next_state.set(GameState::Settings),
+                        MenuAction::Back => next_state.set(GameState::Paused),
+                        MenuAction::MainMenu => next_state.set(GameState::MainMenu),
+                        MenuAction::Quit => exit.send(AppExit),
+                    }
+                },
+                Interaction::Hovered => background.0 = HOVERED_BUTTON,
+                Interaction::None => background.0 = NORMAL_BUTTON,
+            }
+        }
+    }
+
+    pub(crate) fn setup_settings_menu(mut commands: Commands, settings: Res<Settings>) {
+        let text_style = TextStyle {
+            font_size: 20.0,
+            color: Color::WHITE,
+            ..default()
+        };
+
+        commands.spawn(screen_root(Color::rgba(0.0, 0.0, 0.0, 0.8)))
+            .insert(SettingsScreen)
+            .insert(Name::new("Settings menu"))
+            .with_children(|parent| {
+                parent.spawn(TextBundle::from_section("Settings", TextStyle {
+                    font_size: 40.0,
+                    color: Color::WHITE,
+                    ..default()
+                }));
+
+                parent.spawn(NodeBundle {
+                    style: Style {
+                        align_items: AlignItems::FlexStart,
+                        column_gap: Val::Px(24.0),
+                        ..default()
+                    },
+                    ..default()
+                }).with_children(|columns| {
+                    for &groups in SETTING_COLUMNS {
+                        columns.spawn(NodeBundle {
+                            style: Style {
+                                flex_direction: FlexDirection::Column,
+                                row_gap: Val::Px(6.0),
+                                ..default()
+                            },
+                            ..default()
+                        }).with_children(|column| {
+                            for &(title, controls) in groups {
+                                column.spawn(TextBundle::from_section(title, TextStyle {
+                                    font_size: 28.0,
+                                    color: Color::WHITE,
+                                    ..default()
+                                }).with_style(Style {
+                                    margin: UiRect::top(Val::Px(8.0)),
+                                    ..default()
+                                }));
+                                for &control in controls {
+                                    spawn_setting_row(column, control, &settings, &text_style);
+                                }
+                            }
+                        });
+                    }
+                });
+
+                spawn_button(parent, "Back", MenuAction::Back);
+            });
+    }
+
+    fn spawn_setting_row(parent: &mut ChildBuilder, control: SettingControl, settings: &Settings, text_style: &TextStyle) {
+        parent.spawn(NodeBundle {
+            style: Style {
+                align_items: AlignItems::Center,
+                column_gap: Val::Px(12.0),
+                ..default()
+            },
+            ..default()
+        }).with_children(|row| {
+            row.spawn(TextBundle::from_section(control.label(), text_style.clone()).with_style(Style {
+                width: Val::Px(170.0),
+                ..default()
+            }));
+
+            let control_style = Style {
+                width: Val::Px(120.0),
+                height: Val::Px(24.0),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            };
+
+            if control.range().is_some() {
+                row.spawn(ButtonBundle {
+                    style: Style {
+                        justify_content: JustifyContent::FlexStart,
+                        ..control_style
+                    },
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                })  .insert(SettingSlider(control))
+                    .insert(RelativeCursorPosition::default())
+                    .with_children(|slider| {
+                        slider.spawn(NodeBundle {
+                            style: Style {
+                                width: Val::Percent(100.0 * control.slider_fraction(settings)),
+                                height: Val::Percent(100.0),
+                                ..default()
+                            },
+                            background_color: PRESSED_BUTTON.into(),
+                            ..default()
+                        }).insert(SettingSliderFill(control));
+                    });
+            } else {
+                row.spawn(ButtonBundle {
+                    style: control_style,
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                }).insert(SettingButton(control));
+            }
+
+            row.spawn(TextBundle::from_section(control.value_text(settings), text_style.clone()).with_style(Style {
+                width: Val::Px(90.0),
+                ..default()
+            })).insert(SettingValueText(control));
+        });
+    }
+
+    pub(crate) fn setting_buttons(
+        mut buttons: Query<(&Interaction, &SettingButton, &mut BackgroundColor), Changed<Interaction>>,
+        mut settings: ResMut<Settings>,
+        mut play_sound: EventWriter<PlaySound>,
+    ) {
+        for (interaction, button, mut background) in &mut buttons {
+            match interaction {
+                Interaction::Pressed => {
+                    background.0 = PRESSED_BUTTON;
+                    play_sound.send(PlaySound::new(Sound::Click));
+                    button.0.press(&mut settings);
+                },
+                Interaction::Hovered => background.0 = HOVERED_BUTTON,
+                Interaction::None => background.0 = NORMAL_BUTTON,
+            }
+        }
+    }
+
+    pub(crate) fn setting_sliders(
+        sliders: Query<(&Interaction, &SettingSlider, &RelativeCursorPosition)>,
+        mut settings: ResMut<Settings>,
+    ) {
+        for (interaction, slider, cursor) in &sliders {
+            // Keeps following the cursor for as long as the button is held
+            if *interaction != Interaction::Pressed {
+                continue
+            }
+            let (Some((min, max)), Some(position)) = (slider.0.range(), cursor.normalized) else { continue };
+
+            let value = min + (max - min) * position.x.clamp(0.0, 1.0);
+            if let Some(setting) = slider.0.slider_value(settings.bypass_change_detection()) {
+                if *setting != value {
+                    *setting = value;
+                    settings.set_changed();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn update_setting_values(
+        settings: Res<Settings>,
+        mut fills: Query<(&SettingSliderFill, &mut Style)>,
+        mut texts: Query<(&SettingValueText, &mut Text)>,
+    ) {
+        if !settings.is_changed() {
+            return
+        }
+        for (fill, mut style) in &mut fills {
+            style.width = Val::Percent(100.0 * fill.0.slider_fraction(&settings));
+        }
+        for (value, mut text) in &mut texts {
+            text.sections[0].value = value.0.value_text(&settings);
+        }
+    }
+}
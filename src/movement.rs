@@ -1,35 +1,50 @@
 pub(crate) mod movement {
-    const PLAYER_SPEED:f32 = 2.5;
+    pub(crate) const PLAYER_SPEED:f32 = 2.5;
+    pub(crate) const JUMP_VELOCITY:f32 = 10.0;
+    pub(crate) const DASH_MULTIPLIER:f32 = 30.0;
     const DEADZONE:f32 = 0.10;
 
     use bevy::input::gamepad::GamepadConnection;
     use bevy::input::mouse::MouseMotion;
     use bevy::prelude::*;
-    use bevy_kira_audio::AudioControl;
     use bevy_rapier3d::prelude::*;
     //use bevy_kira_audio::prelude::*;
 
+    use crate::audio::audio::{PlaySound, Sound};
+    use crate::collisions::collisions::{CollisionRole, PlayerTouched, PlayerStoppedTouching};
+    use crate::settings::settings::Settings;
     use crate::setup_world;
 
+    /// Something the player just did, for effects to react to
+    #[derive(Event, Clone, Copy, Debug)]
+    pub(crate) enum MovementEvent {
+        Jumped { position: Vec3 },
+        Dashed { position: Vec3, direction: Vec3 },
+        /// Came down on solid ground, falling at `speed`
+        Landed { position: Vec3, speed: f32 },
+    }
+
     pub(crate) fn controls (
             time: Res<Time>,
-            mut query: Query<(&mut setup_world::setup_objects::ControllingButWithInfo, &mut Velocity, Entity, &mut Transform), With<setup_world::setup_objects::Controlling>>,
+            mut query: Query<(&mut setup_world::setup_objects::ControllingButWithInfo, &mut Velocity, Entity, &Transform), With<setup_world::setup_objects::Controlling>>,
             keyboard_input: Res<Input<KeyCode>>,
             mut motion_evr: EventReader<MouseMotion>,
             axes: Res<Axis<GamepadAxis>>,
             buttons: Res<Input<GamepadButton>>,
             my_gamepad: Option<Res<MyGamepad>>,
-            mut collision_events: EventReader<CollisionEvent>,
+            mut touched: EventReader<PlayerTouched>,
+            mut stopped_touching: EventReader<PlayerStoppedTouching>,
             rapier_context: Res<RapierContext>,
-            asset_server: Res<AssetServer>,
-            audio: Res<bevy_kira_audio::Audio>,
+            mut play_sound: EventWriter<PlaySound>,
+            mut movement_events: EventWriter<MovementEvent>,
+            // Physics has already stopped the fall by the time the touch is reported
+            mut last_fall_speed: Local<f32>,
+            settings: Res<Settings>,
             ){
 
-        for  (mut player_info, mut velocity, entity, mut transform) in &mut query{
+        let invert_y = if settings.invert_y { -1.0 } else { 1.0 };
 
-            if transform.translation.y <= -100.0 {
-                transform.translation = Vec3::new(0.0, 5.5, 0.0);
-            }
+        for  (mut player_info, mut velocity, entity, transform) in &mut query{
 
             // slowing down
             velocity.linvel.x = velocity.linvel.x/2.0;
@@ -42,19 +57,16 @@ pub(crate) mod movement {
             let mut dashing = false;
 
 
-            if collision_events.len() >= 1 {
+            // Only solid ground counts for jumping. Checkpoints, pickups and hazards are handled elsewhere
+            for _ in touched.iter().filter(|touch| touch.player == entity && touch.kind == CollisionRole::Platform) {
+                if player_info.objects_hit == 0 {
+                    movement_events.send(MovementEvent::Landed { position: transform.translation, speed: *last_fall_speed });
+                }
                 player_info.has_hit_object = true;
+                player_info.objects_hit+=1;
             }
-            for i in collision_events.iter() {
-                match i {
-                    CollisionEvent::Started(_, _, _) => {
-                        player_info.objects_hit+=1;
-                        let _ = audio.play(asset_server.load("thump.wav"));
-                    },
-                    CollisionEvent::Stopped(_,_, _) => {
-                        player_info.objects_hit-=1;
-                    },
-                }
+            for _ in stopped_touching.iter().filter(|touch| touch.player == entity && touch.kind == CollisionRole::Platform) {
+                player_info.objects_hit-=1;
             }
 
 
@@ -118,15 +130,17 @@ pub(crate) mod movement {
                     };
                     let c_x = axes.get(axis_rx).unwrap_or(0.0_f32);
 
-                    player_info.theta -= 2.0 * c_x * time.delta_seconds();
+                    player_info.theta -= 2.0 * settings.stick_sensitivity * c_x * time.delta_seconds();
 
                     //vertical
                     let axis_ry = GamepadAxis {
                         gamepad, axis_type: GamepadAxisType::RightStickY
                     };
-                    let c_y = axes.get(axis_ry).unwrap_or(0.0_f32);
+                    let raw_y = axes.get(axis_ry).unwrap_or(0.0_f32);
+                    // The deadzone is on the stick itself, so sensitivity doesn't change how far it has to move
+                    let c_y = invert_y * settings.stick_sensitivity * raw_y;
                     
-                    if c_y.abs() >= DEADZONE{
+                    if raw_y.abs() >= DEADZONE{
                         if c_y> 0.0 && player_info.v_theta < 0.45*std::f32::consts::PI {
                             player_info.v_theta +=  2.0 * c_y * time.delta_seconds();
                         }
@@ -141,9 +155,13 @@ pub(crate) mod movement {
                     };
         
                     if buttons.pressed(jump_button) && player_info.objects_hit >=1{
-                        velocity.linvel.y = 10.0;
+                        velocity.linvel.y = JUMP_VELOCITY;
+                        // Holding jump keeps jumping until the player leaves the ground, but only the first one counts
+                        if player_info.has_hit_object {
+                            movement_events.send(MovementEvent::Jumped { position: transform.translation });
+                        }
                         player_info.has_hit_object = false;
-                        let _ = audio.play(asset_server.load("jumping.wav"));
+                        play_sound.send(PlaySound::at(Sound::Jump, transform.translation));
                     }
 
                     // Fast fall
@@ -216,9 +234,12 @@ pub(crate) mod movement {
             }
 
             if keyboard_input.pressed(KeyCode::Space) && player_info.objects_hit >=1{
-                velocity.linvel.y = 10.0;
+                velocity.linvel.y = JUMP_VELOCITY;
+                if player_info.has_hit_object {
+                    movement_events.send(MovementEvent::Jumped { position: transform.translation });
+                }
                 player_info.has_hit_object = false;
-                let _ = audio.play(asset_server.load("jumping.wav"));
+                play_sound.send(PlaySound::at(Sound::Jump, transform.translation));
             
             }
 
@@ -229,18 +250,24 @@ pub(crate) mod movement {
             }
 
             // Dashing
+            player_info.can_dash = moving;
             if moving && (keyboard_input.just_pressed(KeyCode::Q) || dashing){
-                velocity.linvel = velocity.linvel + Vec3{ x: PLAYER_SPEED*input_theta.cos()*speed * 30.0, y: 0.0, z: -PLAYER_SPEED*input_theta.sin()*speed  * 30.0};
+                velocity.linvel = velocity.linvel + Vec3{ x: PLAYER_SPEED*input_theta.cos()*speed * DASH_MULTIPLIER, y: 0.0, z: -PLAYER_SPEED*input_theta.sin()*speed  * DASH_MULTIPLIER};
+                movement_events.send(MovementEvent::Dashed {
+                    position: transform.translation,
+                    direction: Vec3::new(input_theta.cos(), 0.0, -input_theta.sin()),
+                });
             }
 
             for ev in motion_evr.iter() {
-                player_info.theta -= 0.001 * ev.delta.x;
-                if ev.delta.y < 0.0 && player_info.v_theta <= 0.45*std::f32::consts::PI {
-                    player_info.v_theta -= 0.01 * ev.delta.y;
+                let delta = ev.delta * settings.mouse_sensitivity * Vec2::new(1.0, invert_y);
+                player_info.theta -= 0.001 * delta.x;
+                if delta.y < 0.0 && player_info.v_theta <= 0.45*std::f32::consts::PI {
+                    player_info.v_theta -= 0.01 * delta.y;
                 }
 
-                if ev.delta.y > 0.0 && player_info.v_theta >= 0.05*std::f32::consts::PI {
-                    player_info.v_theta -= 0.01 * ev.delta.y;
+                if delta.y > 0.0 && player_info.v_theta >= 0.05*std::f32::consts::PI {
+                    player_info.v_theta -= 0.01 * delta.y;
                 }
             }
 
@@ -253,6 +280,8 @@ pub(crate) mod movement {
             if player_info.v_theta > 0.45*std::f32::consts::PI{
                 player_info.v_theta = 0.45*std::f32::consts::PI;
             }
+
+            *last_fall_speed = (-velocity.linvel.y).max(0.0);
    
         }
 
@@ -0,0 +1,333 @@
+pub(crate) mod music {
+    //! The music director: which track plays, and how loud.
+    //!
+    //! Each level has a [`Soundtrack`], with a playlist for the menus, one for gameplay and one for
+    //! the results screen. When the game state moves to a different playlist, or the current track
+    //! is about to end, the next track fades in while the old one fades out. The fades are kira
+    //! tweens on the instances' start and stop, so they don't fight with the volume changes below.
+    //!
+    //! On top of the fades, every instance's volume is set from the music settings, ducked while an
+    //! important sound effect plays, and for stems, from how fast the player is going. Stems are
+    //! extra layers of the same track (drums, a lead...) that start with it in sync but silent.
+
+    use std::time::Duration;
+    use bevy::prelude::*;
+    use bevy_kira_audio::{AudioChannel, AudioControl, AudioInstance, AudioSource, AudioTween, PlaybackState};
+    use bevy_rapier3d::prelude::Velocity;
+    use rand::prelude::*;
+    use serde::{Serialize, Deserialize};
+
+    use crate::audio::audio::{channel_volume, Music, PlaySound};
+    use crate::game_state::game_state::GameState;
+    use crate::level::level::CurrentLevel;
+    use crate::settings::settings::Settings;
+    use crate::setup_world::setup_objects::Controlling;
+
+    // The music is mixed quieter than sound effects
+    const MUSIC_BASE_VOLUME:f32 = 0.25;
+    const CROSSFADE:f32 = 3.0;
+    // How long volume changes from ducking, stems and settings take
+    const VOLUME_TWEEN:f32 = 0.25;
+    // Volumes closer than this to the last one sent aren't sent again
+    const VOLUME_EPSILON:f64 = 0.005;
+    const DUCK_VOLUME:f32 = 0.4;
+    const DUCK_TIME:f32 = 0.6;
+    // The pause and settings menus play the gameplay music, quieter
+    const PAUSED_VOLUME:f32 = 0.5;
+
+    const DEFAULT_TRACK:&str = "Glimpsing-Infinity-Asher-Fulero.mp3";
+
+    /// A layer of a track that fades in as the player speeds up
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct Stem {
+        pub(crate) path: String,
+        /// Silent at or below this speed...
+        #[serde(default)]
+        pub(crate) min_speed: f32,
+        /// ...and at full volume from this one
+        pub(crate) full_speed: f32,
+    }
+
+    impl Stem {
+        fn mix(&self, speed: f32) -> f32 {
+            ((speed - self.min_speed) / (self.full_speed - self.min_speed).max(0.01)).clamp(0.0, 1.0)
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct Track {
+        pub(crate) path: String,
+        /// Should be the same length as the track, so they stay in sync
+        #[serde(default)]
+        pub(crate) stems: Vec<Stem>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, Default)]
+    pub(crate) struct Playlist {
+        pub(crate) tracks: Vec<Track>,
+        /// Play the tracks in a random order instead of the listed one
+        #[serde(default)]
+        pub(crate) shuffle: bool,
+    }
+
+    impl Playlist {
+        fn single(path: &str) -> Playlist {
+            Playlist { tracks: vec![Track { path: path.to_string(), stems: vec![] }], shuffle: false }
+        }
+
+        fn next_track(&self, current: Option<usize>) -> usize {
+            match current {
+                // Any track but the one that just played
+                Some(current) if self.shuffle && self.tracks.len() > 1 => {
+                    let next = thread_rng().gen_range(0..self.tracks.len() - 1);
+                    if next >= current { next + 1 } else { next }
+                },
+                None if self.shuffle => thread_rng().gen_range(0..self.tracks.len().max(1)),
+                Some(current) => (current + 1) % self.tracks.len(),
+                None => 0,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    #[serde(default)]
+    pub(crate) struct Soundtrack {
+        /// Loading screen and main menu
+        pub(crate) menu: Playlist,
+        /// Also plays, quieter, in the pause and settings menus
+        pub(crate) gameplay: Playlist,
+        pub(crate) results: Playlist,
+    }
+
+    impl Default for Soundtrack {
+        fn default() -> Self {
+            Soundtrack {
+                menu: Playlist::single(DEFAULT_TRACK),
+                gameplay: Playlist::single(DEFAULT_TRACK),
+                results: Playlist::single(DEFAULT_TRACK),
+            }
+        }
+    }
+
+    impl Soundtrack {
+        fn playlist(&self, cue: MusicCue) -> &Playlist {
+            match cue {
+                MusicCue::Menu => &self.menu,
+                MusicCue::Gameplay => &self.gameplay,
+                MusicCue::Results => &self.results,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum MusicCue {
+        Menu,
+        Gameplay,
+        Results,
+    }
+
+    impl MusicCue {
+        fn from_state(state: &GameState) -> MusicCue {
+            match state {
+                GameState::Loading | GameState::MainMenu => MusicCue::Menu,
+                GameState::Playing | GameState::Paused | GameState::Settings => MusicCue::Gameplay,
+                GameState::Results => MusicCue::Results,
+            }
+        }
+    }
+
+    struct PlayingInstance {
+        handle: Handle<AudioInstance>,
+        /// None for the main track
+        stem: Option<Stem>,
+        /// The last volume sent to it
+        volume: f64,
+    }
+
+    struct PlayingTrack {
+        source: Handle<AudioSource>,
+        instances: Vec<PlayingInstance>,
+    }
+
+    #[derive(Resource, Default)]
+    pub(crate) struct MusicDirector {
+        cue: Option<MusicCue>,
+        track: Option<usize>,
+        playing: Option<PlayingTrack>,
+        /// Seconds of ducking left
+        duck: f32,
+        /// Keeps the first track of every playlist loaded, so they can start straight away
+        preloaded: Vec<Handle<AudioSource>>,
+    }
+
+    impl MusicDirector {
+        fn fade_out(&mut self, audio_instances: &mut Assets<AudioInstance>) {
+            let Some(playing) = self.playing.take() else { return };
+            for instance in playing.instances {
+                if let Some(instance) = audio_instances.get_mut(&instance.handle) {
+                    instance.stop(AudioTween::linear(Duration::from_secs_f32(CROSSFADE)));
+                }
+            }
+        }
+
+        fn play(&mut self, track: &Track, asset_server: &AssetServer, music: &AudioChannel<Music>, volume: f64) {
+            let fade_in = AudioTween::linear(Duration::from_secs_f32(CROSSFADE));
+            let source: Handle<AudioSource> = asset_server.load(track.path.as_str());
+
+            let mut instances = vec![PlayingInstance {
+                handle: music.play(source.clone()).with_volume(volume).fade_in(fade_in.clone()).handle(),
+                stem: None,
+                volume,
+            }];
+            // Stems start silent, direct_music brings them in
+            for stem in &track.stems {
+                instances.push(PlayingInstance {
+                    handle: music.play(asset_server.load(stem.path.as_str())).with_volume(0.0).fade_in(fade_in.clone()).handle(),
+                    stem: Some(stem.clone()),
+                    volume: 0.0,
+                });
+            }
+
+            self.playing = Some(PlayingTrack { source, instances });
+        }
+    }
+
+    pub(crate) fn preload_soundtrack(
+        level: Res<CurrentLevel>,
+        asset_server: Res<AssetServer>,
+        mut director: ResMut<MusicDirector>,
+    ) {
+        let soundtrack = &level.0.soundtrack;
+        for playlist in [&soundtrack.menu, &soundtrack.gameplay, &soundtrack.results] {
+            match playlist.tracks.first() {
+                Some(track) => director.preloaded.push(asset_server.load(track.path.as_str())),
+                None => println!("WARNING! A PLAYLIST IN THE SOUNDTRACK FOR {} HAS NO TRACKS", level.0.name),
+            }
+        }
+    }
+
+    /// Seconds until the main track of `playing` ends, if it has started and its length is known
+    fn time_left(playing: &PlayingTrack, audio_instances: &Assets<AudioInstance>, sources: &Assets<AudioSource>) -> Option<f32> {
+        let source = sources.get(&playing.source)?;
+        let instance = audio_instances.get(&playing.instances.first()?.handle)?;
+        match instance.state() {
+            PlaybackState::Playing { position } => Some(source.sound.duration().as_secs_f32() - position as f32),
+            PlaybackState::Stopped => Some(0.0),
+            _ => None,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn direct_music(
+        time: Res<Time>,
+        state: Res<State<GameState>>,
+        level: Res<CurrentLevel>,
+        settings: Res<Settings>,
+        asset_server: Res<AssetServer>,
+        music: Res<AudioChannel<Music>>,
+        mut director: ResMut<MusicDirector>,
+        mut play_sound: EventReader<PlaySound>,
+        mut audio_instances: ResMut<Assets<AudioInstance>>,
+        sources: Res<Assets<AudioSource>>,
+        players: Query<&Velocity, With<Controlling>>,
+    ) {
+        let mut volume = MUSIC_BASE_VOLUME * settings.music_volume;
+
+        director.duck = (director.duck - time.raw_delta_seconds()).max(0.0);
+        if play_sound.iter().any(|ev| ev.sound.ducks_music()) {
+            director.duck = DUCK_TIME;
+        }
+        if director.duck > 0.0 {
+            volume *= DUCK_VOLUME;
+        }
+        if matches!(state.get(), GameState::Paused | GameState::Settings) {
+            volume *= PAUSED_VOLUME;
+        }
+        let volume = channel_volume(&settings, volume, settings.music_muted);
+
+        // Start the next track when the playlist changes, or when the current one is about to end
+        let cue = MusicCue::from_state(state.get());
+        let playlist = level.0.soundtrack.playlist(cue);
+        let next = if director.cue != Some(cue) {
+            Some(playlist.next_track(None))
+        } else {
+            match &director.playing {
+                Some(playing) => time_left(playing, &audio_instances, &sources)
+                    .filter(|left| *left <= CROSSFADE)
+                    .map(|_| playlist.next_track(director.track)),
+                None => None,
+            }
+        };
+        if let Some(next) = next {
+            director.fade_out(&mut audio_instances);
+            director.cue = Some(cue);
+            director.track = Some(next);
+            if let Some(track) = playlist.tracks.get(next) {
+                director.play(track, &asset_server, &music, volume);
+            }
+        }
+
+        let speed = players.get_single().map_or(0.0, |velocity| velocity.linvel.length());
+        let Some(playing) = &mut director.playing else { return };
+        for playing_instance in &mut playing.instances {
+            let target = match &playing_instance.stem {
+                Some(stem) => volume * stem.mix(speed) as f64,
+                None => volume,
+            };
+            if (target - playing_instance.volume).abs() < VOLUME_EPSILON {
+                continue
+            }
+            if let Some(instance) = audio_instances.get_mut(&playing_instance.handle) {
+                instance.set_volume(target, AudioTween::linear(Duration::from_secs_f32(VOLUME_TWEEN)));
+                playing_instance.volume = target;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn playlist(tracks: usize, shuffle: bool) -> Playlist {
+            let tracks = (0..tracks).map(|i| Track { path: format!("track-{}.ogg", i), stems: vec![] }).collect();
+            Playlist { tracks, shuffle }
+        }
+
+        #[test]
+        fn tracks_play_in_order_and_wrap() {
+            let playlist = playlist(3, false);
+            assert_eq!(playlist.next_track(None), 0);
+            assert_eq!(playlist.next_track(Some(0)), 1);
+            assert_eq!(playlist.next_track(Some(2)), 0);
+        }
+
+        #[test]
+        fn single_track_repeats() {
+            let playlist = Playlist::single("theme.ogg");
+            assert_eq!(playlist.next_track(Some(0)), 0);
+            assert_eq!(playlist(1, true).next_track(Some(0)), 0);
+        }
+
+        #[test]
+        fn shuffle_never_repeats_a_track() {
+            let playlist = playlist(4, true);
+            let mut current = playlist.next_track(None);
+            for _ in 0..200 {
+                let next = playlist.next_track(Some(current));
+                assert!(next < 4);
+                assert_ne!(next, current);
+                current = next;
+            }
+        }
+
+        #[test]
+        fn shuffle_can_start_on_any_track() {
+            let playlist = playlist(3, true);
+            let mut started_on = [false; 3];
+            for _ in 0..200 {
+                started_on[playlist.next_track(None)] = true;
+            }
+            assert_eq!(started_on, [true; 3]);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+pub(crate) mod navmesh {
+    //! Navigation mesh for enemies, built from the level's collision geometry.
+    //!
+    //! `move_scene_entities` hands over the same trimesh data it uses for colliders, and
+    //! `spawn_level` adds the level's static primitives. Once every scene has handed over its
+    //! meshes, the walkable (flat enough) triangles become the nodes of a graph, joined wherever two
+    //! triangles share an edge, and the navmesh is built once for the whole level. Paths are found
+    //! with A* over the triangle centers. Building is slow for big levels, so navmeshes are cached in
+    //! `navmesh.bin`, keyed on a hash of the triangles they were built from.
+
+    use std::cmp::Ordering;
+    use std::collections::{BinaryHeap, HashMap};
+    use std::collections::hash_map::DefaultHasher;
+    use std::fs;
+    use std::hash::{Hash, Hasher};
+    use bevy::asset::LoadState;
+    use bevy::prelude::*;
+    use bevy::render::mesh::VertexAttributeValues;
+    use serde::{Serialize, Deserialize};
+
+    use crate::level::level::SceneSource;
+    use crate::MakeHitboxes;
+
+    const CACHE_PATH:&str = "navmesh.bin";
+    // Steepest walkable slope, in degrees
+    const MAX_SLOPE:f32 = 45.0;
+    // Vertices closer than this are treated as the same vertex when joining triangles
+    const WELD_DISTANCE:f32 = 0.01;
+    // How far above or below a triangle a point can be and still count as standing on it
+    const MAX_STANDING_HEIGHT:f32 = 2.0;
+
+    /// Geometry waiting to be turned into the level's navmesh
+    #[derive(Resource, Default)]
+    pub(crate) struct PendingNavMeshes {
+        /// Scene meshes in their own local space, moved into world space once their transforms are final
+        pub(crate) meshes: Vec<(Entity, Vec<Vec3>, Vec<[u32;3]>)>,
+        // World space triangles gathered so far
+        triangles: Vec<[Vec3;3]>,
+        /// Set once the navmesh has been built from the whole level
+        pub(crate) built: bool,
+    }
+
+    impl PendingNavMeshes {
+        /**
+         * Adds a mesh that won't move, like a level primitive, placed with `transform`
+         */
+        pub(crate) fn add_mesh(&mut self, mesh: &Mesh, transform: &Transform) {
+            let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else { return };
+            let Some(indices) = mesh.indices() else { return };
+
+            let world: Vec<Vec3> = positions.iter().map(|position| transform.transform_point(Vec3::from(*position))).collect();
+            let indices: Vec<usize> = indices.iter().collect();
+            for triangle in indices.chunks_exact(3) {
+                self.triangles.push([world[triangle[0]], world[triangle[1]], world[triangle[2]]]);
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone)]
+    struct NavNode {
+        corners: [Vec3;3],
+        center: Vec3,
+        neighbours: Vec<usize>,
+    }
+
+    #[derive(Resource, Serialize, Deserialize, Clone)]
+    pub(crate) struct NavMesh {
+        nodes: Vec<NavNode>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct CachedNavMesh {
+        // Hash of the triangles the navmesh was built from
+        source_hash: u64,
+        navmesh: NavMesh,
+    }
+
+    fn weld(point: Vec3) -> (i64, i64, i64) {
+        (
+            (point.x / WELD_DISTANCE).round() as i64,
+            (point.y / WELD_DISTANCE).round() as i64,
+            (point.z / WELD_DISTANCE).round() as i64,
+        )
+    }
+
+    fn hash_triangles(triangles: &[[Vec3;3]]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for triangle in triangles {
+            for corner in triangle {
+                corner.x.to_bits().hash(&mut hasher);
+                corner.y.to_bits().hash(&mut hasher);
+                corner.z.to_bits().hash(&mut hasher);
+            }
+        }
+        // Changing how navmeshes are built invalidates the cache
+        (MAX_SLOPE.to_bits(), WELD_DISTANCE.to_bits()).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn load_cache() -> Vec<CachedNavMesh> {
+        fs::read(CACHE_PATH).ok()
+            .and_then(|bytes| bincode::deserialize(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    fn add_to_cache(item: CachedNavMesh) {
+        let mut cache = load_cache();
+        cache.retain(|cached| cached.source_hash != item.source_hash);
+        cache.push(item);
+        match fs::write(CACHE_PATH, bincode::serialize(&cache).unwrap()) {
+            Ok(_) => println!("Cached navmesh into {}", CACHE_PATH),
+            Err(why) => println!("WARNING! FAILED TO WRITE NAVMESH CACHE TO {}: {}", CACHE_PATH, why),
+        }
+    }
+
+    impl NavMesh {
+        /**
+         * Builds a navmesh from world space triangles, keeping only the ones that are flat enough to walk on
+         */
+        pub(crate) fn build(triangles: &[[Vec3;3]]) -> NavMesh {
+            let min_up = MAX_SLOPE.to_radians().cos();
+
+            let mut nodes: Vec<NavNode> = triangles.iter()
+                .filter(|[a, b, c]| {
+                    let normal = (*b - *a).cross(*c - *a).normalize_or_zero();
+                    normal.y >= min_up
+                })
+                .map(|corners| NavNode {
+                    corners: *corners,
+                    center: (corners[0] + corners[1] + corners[2]) / 3.0,
+                    neighbours: vec![],
+                })
+                .collect();
+
+            // Triangles that share an edge are neighbours
+            let mut edges: HashMap<((i64, i64, i64), (i64, i64, i64)), Vec<usize>> = HashMap::new();
+            for (index, node) in nodes.iter().enumerate() {
+                for i in 0..3 {
+                    let a = weld(node.corners[i]);
+                    let b = weld(node.corners[(i + 1) % 3]);
+                    let edge = if a < b { (a, b) } else { (b, a) };
+                    edges.entry(edge).or_default().push(index);
+                }
+            }
+            for sharing in edges.values() {
+                for &a in sharing {
+                    for &b in sharing {
+                        if a != b && !nodes[a].neighbours.contains(&b) {
+                            nodes[a].neighbours.push(b);
+                        }
+                    }
+                }
+            }
+
+            info!("built navmesh with {} walkable triangles", nodes.len());
+            NavMesh { nodes }
+        }
+
+        /**
+         * Loads the navmesh from the cache if it was built from the same triangles, otherwise builds and caches it
+         */
+        pub(crate) fn load_or_build(triangles: &[[Vec3;3]]) -> NavMesh {
+            let source_hash = hash_triangles(triangles);
+            if let Some(cached) = load_cache().into_iter().find(|cached| cached.source_hash == source_hash) {
+                println!("Found cached navmesh in {}", CACHE_PATH);
+                return cached.navmesh
+            }
+
+            let navmesh = NavMesh::build(triangles);
+            add_to_cache(CachedNavMesh { source_hash, navmesh: navmesh.clone() });
+            navmesh
+        }
+
+        // The triangle under (or closest to) a point
+        fn node_at(&self, point: Vec3) -> Option<usize> {
+            self.nodes.iter().enumerate()
+                .filter(|(_, node)| (node.center.y - point.y).abs() <= MAX_STANDING_HEIGHT + triangle_radius(node))
+                .min_by(|(_, a), (_, b)| {
+                    let a = a.center.distance_squared(point);
+                    let b = b.center.distance_squared(point);
+                    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+                })
+                .map(|(index, _)| index)
+        }
+
+        /**
+         * Finds a path across the navmesh with A*. The path is a list of points to walk through, ending at `to`.
+         */
+        pub(crate) fn find_path(&self, from: Vec3, to: Vec3) -> Option<Vec<Vec3>> {
+            let start = self.node_at(from)?;
+            let goal = self.node_at(to)?;
+
+            let mut open = BinaryHeap::from([OpenNode { cost: 0.0, index: start }]);
+            let mut came_from: HashMap<usize, usize> = HashMap::new();
+            let mut best_cost: HashMap<usize, f32> = HashMap::from([(start, 0.0)]);
+
+            while let Some(OpenNode { index, .. }) = open.pop() {
+                if index == goal {
+                    let mut path = vec![to];
+                    let mut current = goal;
+                    while let Some(&previous) = came_from.get(&current) {
+                        path.push(self.nodes[current].center);
+                        current = previous;
+                    }
+                    path.reverse();
+                    return Some(path)
+                }
+
+                let cost_here = best_cost[&index];
+                for &next in &self.nodes[index].neighbours {
+                    let cost = cost_here + self.nodes[index].center.distance(self.nodes[next].center);
+                    if best_cost.get(&next).map_or(true, |&best| cost < best) {
+                        best_cost.insert(next, cost);
+                        came_from.insert(next, index);
+                        let estimate = cost + self.nodes[next].center.distance(self.nodes[goal].center);
+                        open.push(OpenNode { cost: estimate, index: next });
+                    }
+                }
+            }
+
+            None
+        }
+    }
+
+    fn triangle_radius(node: &NavNode) -> f32 {
+        node.corners.iter().map(|corner| corner.distance(node.center)).fold(0.0, f32::max)
+    }
+
+    // Entry in the A* open list, ordered so the cheapest comes out of the heap first
+    struct OpenNode {
+        cost: f32,
+        index: usize,
+    }
+
+    impl PartialEq for OpenNode {
+        fn eq(&self, other: &Self) -> bool {
+            self.cost == other.cost
+        }
+    }
+
+    impl Eq for OpenNode {}
+
+    impl PartialOrd for OpenNode {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for OpenNode {
+        fn cmp(&self, other: &Self) -> Ordering {
+            other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+        }
+    }
+
+    /**
+     * Moves the meshes collected by `move_scene_entities` into world space, a frame after they were
+     * collected once their transforms have been propagated. When no scene is left to hand over its
+     * meshes, the navmesh is built from everything gathered.
+     */
+    pub(crate) fn build_navmesh(
+        mut commands: Commands,
+        mut pending: ResMut<PendingNavMeshes>,
+        transforms: Query<&GlobalTransform>,
+        unbuilt_scenes: Query<Option<&SceneSource>, With<MakeHitboxes>>,
+        asset_server: Res<AssetServer>,
+    ) {
+        if pending.built {
+            return
+        }
+
+        let pending = &mut *pending;
+        for (entity, vertices, indices) in pending.meshes.drain(..) {
+            let Ok(transform) = transforms.get(entity) else { continue };
+            let world: Vec<Vec3> = vertices.iter().map(|vertex| transform.transform_point(*vertex)).collect();
+            for [a, b, c] in indices {
+                pending.triangles.push([world[a as usize], world[b as usize], world[c as usize]]);
+            }
+        }
+
+        // Scenes that failed to load will never hand anything over
+        let scenes_done = unbuilt_scenes.iter()
+            .all(|source| source.is_some_and(|source| asset_server.get_load_state(source.0.id()) == LoadState::Failed));
+        if !scenes_done {
+            return
+        }
+
+        commands.insert_resource(NavMesh::load_or_build(&pending.triangles));
+        pending.triangles = vec![];
+        pending.built = true;
+    }
+}
@@ -0,0 +1,646 @@
+pub(crate) mod particles {
+    //! Lightweight CPU particles: dust when landing, a burst when jumping, a trail behind dashes,
+    //! a flash when respawning, sparkles from pickups and motes drifting around the light orbs.
+    //!
+    //! Particles are simulated on the CPU in [`Particles`]. They come from bursts, which
+    //! `movement_particles` spawns for each [`MovementEvent`] and `event_particles` for respawns and
+    //! pickups, and from [`ParticleEmitter`]s, which
+    //! spawn them steadily for as long as they're on an entity. Each one fades and shrinks
+    //! between its colors and sizes over its lifetime.
+    //!
+    //! All of them are drawn as camera-facing quads with one draw call. With instancing, a single
+    //! quad is drawn once per particle by `particles.wgsl`, which faces it towards the camera.
+    //! Without it (the `Software` graphics quality), the quads are built on the CPU into one mesh
+    //! every frame and drawn with an unlit `StandardMaterial`.
+
+    use bevy::{
+        core_pipeline::core_3d::Transparent3d,
+        ecs::{
+            query::QueryItem,
+            system::{lifetimeless::{Read, SRes}, SystemParamItem},
+        },
+        pbr::{MeshPipeline, MeshPipelineKey, MeshUniform, NotShadowCaster, NotShadowReceiver, SetMeshBindGroup, SetMeshViewBindGroup},
+        prelude::*,
+        reflect::TypeUuid,
+        render::{
+            extract_component::{ExtractComponent, ExtractComponentPlugin},
+            mesh::{GpuBufferInfo, Indices, MeshVertexBufferLayout},
+            render_asset::RenderAssets,
+            render_phase::{AddRenderCommand, DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline, TrackedRenderPass},
+            render_resource::{
+                Buffer, BufferInitDescriptor, BufferUsages, Extent3d, PipelineCache, PrimitiveTopology, RenderPipelineDescriptor,
+                SpecializedMeshPipeline, SpecializedMeshPipelineError, SpecializedMeshPipelines, TextureDimension, TextureFormat,
+                VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode,
+            },
+            renderer::RenderDevice,
+            view::{ExtractedView, NoFrustumCulling},
+            Render, RenderApp, RenderSet,
+        },
+    };
+    use bytemuck::{Pod, Zeroable};
+    use rand::prelude::*;
+
+    use crate::checkpoints::checkpoints::PlayerRespawned;
+    use crate::collectibles::collectibles::CollectiblePickedUp;
+    use crate::game_state::game_state::GameState;
+    use crate::movement::movement::MovementEvent;
+    use crate::setup_world::setup_objects::Controlling;
+
+    pub(crate) const PARTICLE_SHADER_HANDLE: HandleUntyped = HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x7a3e_55c1_0f2b_9d48);
+
+    // Oldest particles are dropped past this
+    const MAX_PARTICLES:usize = 4096;
+    // The player cube's half size, to put dust at its feet
+    const PLAYER_HALF_SIZE:f32 = 0.5;
+    // Landings slower than this don't kick up dust
+    const MIN_LANDING_SPEED:f32 = 4.0;
+    // Landings this fast or faster kick up the most dust
+    const HARD_LANDING_SPEED:f32 = 25.0;
+    const DASH_TRAIL_SECONDS:f32 = 0.35;
+
+    /// How the particles of one kind look and move
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) struct ParticleEffect {
+        /// Seconds, picked at random for each particle
+        pub(crate) lifetime: (f32, f32),
+        /// Starting speed, picked at random for each particle
+        pub(crate) speed: (f32, f32),
+        /// How far from the emitting direction particles can go, from 0 (straight along it) to 1
+        /// (any direction)
+        pub(crate) spread: f32,
+        /// Particles start this far from the emitter at most
+        pub(crate) radius: f32,
+        /// Downwards acceleration. Negative floats up.
+        pub(crate) gravity: f32,
+        /// Fraction of velocity lost per second
+        pub(crate) drag: f32,
+        /// Width at birth and at death
+        pub(crate) size: (f32, f32),
+        /// Color at birth and at death, alpha included. Above 1 glows with bloom.
+        pub(crate) color: (Color, Color),
+    }
+
+    pub(crate) const LANDING_DUST: ParticleEffect = ParticleEffect {
+        lifetime: (0.5, 0.9),
+        speed: (2.0, 5.0),
+        spread: 0.15,
+        radius: 0.4,
+        gravity: -0.5,
+        drag: 3.0,
+        size: (0.25, 0.6),
+        color: (Color::rgba(0.75, 0.72, 0.65, 0.5), Color::rgba(0.75, 0.72, 0.65, 0.0)),
+    };
+
+    pub(crate) const JUMP_BURST: ParticleEffect = ParticleEffect {
+        lifetime: (0.25, 0.45),
+        speed: (3.0, 6.0),
+        spread: 0.5,
+        radius: 0.3,
+        gravity: 9.8,
+        drag: 2.0,
+        size: (0.15, 0.05),
+        color: (Color::rgba(1.6, 1.4, 0.6, 0.9), Color::rgba(1.0, 0.6, 0.2, 0.0)),
+    };
+
+    pub(crate) const DASH_TRAIL: ParticleEffect = ParticleEffect {
+        lifetime: (0.3, 0.5),
+        speed: (0.2, 1.0),
+        spread: 1.0,
+        radius: 0.45,
+        gravity: 0.0,
+        drag: 4.0,
+        size: (0.35, 0.05),
+        color: (Color::rgba(2.0, 1.7, 0.5, 0.8), Color::rgba(1.2, 0.4, 0.1, 0.0)),
+    };
+
+    pub(crate) const RESPAWN_FLASH: ParticleEffect = ParticleEffect {
+        lifetime: (0.4, 0.8),
+        speed: (2.0, 4.0),
+        spread: 1.0,
+        radius: 0.5,
+        gravity: -1.0,
+        drag: 2.5,
+        size: (0.2, 0.05),
+        color: (Color::rgba(0.6, 1.8, 2.0, 0.9), Color::rgba(0.2, 0.6, 1.0, 0.0)),
+    };
+
+    /// Motes drifting up around a light orb, in the orb's hue
+    pub(crate) fn orb_motes(hue: f32) -> ParticleEffect {
+        ParticleEffect {
+            lifetime: (2.0, 4.0),
+            speed: (0.1, 0.3),
+            spread: 1.0,
+            radius: 1.2,
+            gravity: -0.15,
+            drag: 0.5,
+            size: (0.08, 0.02),
+            // Bright enough to bloom
+            color: (Color::hsla(hue, 1.0, 0.7, 0.8).as_rgba_linear() * 3.0, Color::hsla(hue, 1.0, 0.5, 0.0)),
+        }
+    }
+
+    /// Sparkles thrown up by a pickup, in its color
+    pub(crate) fn pickup_sparkles(color: Color) -> ParticleEffect {
+        ParticleEffect {
+            lifetime: (0.4, 0.7),
+            speed: (2.0, 5.0),
+            spread: 0.6,
+            radius: 0.2,
+            gravity: 6.0,
+            drag: 1.5,
+            size: (0.12, 0.03),
+            color: (color.as_rgba_linear() * 3.0, color.with_a(0.0)),
+        }
+    }
+
+    /// Spawns particles steadily around its entity
+    #[derive(Component)]
+    pub(crate) struct ParticleEmitter {
+        pub(crate) effect: ParticleEffect,
+        /// Particles per second
+        pub(crate) rate: f32,
+        /// Which way particles are thrown
+        pub(crate) direction: Vec3,
+        /// Removes the emitter when it finishes. Emits forever without one.
+        pub(crate) duration: Option<Timer>,
+        /// Fractions of a particle left over from earlier frames
+        owed: f32,
+    }
+
+    impl ParticleEmitter {
+        pub(crate) fn new(effect: ParticleEffect, rate: f32, direction: Vec3) -> ParticleEmitter {
+            ParticleEmitter { effect, rate, direction, duration: None, owed: 0.0 }
+        }
+
+        pub(crate) fn for_seconds(self, seconds: f32) -> ParticleEmitter {
+            ParticleEmitter { duration: Some(Timer::from_seconds(seconds, TimerMode::Once)), ..self }
+        }
+    }
+
+    struct Particle {
+        position: Vec3,
+        velocity: Vec3,
+        age: f32,
+        lifetime: f32,
+        gravity: f32,
+        drag: f32,
+        size: (f32, f32),
+        color: (Vec4, Vec4),
+    }
+
+    impl Particle {
+        fn life_fraction(&self) -> f32 {
+            (self.age / self.lifetime).min(1.0)
+        }
+    }
+
+    /// Every live particle
+    #[derive(Resource, Default)]
+    pub(crate) struct Particles {
+        particles: Vec<Particle>,
+    }
+
+    impl Particles {
+        /// Throws `count` particles from `position`, around `direction`
+        pub(crate) fn burst(&mut self, effect: &ParticleEffect, position: Vec3, direction: Vec3, count: usize) {
+            let mut rng = thread_rng();
+            let direction = direction.try_normalize().unwrap_or(Vec3::Y);
+            for _ in 0..count {
+                let offset = random_direction(&mut rng) * effect.radius * rng.gen::<f32>().cbrt();
+                let velocity = direction.lerp(random_direction(&mut rng), effect.spread).normalize_or_zero()
+                    * random_in(&mut rng, effect.speed);
+                self.particles.push(Particle {
+                    position: position + offset,
+                    velocity,
+                    age: 0.0,
+                    lifetime: random_in(&mut rng, effect.lifetime).max(0.01),
+                    gravity: effect.gravity,
+                    drag: effect.drag,
+                    size: effect.size,
+                    color: (Vec4::from_array(effect.color.0.as_linear_rgba_f32()), Vec4::from_array(effect.color.1.as_linear_rgba_f32())),
+                });
+            }
+            if self.particles.len() > MAX_PARTICLES {
+                let overflow = self.particles.len() - MAX_PARTICLES;
+                self.particles.drain(..overflow);
+            }
+        }
+    }
+
+    fn random_in(rng: &mut ThreadRng, (min, max): (f32, f32)) -> f32 {
+        if max > min { rng.gen_range(min..max) } else { min }
+    }
+
+    fn random_direction(rng: &mut ThreadRng) -> Vec3 {
+        loop {
+            let point = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            if let Some(direction) = point.try_normalize().filter(|_| point.length_squared() <= 1.0) {
+                return direction
+            }
+        }
+    }
+
+    pub(crate) struct ParticlePlugin {
+        /// Draw particles with one instanced quad, instead of building a mesh of them every frame
+        pub(crate) instancing: bool,
+    }
+
+    impl Default for ParticlePlugin {
+        fn default() -> Self {
+            ParticlePlugin { instancing: true }
+        }
+    }
+
+    impl Plugin for ParticlePlugin {
+        fn build(&self, app: &mut App) {
+            app.init_resource::<Particles>()
+                // Frozen while paused, like everything else
+                .add_systems(Update, (movement_particles, event_particles, emit_particles, simulate_particles).chain().run_if(in_state(GameState::Playing)));
+
+            if self.instancing {
+                app.add_plugins(ExtractComponentPlugin::<ParticleInstances>::default())
+                    .add_systems(Startup, (load_particle_shader, setup_instanced_particles))
+                    .add_systems(Update, update_particle_instances.after(simulate_particles));
+                app.sub_app_mut(RenderApp)
+                    .add_render_command::<Transparent3d, DrawParticles>()
+                    .init_resource::<SpecializedMeshPipelines<ParticlePipeline>>()
+                    .add_systems(Render, (
+                        queue_particles.in_set(RenderSet::Queue),
+                        prepare_particle_buffers.in_set(RenderSet::Prepare),
+                    ));
+            } else {
+                app.add_systems(Startup, setup_particle_mesh)
+                    .add_systems(Update, update_particle_mesh.after(simulate_particles));
+            }
+        }
+
+        fn finish(&self, app: &mut App) {
+            if self.instancing {
+                // Needs the MeshPipeline, which only exists once the renderer has started
+                app.sub_app_mut(RenderApp).init_resource::<ParticlePipeline>();
+            }
+        }
+    }
+
+    /**
+     * Dust when landing, a burst when jumping, and a trail behind dashes
+     */
+    pub(crate) fn movement_particles(
+        mut commands: Commands,
+        mut particles: ResMut<Particles>,
+        mut movement_events: EventReader<MovementEvent>,
+        players: Query<Entity, With<Controlling>>,
+    ) {
+        let feet = Vec3::new(0.0, -PLAYER_HALF_SIZE, 0.0);
+        for ev in movement_events.iter() {
+            match *ev {
+                MovementEvent::Landed { position, speed } if speed >= MIN_LANDING_SPEED => {
+                    let hardness = ((speed - MIN_LANDING_SPEED) / (HARD_LANDING_SPEED - MIN_LANDING_SPEED)).min(1.0);
+                    // Spreads out along the ground
+                    for direction in [Vec3::X, Vec3::NEG_X, Vec3::Z, Vec3::NEG_Z] {
+                        particles.burst(&LANDING_DUST, position + feet, direction, 3 + (5.0 * hardness) as usize);
+                    }
+                },
+                MovementEvent::Landed { .. } => {},
+                MovementEvent::Jumped { position } => {
+                    particles.burst(&JUMP_BURST, position + feet, Vec3::NEG_Y, 16);
+                },
+                MovementEvent::Dashed { position, direction } => {
+                    particles.burst(&DASH_TRAIL, position, -direction, 10);
+                    for player in &players {
+                        commands.entity(player).insert(ParticleEmitter::new(DASH_TRAIL, 90.0, -direction).for_seconds(DASH_TRAIL_SECONDS));
+                    }
+                },
+            }
+        }
+    }
+
+    /**
+     * A flash where the player comes back, and sparkles where something was picked up
+     */
+    pub(crate) fn event_particles(
+        mut particles: ResMut<Particles>,
+        mut player_respawned: EventReader<PlayerRespawned>,
+        mut picked_up: EventReader<CollectiblePickedUp>,
+    ) {
+        for ev in player_respawned.iter() {
+            particles.burst(&RESPAWN_FLASH, ev.position, Vec3::Y, 40);
+        }
+        for ev in picked_up.iter() {
+            particles.burst(&pickup_sparkles(ev.kind.color()), ev.position, Vec3::Y, 24);
+        }
+    }
+
+    pub(crate) fn emit_particles(
+        mut commands: Commands,
+        time: Res<Time>,
+        mut particles: ResMut<Particles>,
+        mut emitters: Query<(Entity, &GlobalTransform, &mut ParticleEmitter)>,
+    ) {
+        for (entity, transform, mut emitter) in &mut emitters {
+            emitter.owed += emitter.rate * time.delta_seconds();
+            let count = emitter.owed.floor();
+            emitter.owed -= count;
+            particles.burst(&emitter.effect, transform.translation(), emitter.direction, count as usize);
+
+            if let Some(duration) = &mut emitter.duration {
+                if duration.tick(time.delta()).finished() {
+                    commands.entity(entity).remove::<ParticleEmitter>();
+                }
+            }
+        }
+    }
+
+    pub(crate) fn simulate_particles(time: Res<Time>, mut particles: ResMut<Particles>) {
+        let delta = time.delta_seconds();
+        particles.particles.retain_mut(|particle| {
+            particle.age += delta;
+            particle.velocity.y -= particle.gravity * delta;
+            particle.velocity *= (1.0 - particle.drag * delta).max(0.0);
+            particle.position += particle.velocity * delta;
+            particle.age < particle.lifetime
+        });
+    }
+
+    /// Where a particle is, how big and what color, as the shader reads it
+    #[derive(Clone, Copy, Pod, Zeroable)]
+    #[repr(C)]
+    struct ParticleInstance {
+        position: Vec3,
+        size: f32,
+        color: Vec4,
+    }
+
+    fn instance(particle: &Particle) -> ParticleInstance {
+        let t = particle.life_fraction();
+        ParticleInstance {
+            position: particle.position,
+            size: particle.size.0 + (particle.size.1 - particle.size.0) * t,
+            color: particle.color.0.lerp(particle.color.1, t),
+        }
+    }
+
+    /// The entity all particles are drawn on
+    #[derive(Component)]
+    struct ParticleCloud;
+
+    /// This frame's particles, copied to the render world
+    #[derive(Component, Deref, Clone)]
+    struct ParticleInstances(Vec<ParticleInstance>);
+
+    impl ExtractComponent for ParticleInstances {
+        type Query = &'static ParticleInstances;
+        type Filter = ();
+        type Out = Self;
+
+        fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self> {
+            Some(item.clone())
+        }
+    }
+
+    fn load_particle_shader(mut shaders: ResMut<Assets<Shader>>) {
+        shaders.set_untracked(PARTICLE_SHADER_HANDLE, Shader::from_wgsl(include_str!("particles.wgsl"), "particles.wgsl"));
+    }
+
+    fn setup_instanced_particles(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+        commands.spawn((
+            meshes.add(Mesh::from(shape::Quad::new(Vec2::ONE))),
+            SpatialBundle::INHERITED_IDENTITY,
+            ParticleInstances(Vec::new()),
+            // The quad is at the origin, but its copies are everywhere
+            NoFrustumCulling,
+            ParticleCloud,
+            Name::new("Particles"),
+        ));
+    }
+
+    fn update_particle_instances(particles: Res<Particles>, mut clouds: Query<&mut ParticleInstances, With<ParticleCloud>>) {
+        if !particles.is_changed() {
+            return
+        }
+        for mut instances in &mut clouds {
+            instances.0.clear();
+            instances.0.extend(particles.particles.iter().map(instance));
+        }
+    }
+
+    #[derive(Resource)]
+    struct ParticlePipeline {
+        mesh_pipeline: MeshPipeline,
+    }
+
+    impl FromWorld for ParticlePipeline {
+        fn from_world(world: &mut World) -> Self {
+            ParticlePipeline { mesh_pipeline: world.resource::<MeshPipeline>().clone() }
+        }
+    }
+
+    impl SpecializedMeshPipeline for ParticlePipeline {
+        type Key = MeshPipelineKey;
+
+        fn specialize(
+            &self,
+            key: Self::Key,
+            layout: &MeshVertexBufferLayout,
+        ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+            let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+            descriptor.label = Some("particle_pipeline".into());
+            descriptor.vertex.shader = PARTICLE_SHADER_HANDLE.typed();
+            descriptor.vertex.buffers.push(VertexBufferLayout {
+                array_stride: std::mem::size_of::<ParticleInstance>() as u64,
+                step_mode: VertexStepMode::Instance,
+                attributes: vec![
+                    // Locations 0 to 2 are the quad's position, normal and uv
+                    VertexAttribute { format: VertexFormat::Float32x4, offset: 0, shader_location: 3 },
+                    VertexAttribute { format: VertexFormat::Float32x4, offset: VertexFormat::Float32x4.size(), shader_location: 4 },
+                ],
+            });
+            descriptor.fragment.as_mut().unwrap().shader = PARTICLE_SHADER_HANDLE.typed();
+            // Particles pass through each other
+            descriptor.primitive.cull_mode = None;
+            Ok(descriptor)
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn queue_particles(
+        draw_functions: Res<DrawFunctions<Transparent3d>>,
+        particle_pipeline: Res<ParticlePipeline>,
+        msaa: Res<Msaa>,
+        mut pipelines: ResMut<SpecializedMeshPipelines<ParticlePipeline>>,
+        pipeline_cache: Res<PipelineCache>,
+        meshes: Res<RenderAssets<Mesh>>,
+        clouds: Query<(Entity, &MeshUniform, &Handle<Mesh>, &ParticleInstances)>,
+        mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
+    ) {
+        let draw_particles = draw_functions.read().id::<DrawParticles>();
+        let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+
+        for (view, mut transparent_phase) in &mut views {
+            let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr) | MeshPipelineKey::BLEND_PREMULTIPLIED_ALPHA;
+            let rangefinder = view.rangefinder3d();
+            for (entity, mesh_uniform, mesh_handle, instances) in &clouds {
+                if instances.is_empty() {
+                    continue
+                }
+                let Some(mesh) = meshes.get(mesh_handle) else { continue };
+                let key = view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+                let Ok(pipeline) = pipelines.specialize(&pipeline_cache, &particle_pipeline, key, &mesh.layout) else { continue };
+                transparent_phase.add(Transparent3d {
+                    entity,
+                    pipeline,
+                    draw_function: draw_particles,
+                    distance: rangefinder.distance(&mesh_uniform.transform),
+                });
+            }
+        }
+    }
+
+    #[derive(Component)]
+    struct ParticleBuffer {
+        buffer: Buffer,
+        length: usize,
+    }
+
+    fn prepare_particle_buffers(
+        mut commands: Commands,
+        clouds: Query<(Entity, &ParticleInstances)>,
+        render_device: Res<RenderDevice>,
+    ) {
+        for (entity, instances) in &clouds {
+            // Empty buffers can't be bound, and nothing is drawn anyway
+            if instances.is_empty() {
+                continue
+            }
+            let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("particle instance buffer"),
+                contents: bytemuck::cast_slice(instances.as_slice()),
+                usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            });
+            commands.entity(entity).insert(ParticleBuffer { buffer, length: instances.len() });
+        }
+    }
+
+    type DrawParticles = (
+        SetItemPipeline,
+        SetMeshViewBindGroup<0>,
+        SetMeshBindGroup<1>,
+        DrawQuadInstanced,
+    );
+
+    struct DrawQuadInstanced;
+
+    impl<P: PhaseItem> RenderCommand<P> for DrawQuadInstanced {
+        type Param = SRes<RenderAssets<Mesh>>;
+        type ViewWorldQuery = ();
+        type ItemWorldQuery = (Read<Handle<Mesh>>, Read<ParticleBuffer>);
+
+        #[inline]
+        fn render<'w>(
+            _item: &P,
+            _view: (),
+            (mesh_handle, particle_buffer): (&'w Handle<Mesh>, &'w ParticleBuffer),
+            meshes: SystemParamItem<'w, '_, Self::Param>,
+            pass: &mut TrackedRenderPass<'w>,
+        ) -> RenderCommandResult {
+            let Some(gpu_mesh) = meshes.into_inner().get(mesh_handle) else { return RenderCommandResult::Failure };
+
+            pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+            pass.set_vertex_buffer(1, particle_buffer.buffer.slice(..));
+            let instances = 0..particle_buffer.length as u32;
+            match &gpu_mesh.buffer_info {
+                GpuBufferInfo::Indexed { buffer, index_format, count } => {
+                    pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                    pass.draw_indexed(0..*count, 0, instances);
+                },
+                GpuBufferInfo::NonIndexed => {
+                    pass.draw(0..gpu_mesh.vertex_count, instances);
+                },
+            }
+            RenderCommandResult::Success
+        }
+    }
+
+    /// A soft round dot, so the quads don't look square
+    fn soft_dot() -> Image {
+        const SIZE:u32 = 32;
+        let mut data = Vec::with_capacity((4 * SIZE * SIZE) as usize);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let offset = (Vec2::new(x as f32, y as f32) + 0.5) / SIZE as f32 * 2.0 - 1.0;
+                let alpha = (1.0 - offset.length()).clamp(0.0, 1.0);
+                data.extend_from_slice(&[255, 255, 255, (alpha * alpha * 255.0) as u8]);
+            }
+        }
+        Image::new(Extent3d { width: SIZE, height: SIZE, depth_or_array_layers: 1 }, TextureDimension::D2, data, TextureFormat::Rgba8UnormSrgb)
+    }
+
+    fn setup_particle_mesh(
+        mut commands: Commands,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut materials: ResMut<Assets<StandardMaterial>>,
+        mut images: ResMut<Assets<Image>>,
+    ) {
+        commands.spawn(PbrBundle {
+            mesh: meshes.add(Mesh::new(PrimitiveTopology::TriangleList)),
+            material: materials.add(StandardMaterial {
+                base_color_texture: Some(images.add(soft_dot())),
+                unlit: true,
+                alpha_mode: AlphaMode::Blend,
+                cull_mode: None,
+                ..default()
+            }),
+            visibility: Visibility::Hidden,
+            ..default()
+        })  .insert((NotShadowCaster, NotShadowReceiver))
+            .insert(NoFrustumCulling)
+            .insert(ParticleCloud)
+            .insert(Name::new("Particles"));
+    }
+
+    /**
+     * Builds a quad facing the camera for every particle
+     */
+    fn update_particle_mesh(
+        particles: Res<Particles>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut clouds: Query<(&Handle<Mesh>, &mut Visibility), With<ParticleCloud>>,
+        cameras: Query<&GlobalTransform, With<Camera3d>>,
+    ) {
+        let Ok(camera) = cameras.get_single() else { return };
+        let (right, up, back) = (camera.right(), camera.up(), camera.back());
+
+        for (mesh_handle, mut visibility) in &mut clouds {
+            // Empty meshes can't be drawn
+            let shown = if particles.particles.is_empty() { Visibility::Hidden } else { Visibility::Inherited };
+            if *visibility != shown {
+                *visibility = shown;
+            }
+            if particles.particles.is_empty() {
+                continue
+            }
+            let Some(mesh) = meshes.get_mut(mesh_handle) else { continue };
+
+            let count = particles.particles.len();
+            let mut positions = Vec::with_capacity(4 * count);
+            let mut colors = Vec::with_capacity(4 * count);
+            let mut indices = Vec::with_capacity(6 * count);
+            for (i, particle) in particles.particles.iter().enumerate() {
+                let instance = instance(particle);
+                let (half_right, half_up) = (right * instance.size * 0.5, up * instance.size * 0.5);
+                for corner in [-half_right - half_up, half_right - half_up, half_right + half_up, -half_right + half_up] {
+                    positions.push((instance.position + corner).to_array());
+                    colors.push(instance.color.to_array());
+                }
+                let first = 4 * i as u32;
+                indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+            }
+
+            mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![back.to_array(); 4 * count]);
+            mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]].repeat(count));
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+            mesh.set_indices(Some(Indices::U32(indices)));
+        }
+    }
+}
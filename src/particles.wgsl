@@ -0,0 +1,44 @@
+// Particles: one quad, drawn once per particle, turned to face the camera and faded into a
+// soft round dot.
+
+#import bevy_pbr::mesh_view_bindings view
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+
+    // Per particle: center in xyz, width in w
+    @location(3) i_position_size: vec4<f32>,
+    @location(4) i_color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    // The camera's right and up directions, so the quad always faces it
+    let right = view.view[0].xyz;
+    let up = view.view[1].xyz;
+    let corner = (right * vertex.position.x + up * vertex.position.y) * vertex.i_position_size.w;
+    let world_position = vertex.i_position_size.xyz + corner;
+
+    var out: VertexOutput;
+    out.clip_position = view.view_proj * vec4<f32>(world_position, 1.0);
+    out.uv = vertex.uv;
+    out.color = vertex.i_color;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let from_center = length(in.uv * 2.0 - 1.0);
+    let falloff = clamp(1.0 - from_center, 0.0, 1.0);
+    let alpha = in.color.a * falloff * falloff;
+    // Drawn with premultiplied alpha
+    return vec4<f32>(in.color.rgb * alpha, alpha);
+}
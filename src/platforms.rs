@@ -0,0 +1,316 @@
+pub(crate) mod platforms {
+    //! Kinematic platforms that follow a path.
+    //!
+    //! Platforms are `RigidBody::KinematicPositionBased`, so Rapier knows they are moving instead
+    //! of teleporting. The player's horizontal velocity is damped every frame, so friction alone
+    //! won't carry them; [`carry_riders`] moves anyone standing on a platform along with it.
+    //!
+    //! A [`PlatformPath`] is checked and turned into a [`PlatformMotion`] once, when the platform
+    //! is spawned, so following it every frame doesn't allocate.
+
+    use bevy::prelude::*;
+    use bevy_rapier3d::prelude::*;
+    use serde::{Serialize, Deserialize};
+
+    use crate::setup_world::setup_objects::Controlling;
+
+    /// How a platform moves, relative to where it was placed
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) enum PlatformPath {
+        /// Travels through each point in turn, then back (or around, if `looped`)
+        Waypoints {
+            points: Vec<Vec3>,
+            /// Units per second
+            speed: f32,
+            /// Seconds to wait at each point
+            #[serde(default)]
+            pause: f32,
+            #[serde(default)]
+            easing: Easing,
+            #[serde(default)]
+            looped: bool,
+        },
+        /// Swings back and forth between -offset and offset
+        Sine {
+            offset: Vec3,
+            /// Seconds for a full swing
+            period: f32,
+        },
+        /// Circles around a point `radius` away, starting from where it was placed
+        Orbit {
+            radius: f32,
+            /// Seconds for a full circle
+            period: f32,
+            #[serde(default = "default_orbit_axis")]
+            axis: Vec3,
+        },
+    }
+
+    fn default_orbit_axis() -> Vec3 { Vec3::Y }
+
+    /// Speed curve for each leg of a waypoint path
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+    pub(crate) enum Easing {
+        #[default]
+        Linear,
+        EaseIn,
+        EaseOut,
+        EaseInOut,
+    }
+
+    impl Easing {
+        fn apply(self, t: f32) -> f32 {
+            match self {
+                Easing::Linear => t,
+                Easing::EaseIn => t * t,
+                Easing::EaseOut => t * (2.0 - t),
+                Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            }
+        }
+    }
+
+    impl PlatformPath {
+        /// Why a platform can't follow this path, if it can't
+        pub(crate) fn validate(&self) -> Result<(), &'static str> {
+            match self {
+                PlatformPath::Waypoints { speed, .. } if *speed <= 0.0 => Err("the speed has to be above 0"),
+                PlatformPath::Sine { period, .. } | PlatformPath::Orbit { period, .. } if *period <= 0.0 => Err("the period has to be above 0"),
+                _ => Ok(()),
+            }
+        }
+
+        pub(crate) fn motion(&self) -> PlatformMotion {
+            match self {
+                PlatformPath::Waypoints { points, speed, pause, easing, looped } => {
+                    let first = points.first().copied().unwrap_or(Vec3::ZERO);
+                    if points.len() < 2 || *speed <= 0.0 {
+                        return PlatformMotion::Waypoints { first, legs: vec![], pause: 0.0, easing: *easing, cycle: 0.0 }
+                    }
+
+                    // Ping-pong paths go through the points forwards, then backwards
+                    let mut route: Vec<Vec3> = points.to_vec();
+                    if *looped {
+                        route.push(points[0]);
+                    } else {
+                        route.extend(points.iter().rev().skip(1));
+                    }
+
+                    let legs: Vec<(Vec3, Vec3, f32)> = route.windows(2)
+                        .map(|leg| (leg[0], leg[1], leg[0].distance(leg[1]) / speed))
+                        .collect();
+                    let cycle = legs.iter().map(|(_, _, duration)| duration + pause).sum();
+                    PlatformMotion::Waypoints { first, legs, pause: *pause, easing: *easing, cycle }
+                },
+                PlatformPath::Sine { offset, period } => PlatformMotion::Sine { offset: *offset, period: *period },
+                PlatformPath::Orbit { radius, period, axis } => {
+                    let axis = axis.normalize_or_zero();
+                    // Any direction perpendicular to the axis works as the way to the center
+                    let from_center = axis.any_orthonormal_vector() * *radius;
+                    PlatformMotion::Orbit { from_center, axis, period: *period }
+                },
+            }
+        }
+    }
+
+    /// A [`PlatformPath`] with everything that doesn't change over time worked out
+    #[derive(Clone, Debug)]
+    pub(crate) enum PlatformMotion {
+        Waypoints {
+            first: Vec3,
+            /// Start, end and duration of each leg, back to the start
+            legs: Vec<(Vec3, Vec3, f32)>,
+            pause: f32,
+            easing: Easing,
+            /// Seconds to go through every leg
+            cycle: f32,
+        },
+        Sine {
+            offset: Vec3,
+            period: f32,
+        },
+        Orbit {
+            /// Where the platform starts, relative to the center of the circle
+            from_center: Vec3,
+            axis: Vec3,
+            period: f32,
+        },
+    }
+
+    impl PlatformMotion {
+        /// Offset from the platform's origin `time` seconds after it started moving
+        pub(crate) fn offset_at(&self, time: f32) -> Vec3 {
+            match self {
+                PlatformMotion::Waypoints { first, legs, pause, easing, cycle } => {
+                    waypoint_offset(*first, legs, *pause, *easing, *cycle, time)
+                },
+                PlatformMotion::Sine { offset, period } => {
+                    *offset * (std::f32::consts::TAU * time / period).sin()
+                },
+                PlatformMotion::Orbit { from_center, axis, period } => {
+                    Quat::from_axis_angle(*axis, std::f32::consts::TAU * time / period) * *from_center - *from_center
+                },
+            }
+        }
+    }
+
+    fn waypoint_offset(first: Vec3, legs: &[(Vec3, Vec3, f32)], pause: f32, easing: Easing, cycle: f32, time: f32) -> Vec3 {
+        if cycle <= 0.0 {
+            return first
+        }
+
+        let mut t = time.rem_euclid(cycle);
+        for &(from, to, duration) in legs {
+            if t < pause {
+                return from
+            }
+            t -= pause;
+            if t < duration {
+                return from.lerp(to, easing.apply(t / duration))
+            }
+            t -= duration;
+        }
+
+        first
+    }
+
+    #[derive(Component)]
+    pub(crate) struct MovingPlatform {
+        pub(crate) origin: Vec3,
+        motion: Option<PlatformMotion>,
+        /// Angular velocity in radians per second
+        pub(crate) spin: Vec3,
+        elapsed: f32,
+        /// How far the platform moved last frame
+        delta: Vec3,
+    }
+
+    impl MovingPlatform {
+        /**
+         * A platform at `origin`. Paths it can't follow are left out with a warning, so it stays put.
+         */
+        pub(crate) fn new(origin: Vec3, path: Option<PlatformPath>, spin: Vec3) -> Self {
+            let motion = path.and_then(|path| match path.validate() {
+                Ok(()) => Some(path.motion()),
+                Err(why) => {
+                    println!("WARNING! THE PLATFORM AT {} WON'T MOVE: {}", origin, why);
+                    None
+                },
+            });
+            MovingPlatform { origin, motion, spin, elapsed: 0.0, delta: Vec3::ZERO }
+        }
+    }
+
+    pub(crate) fn move_platforms(time: Res<Time>, mut platforms: Query<(&mut MovingPlatform, &mut Transform)>) {
+        for (mut platform, mut transform) in &mut platforms {
+            platform.elapsed += time.delta_seconds();
+
+            let target = match &platform.motion {
+                Some(motion) => platform.origin + motion.offset_at(platform.elapsed),
+                None => platform.origin,
+            };
+            platform.delta = target - transform.translation;
+            transform.translation = target;
+
+            let spin = platform.spin * time.delta_seconds();
+            if spin != Vec3::ZERO {
+                transform.rotate(Quat::from_euler(EulerRot::XYZ, spin.x, spin.y, spin.z));
+            }
+        }
+    }
+
+    pub(crate) fn carry_riders(
+        rapier_context: Res<RapierContext>,
+        platforms: Query<(&MovingPlatform, &GlobalTransform)>,
+        mut players: Query<(Entity, &mut Transform), With<Controlling>>,
+    ) {
+        for (player, mut transform) in &mut players {
+            for contact in rapier_context.contacts_with(player) {
+                if !contact.has_any_active_contacts() {
+                    continue
+                }
+
+                let other = if contact.collider1() == player { contact.collider2() } else { contact.collider1() };
+                let Ok((platform, platform_transform)) = platforms.get(other) else { continue };
+
+                // Only riders standing on top get carried, not things the platform bumps into
+                if transform.translation.y > platform_transform.translation().y {
+                    transform.translation += platform.delta;
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn close(a: Vec3, b: Vec3) -> bool {
+            a.distance(b) < 1e-4
+        }
+
+        #[test]
+        fn waypoints_ping_pong() {
+            let path = PlatformPath::Waypoints {
+                points: vec![Vec3::ZERO, Vec3::X * 2.0],
+                speed: 1.0,
+                pause: 0.0,
+                easing: Easing::Linear,
+                looped: false,
+            };
+            let motion = path.motion();
+            assert!(close(motion.offset_at(0.0), Vec3::ZERO));
+            assert!(close(motion.offset_at(1.0), Vec3::X));
+            assert!(close(motion.offset_at(3.0), Vec3::X));
+            // Back at the start after going there and back
+            assert!(close(motion.offset_at(4.0), Vec3::ZERO));
+        }
+
+        #[test]
+        fn waypoints_pause_at_each_point() {
+            let path = PlatformPath::Waypoints {
+                points: vec![Vec3::ZERO, Vec3::Y, Vec3::Z],
+                speed: 1.0,
+                pause: 0.5,
+                easing: Easing::EaseInOut,
+                looped: true,
+            };
+            let motion = path.motion();
+            assert!(close(motion.offset_at(0.25), Vec3::ZERO));
+            assert!(close(motion.offset_at(1.0), Vec3::Y * 0.5));
+            assert!(close(motion.offset_at(1.75), Vec3::Y));
+        }
+
+        #[test]
+        fn sine_swings_both_ways() {
+            let motion = PlatformPath::Sine { offset: Vec3::X, period: 4.0 }.motion();
+            assert!(close(motion.offset_at(0.0), Vec3::ZERO));
+            assert!(close(motion.offset_at(1.0), Vec3::X));
+            assert!(close(motion.offset_at(3.0), Vec3::NEG_X));
+        }
+
+        #[test]
+        fn orbit_starts_where_it_was_placed() {
+            let motion = PlatformPath::Orbit { radius: 3.0, period: 2.0, axis: Vec3::Y }.motion();
+            assert!(close(motion.offset_at(0.0), Vec3::ZERO));
+            assert!(close(motion.offset_at(2.0), Vec3::ZERO));
+            // Halfway round is across the circle
+            assert!((motion.offset_at(1.0).length() - 6.0).abs() < 1e-4);
+            assert!(motion.offset_at(1.0).y.abs() < 1e-4);
+        }
+
+        #[test]
+        fn paths_that_cant_be_followed_are_rejected() {
+            let stopped = PlatformPath::Waypoints { points: vec![Vec3::ZERO, Vec3::X], speed: 0.0, pause: 0.0, easing: Easing::Linear, looped: false };
+            assert!(stopped.validate().is_err());
+            assert!(PlatformPath::Sine { offset: Vec3::X, period: 0.0 }.validate().is_err());
+            assert!(PlatformPath::Orbit { radius: 1.0, period: -1.0, axis: Vec3::Y }.validate().is_err());
+            assert!(PlatformPath::Orbit { radius: 1.0, period: 1.0, axis: Vec3::Y }.validate().is_ok());
+        }
+
+        #[test]
+        fn invalid_paths_leave_the_platform_still() {
+            let platform = MovingPlatform::new(Vec3::ONE, Some(PlatformPath::Sine { offset: Vec3::X, period: 0.0 }), Vec3::ZERO);
+            assert!(platform.motion.is_none());
+        }
+    }
+}
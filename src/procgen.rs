@@ -0,0 +1,314 @@
+pub(crate) mod procgen {
+    //! Seeded procedural generation.
+    //!
+    //! Everything random about the world is drawn from a [`WorldSeed`], so a layout can be
+    //! reproduced by passing the same `seed=N` argument (or `seed` in the level file).
+    //! [`generate_platforms`] builds chains of floating platforms that the player can actually
+    //! get across, and [`validate`] proves it by searching for a path from the first platform to
+    //! the last one.
+
+    use std::collections::VecDeque;
+    use bevy::prelude::*;
+    use rand::prelude::*;
+    use rand::rngs::StdRng;
+    use serde::{Serialize, Deserialize};
+
+    use crate::movement::movement::{PLAYER_SPEED, JUMP_VELOCITY, DASH_MULTIPLIER};
+
+    // Rapier's default gravity
+    const GRAVITY:f32 = 9.81;
+    // The player's horizontal velocity is halved every frame, so movement is framerate dependent.
+    // Reach is computed for this framerate.
+    const ASSUMED_FPS:f32 = 60.0;
+    const MAX_ATTEMPTS:u32 = 100;
+
+    #[derive(Resource, Clone, Copy, Debug)]
+    pub(crate) struct WorldSeed(pub(crate) u64);
+
+    impl WorldSeed {
+        /// Picks a random seed, for when none was given
+        pub(crate) fn random() -> WorldSeed {
+            WorldSeed(rand::thread_rng().gen())
+        }
+
+        /// A seeded rng. Different `stream`s give independent sequences from the same seed, so
+        /// adding a new random feature doesn't change the layout of the existing ones.
+        pub(crate) fn rng(&self, stream: u64) -> StdRng {
+            StdRng::seed_from_u64(self.0 ^ stream.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        }
+    }
+
+    /// How far the player can get in one jump
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+    pub(crate) struct PlayerReach {
+        pub(crate) jump_velocity: f32,
+        pub(crate) gravity: f32,
+        /// Horizontal speed while holding a direction
+        pub(crate) run_speed: f32,
+        /// Extra horizontal distance from one dash in the air
+        pub(crate) dash_distance: f32,
+    }
+
+    impl Default for PlayerReach {
+        fn default() -> Self {
+            // Velocity is halved then increased by PLAYER_SPEED every frame, so it settles at twice that.
+            // A dash adds a burst that halves every frame, which adds up to twice the burst over one frame.
+            PlayerReach {
+                jump_velocity: JUMP_VELOCITY,
+                gravity: GRAVITY,
+                run_speed: 2.0 * PLAYER_SPEED,
+                dash_distance: 2.0 * PLAYER_SPEED * DASH_MULTIPLIER / ASSUMED_FPS,
+            }
+        }
+    }
+
+    impl PlayerReach {
+        /// Highest the player can jump
+        pub(crate) fn jump_height(&self) -> f32 {
+            self.jump_velocity * self.jump_velocity / (2.0 * self.gravity)
+        }
+
+        /// Furthest horizontal distance the player can cover while landing `rise` above where they jumped
+        pub(crate) fn horizontal_reach(&self, rise: f32) -> Option<f32> {
+            let discriminant = self.jump_velocity * self.jump_velocity - 2.0 * self.gravity * rise;
+            if discriminant < 0.0 {
+                return None
+            }
+            let air_time = (self.jump_velocity + discriminant.sqrt()) / self.gravity;
+            Some(self.run_speed * air_time + self.dash_distance)
+        }
+    }
+
+    /// Parameters for a procedurally generated chain of platforms
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct PlatformGenerator {
+        pub(crate) count: u32,
+        /// Where the first platform goes
+        pub(crate) start: Vec3,
+        /// The first platform is moved somewhere in -x_range..x_range along x
+        #[serde(default)]
+        pub(crate) x_range: f32,
+        pub(crate) half_extents: Vec3,
+        /// 0.0 is a gentle staircase, 1.0 has every jump near the limit of what the player can do
+        #[serde(default = "default_difficulty")]
+        pub(crate) difficulty: f32,
+        /// How far, in degrees, each step can turn away from the previous one
+        #[serde(default = "default_max_turn")]
+        pub(crate) max_turn: f32,
+        /// Fraction of the player's reach that is never used, to leave room for mistakes
+        #[serde(default = "default_safety_margin")]
+        pub(crate) safety_margin: f32,
+        #[serde(default)]
+        pub(crate) reach: PlayerReach,
+    }
+
+    fn default_difficulty() -> f32 { 0.3 }
+    fn default_max_turn() -> f32 { 30.0 }
+    fn default_safety_margin() -> f32 { 0.2 }
+
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) struct GeneratedPlatform {
+        pub(crate) center: Vec3,
+        pub(crate) half_extents: Vec3,
+    }
+
+    impl GeneratedPlatform {
+        fn top(&self) -> f32 {
+            self.center.y + self.half_extents.y
+        }
+    }
+
+    /// None of the chains generated had a path through them
+    #[derive(Debug)]
+    pub(crate) struct UnreachableChain {
+        pub(crate) attempts: u32,
+        /// The last chain generated, for when a level is better off with it than with nothing
+        pub(crate) last_attempt: Vec<GeneratedPlatform>,
+    }
+
+    /**
+     * Generates a chain of platforms from the seed. The chain is checked with [`validate`], and
+     * regenerated (with the same rng, so still reproducibly) if there is no path through it.
+     * Every step is generated inside the player's reach, so running out of attempts is a bug.
+     */
+    pub(crate) fn generate_platforms(seed: &WorldSeed, generator: &PlatformGenerator) -> Result<Vec<GeneratedPlatform>, UnreachableChain> {
+        let mut rng = seed.rng(1);
+        let mut platforms = vec![];
+
+        for attempt in 0..MAX_ATTEMPTS {
+            platforms = generate_chain(&mut rng, generator);
+            match validate(&platforms, &generator.reach) {
+                Some(_) => return Ok(platforms),
+                None => info!("generated platform chain {} had no path, trying again", attempt),
+            }
+        }
+
+        Err(UnreachableChain { attempts: MAX_ATTEMPTS, last_attempt: platforms })
+    }
+
+    fn generate_chain(rng: &mut StdRng, generator: &PlatformGenerator) -> Vec<GeneratedPlatform> {
+        let difficulty = generator.difficulty.clamp(0.0, 1.0);
+        let usable = 1.0 - generator.safety_margin.clamp(0.0, 1.0);
+        let reach = &generator.reach;
+
+        // Harder chains use smaller platforms
+        let half_extents = generator.half_extents * Vec3::new(1.0 - 0.5 * difficulty, 1.0, 1.0 - 0.5 * difficulty);
+        let first = generator.start + Vec3::X * (rng.gen::<f32>() * 2.0 * generator.x_range - generator.x_range);
+
+        let mut platforms = vec![GeneratedPlatform { center: first, half_extents }];
+        let mut heading:f32 = 0.0;
+
+        for _ in 1..generator.count {
+            let previous = platforms.last().unwrap();
+
+            let max_rise = reach.jump_height() * usable;
+            let rise = max_rise * rng.gen_range(-0.5..=1.0) * difficulty.max(0.1);
+
+            // Edge to edge gap, as a fraction of what is possible for this rise
+            let max_gap = reach.horizontal_reach(rise).unwrap_or(0.0) * usable;
+            let gap = max_gap * rng.gen_range(0.5..=1.0) * difficulty.max(0.2);
+
+            heading += rng.gen_range(-1.0..=1.0) * generator.max_turn.to_radians();
+            let direction = Vec3::new(heading.sin(), 0.0, heading.cos());
+            // Step far enough to clear both platforms' edges along the direction of travel
+            let step = gap + edge_distance(half_extents, direction) * 2.0;
+
+            platforms.push(GeneratedPlatform {
+                center: previous.center + direction * step + Vec3::Y * rise,
+                half_extents,
+            });
+        }
+
+        platforms
+    }
+
+    // Distance from the center of an axis-aligned platform to its edge, in a horizontal direction
+    fn edge_distance(half_extents: Vec3, direction: Vec3) -> f32 {
+        let x = if direction.x.abs() > f32::EPSILON { half_extents.x / direction.x.abs() } else { f32::INFINITY };
+        let z = if direction.z.abs() > f32::EPSILON { half_extents.z / direction.z.abs() } else { f32::INFINITY };
+        x.min(z)
+    }
+
+    // Whether the player can jump from the top of `from` onto the top of `to`
+    fn can_jump(from: &GeneratedPlatform, to: &GeneratedPlatform, reach: &PlayerReach) -> bool {
+        let rise = to.top() - from.top();
+        let offset = to.center - from.center;
+        let horizontal = Vec3::new(offset.x, 0.0, offset.z);
+        let distance = horizontal.length();
+        if distance <= f32::EPSILON {
+            return rise <= reach.jump_height()
+        }
+
+        let direction = horizontal / distance;
+        let gap = distance - edge_distance(from.half_extents, direction) - edge_distance(to.half_extents, direction);
+
+        match reach.horizontal_reach(rise) {
+            Some(max_gap) => gap <= max_gap,
+            None => false,
+        }
+    }
+
+    /**
+     * Searches for a sequence of jumps from the first platform to the last one.
+     * Returns the indices of the platforms along the path, or None if the last platform can't be reached.
+     */
+    pub(crate) fn validate(platforms: &[GeneratedPlatform], reach: &PlayerReach) -> Option<Vec<usize>> {
+        if platforms.is_empty() {
+            return Some(vec![])
+        }
+
+        let goal = platforms.len() - 1;
+        let mut came_from: Vec<Option<usize>> = vec![None; platforms.len()];
+        let mut visited = vec![false; platforms.len()];
+        let mut queue = VecDeque::from([0]);
+        visited[0] = true;
+
+        while let Some(current) = queue.pop_front() {
+            if current == goal {
+                let mut path = vec![goal];
+                while let Some(previous) = came_from[*path.last().unwrap()] {
+                    path.push(previous);
+                }
+                path.reverse();
+                return Some(path)
+            }
+
+            for next in 0..platforms.len() {
+                if !visited[next] && can_jump(&platforms[current], &platforms[next], reach) {
+                    visited[next] = true;
+                    came_from[next] = Some(current);
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        None
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn platform(x: f32, y: f32) -> GeneratedPlatform {
+            GeneratedPlatform { center: Vec3::new(x, y, 0.0), half_extents: Vec3::new(1.0, 0.25, 1.0) }
+        }
+
+        fn generator(difficulty: f32) -> PlatformGenerator {
+            PlatformGenerator {
+                count: 20,
+                start: Vec3::ZERO,
+                x_range: 5.0,
+                half_extents: Vec3::new(2.0, 0.25, 2.0),
+                difficulty,
+                max_turn: default_max_turn(),
+                safety_margin: default_safety_margin(),
+                reach: PlayerReach::default(),
+            }
+        }
+
+        #[test]
+        fn validate_finds_a_path_through_close_platforms() {
+            // Each jump is in reach, but skipping the middle platform isn't
+            let platforms = [platform(0.0, 0.0), platform(10.0, 1.0), platform(20.0, 2.0)];
+            assert_eq!(validate(&platforms, &PlayerReach::default()), Some(vec![0, 1, 2]));
+        }
+
+        #[test]
+        fn validate_rejects_a_gap_too_wide() {
+            let reach = PlayerReach::default();
+            let too_far = 2.0 + reach.horizontal_reach(0.0).unwrap() + 1.0;
+            let platforms = [platform(0.0, 0.0), platform(too_far, 0.0)];
+            assert_eq!(validate(&platforms, &reach), None);
+        }
+
+        #[test]
+        fn validate_rejects_a_ledge_too_high() {
+            let reach = PlayerReach::default();
+            let platforms = [platform(0.0, 0.0), platform(3.0, reach.jump_height() + 1.0)];
+            assert_eq!(validate(&platforms, &reach), None);
+        }
+
+        #[test]
+        fn validate_can_skip_platforms() {
+            let reach = PlayerReach::default();
+            // The middle platform is out of reach, but the last one isn't
+            let platforms = [platform(0.0, 0.0), platform(0.0, reach.jump_height() + 5.0), platform(4.0, 0.0)];
+            assert_eq!(validate(&platforms, &reach), Some(vec![0, 2]));
+        }
+
+        #[test]
+        fn generated_chains_are_reachable_and_reproducible() {
+            for difficulty in [0.0, 0.5, 1.0] {
+                let generator = generator(difficulty);
+                for seed in 0..20 {
+                    let platforms = generate_platforms(&WorldSeed(seed), &generator).unwrap();
+                    assert_eq!(platforms.len(), generator.count as usize);
+                    assert!(validate(&platforms, &generator.reach).is_some());
+
+                    let again = generate_platforms(&WorldSeed(seed), &generator).unwrap();
+                    assert!(platforms.iter().zip(&again).all(|(a, b)| a.center == b.center));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,580 @@
+pub(crate) mod settings {
+    //! Player settings, and the systems that apply them as soon as they change.
+    //!
+    //! Settings are kept in `settings.ron` next to the executable. They're saved when leaving the
+    //! settings menu, and a missing or broken file just gives the defaults.
+    //!
+    //! Graphics settings can be set one by one, or all at once with a [`GraphicsQuality`] preset.
+    //! `Auto` picks a preset from the graphics adapter every time the game starts.
+
+    use std::fs;
+    use bevy::core_pipeline::bloom::BloomSettings;
+    use bevy::pbr::{DirectionalLightShadowMap, PointLightShadowMap};
+    use bevy::prelude::*;
+    use bevy::render::renderer::{RenderAdapterInfo, RenderDevice};
+    use bevy::render::render_resource::{WgpuAdapterInfo, WgpuLimits};
+    use bevy::render::settings::{PowerPreference, WgpuSettings, WgpuSettingsPriority};
+    use bevy::window::{PresentMode, WindowMode};
+    use wgpu::DeviceType;
+    use serde::{Serialize, Deserialize};
+
+    use crate::hud::hud::HudSettings;
+
+    const SETTINGS_PATH:&str = "settings.ron";
+
+    pub(crate) const RESOLUTIONS: &[(f32, f32)] = &[
+        (1280.0, 720.0),
+        (1600.0, 900.0),
+        (1920.0, 1080.0),
+        (2560.0, 1440.0),
+        (3840.0, 2160.0),
+    ];
+
+    #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+    pub(crate) enum DisplayMode {
+        Windowed,
+        Borderless,
+        Fullscreen,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+    pub(crate) enum PresentModeSetting {
+        /// Vsync, with the best mode the platform has
+        Vsync,
+        /// No vsync, with the best mode the platform has
+        NoVsync,
+        Fifo,
+        Mailbox,
+        Immediate,
+    }
+
+    impl PresentModeSetting {
+        pub(crate) fn present_mode(&self) -> PresentMode {
+            match self {
+                PresentModeSetting::Vsync => PresentMode::AutoVsync,
+                PresentModeSetting::NoVsync => PresentMode::AutoNoVsync,
+                PresentModeSetting::Fifo => PresentMode::Fifo,
+                PresentModeSetting::Mailbox => PresentMode::Mailbox,
+                PresentModeSetting::Immediate => PresentMode::Immediate,
+            }
+        }
+
+        pub(crate) fn next(&self) -> PresentModeSetting {
+            match self {
+                PresentModeSetting::Vsync => PresentModeSetting::NoVsync,
+                PresentModeSetting::NoVsync => PresentModeSetting::Fifo,
+                PresentModeSetting::Fifo => PresentModeSetting::Mailbox,
+                PresentModeSetting::Mailbox => PresentModeSetting::Immediate,
+                PresentModeSetting::Immediate => PresentModeSetting::Vsync,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+    pub(crate) enum GraphicsQuality {
+        /// One of the presets, picked from the graphics adapter when the game starts
+        Auto,
+        Low,
+        Medium,
+        High,
+        Ultra,
+        /// For CPU rasterizers like llvmpipe, so CI can take screenshots: nothing expensive, and
+        /// the renderer asks for WebGL2 limits. Only fully takes effect after a restart.
+        Software,
+        /// Graphics settings were changed one by one
+        Custom,
+    }
+
+    /// Everything a [`GraphicsQuality`] preset sets
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    struct GraphicsPreset {
+        shadows: bool,
+        shadow_lights: usize,
+        shadow_map_size: usize,
+        bloom: bool,
+        msaa_samples: u32,
+        hdr: bool,
+    }
+
+    impl GraphicsQuality {
+        fn preset(&self) -> Option<GraphicsPreset> {
+            let (shadows, shadow_lights, shadow_map_size, bloom, msaa_samples, hdr) = match self {
+                GraphicsQuality::Software => (false, 0, 512, false, 1, false),
+                // Only the sun casts shadows
+                GraphicsQuality::Low => (true, 0, 1024, false, 1, false),
+                GraphicsQuality::Medium => (true, 2, 2048, true, 4, true),
+                GraphicsQuality::High => (true, 4, 2048, true, 4, true),
+                GraphicsQuality::Ultra => (true, 16, 4096, true, 4, true),
+                GraphicsQuality::Auto | GraphicsQuality::Custom => return None,
+            };
+            Some(GraphicsPreset {
+                shadows,
+                shadow_lights,
+                shadow_map_size,
+                bloom,
+                msaa_samples,
+                // HDR (needed for bloom) doesn't seem to work for WASM
+                hdr: hdr && cfg!(not(target_arch = "wasm32")),
+            })
+        }
+
+        /// The preset that suits this adapter
+        pub(crate) fn detect(adapter: &WgpuAdapterInfo, limits: &WgpuLimits) -> GraphicsQuality {
+            match adapter.device_type {
+                DeviceType::Cpu => GraphicsQuality::Software,
+                // Downlevel adapters, like WebGL2
+                _ if limits.max_texture_dimension_2d < 8192 || limits.max_storage_buffers_per_shader_stage == 0 => GraphicsQuality::Low,
+                DeviceType::DiscreteGpu if limits.max_texture_dimension_2d >= 16384 => GraphicsQuality::Ultra,
+                DeviceType::DiscreteGpu => GraphicsQuality::High,
+                DeviceType::IntegratedGpu => GraphicsQuality::Medium,
+                DeviceType::VirtualGpu | DeviceType::Other => GraphicsQuality::Low,
+            }
+        }
+
+        /// How the renderer is set up. Has to be decided before the app is built.
+        pub(crate) fn wgpu_settings(&self) -> WgpuSettings {
+            match self {
+                GraphicsQuality::Software => WgpuSettings {
+                    power_preference: PowerPreference::LowPower,
+                    priority: WgpuSettingsPriority::WebGL2,
+                    limits: WgpuLimits::downlevel_webgl2_defaults(),
+                    ..default()
+                },
+                _ => WgpuSettings::default(),
+            }
+        }
+
+        pub(crate) fn next(&self) -> GraphicsQuality {
+            match self {
+                GraphicsQuality::Auto => GraphicsQuality::Low,
+                GraphicsQuality::Low => GraphicsQuality::Medium,
+                GraphicsQuality::Medium => GraphicsQuality::High,
+                GraphicsQuality::High => GraphicsQuality::Ultra,
+                GraphicsQuality::Ultra => GraphicsQuality::Software,
+                GraphicsQuality::Software | GraphicsQuality::Custom => GraphicsQuality::Auto,
+            }
+        }
+
+        pub(crate) fn from_name(name: &str) -> Option<GraphicsQuality> {
+            match name.to_lowercase().as_str() {
+                "auto" => Some(GraphicsQuality::Auto),
+                "low" => Some(GraphicsQuality::Low),
+                "medium" => Some(GraphicsQuality::Medium),
+                "high" => Some(GraphicsQuality::High),
+                "ultra" => Some(GraphicsQuality::Ultra),
+                "software" => Some(GraphicsQuality::Software),
+                _ => None,
+            }
+        }
+    }
+
+    impl DisplayMode {
+        pub(crate) fn window_mode(&self) -> WindowMode {
+            match self {
+                DisplayMode::Windowed => WindowMode::Windowed,
+                DisplayMode::Borderless => WindowMode::BorderlessFullscreen,
+                // Exclusive fullscreen, with the video mode closest to the resolution setting
+                DisplayMode::Fullscreen => WindowMode::SizedFullscreen,
+            }
+        }
+
+        pub(crate) fn next(&self) -> DisplayMode {
+            match self {
+                DisplayMode::Windowed => DisplayMode::Borderless,
+                DisplayMode::Borderless => DisplayMode::Fullscreen,
+                DisplayMode::Fullscreen => DisplayMode::Windowed,
+            }
+        }
+    }
+
+    /// The next size in `RESOLUTIONS` after `current`, wrapping back to the smallest
+    pub(crate) fn next_resolution(current: (f32, f32)) -> (f32, f32) {
+        RESOLUTIONS.iter()
+            .find(|(width, height)| *width * *height > current.0 * current.1)
+            .copied()
+            .unwrap_or(RESOLUTIONS[0])
+    }
+
+    #[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+    #[serde(default)]
+    pub(crate) struct Settings {
+        // Volumes are from 0 to 1
+        pub(crate) master_volume: f32,
+        pub(crate) music_volume: f32,
+        pub(crate) sfx_volume: f32,
+        pub(crate) ui_volume: f32,
+        pub(crate) music_muted: bool,
+        pub(crate) sfx_muted: bool,
+        pub(crate) ui_muted: bool,
+
+        /// Multiplier on camera turning speed
+        pub(crate) mouse_sensitivity: f32,
+        pub(crate) stick_sensitivity: f32,
+        pub(crate) invert_y: bool,
+
+        /// Vertical field of view, in degrees
+        pub(crate) fov: f32,
+        /// Sets the graphics settings below, unless it's `Custom`
+        pub(crate) graphics_quality: GraphicsQuality,
+        pub(crate) bloom: bool,
+        pub(crate) bloom_intensity: f32,
+        pub(crate) shadows: bool,
+        /// How many point lights cast shadows, the ones nearest the camera
+        pub(crate) shadow_lights: usize,
+        /// Width of the sun's shadow maps. Point lights use half of it for each cube face.
+        pub(crate) shadow_map_size: usize,
+        /// 1 turns MSAA off
+        pub(crate) msaa_samples: u32,
+        pub(crate) hdr: bool,
+
+        pub(crate) present_mode: PresentModeSetting,
+        pub(crate) display_mode: DisplayMode,
+        /// Window size when windowed, and the video mode for exclusive fullscreen
+        pub(crate) resolution: (f32, f32),
+        /// Where the window opens. Centered on `monitor` if not set.
+        pub(crate) position: Option<(i32, i32)>,
+        /// Index of the monitor to open on. The primary one if not set.
+        pub(crate) monitor: Option<usize>,
+
+        pub(crate) hud: HudSettings,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Settings {
+                master_volume: 1.0,
+                music_volume: 1.0,
+                sfx_volume: 1.0,
+                ui_volume: 1.0,
+                music_muted: false,
+                sfx_muted: false,
+                ui_muted: false,
+                mouse_sensitivity: 1.0,
+                stick_sensitivity: 1.0,
+                invert_y: false,
+                fov: 45.0,
+                graphics_quality: GraphicsQuality::Auto,
+                bloom: true,
+                bloom_intensity: 0.5,
+                shadows: true,
+                shadow_lights: 16,
+                shadow_map_size: 2048,
+                msaa_samples: 4,
+                // HDR (needed for bloom) doesn't seem to work for WASM, so its disabled when on WASM
+                hdr: cfg!(not(target_arch = "wasm32")),
+                present_mode: PresentModeSetting::Vsync,
+                display_mode: DisplayMode::Windowed,
+                resolution: RESOLUTIONS[0],
+                position: None,
+                monitor: None,
+                hud: HudSettings::default(),
+            }
+        }
+    }
+
+    impl Settings {
+        fn graphics_preset(&self) -> GraphicsPreset {
+            GraphicsPreset {
+                shadows: self.shadows,
+                shadow_lights: self.shadow_lights,
+                shadow_map_size: self.shadow_map_size,
+                bloom: self.bloom,
+                msaa_samples: self.msaa_samples,
+                hdr: self.hdr,
+            }
+        }
+
+        fn set_graphics_preset(&mut self, preset: GraphicsPreset) {
+            self.shadows = preset.shadows;
+            self.shadow_lights = preset.shadow_lights;
+            self.shadow_map_size = preset.shadow_map_size;
+            self.bloom = preset.bloom;
+            self.msaa_samples = preset.msaa_samples;
+            self.hdr = preset.hdr;
+        }
+    }
+
+    pub(crate) fn load_settings() -> Settings {
+        match fs::read_to_string(SETTINGS_PATH) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|why| {
+                println!("WARNING! COULDN'T READ {}, USING DEFAULT SETTINGS: {}", SETTINGS_PATH, why);
+                Settings::default()
+            }),
+            Err(_) => Settings::default(),
+        }
+    }
+
+    /// The settings as loaded and as the command line left them, so arguments only last for one
+    /// run instead of being saved into `settings.ron`
+    #[derive(Resource)]
+    pub(crate) struct CommandLineOverrides {
+        loaded: Settings,
+        overridden: Settings,
+    }
+
+    fn restore_field<T: PartialEq + Copy>(current: &mut T, overridden: T, loaded: T) {
+        if *current == overridden {
+            *current = loaded;
+        }
+    }
+
+    impl CommandLineOverrides {
+        pub(crate) fn new(loaded: Settings, overridden: &Settings) -> CommandLineOverrides {
+            CommandLineOverrides { loaded, overridden: overridden.clone() }
+        }
+
+        /**
+         * The settings to save: whatever the arguments changed, and the player hasn't changed
+         * since, goes back to how it was loaded
+         */
+        pub(crate) fn restore(&self, settings: &Settings) -> Settings {
+            let (loaded, overridden) = (&self.loaded, &self.overridden);
+            let mut saved = settings.clone();
+            restore_field(&mut saved.display_mode, overridden.display_mode, loaded.display_mode);
+            restore_field(&mut saved.resolution, overridden.resolution, loaded.resolution);
+            restore_field(&mut saved.position, overridden.position, loaded.position);
+            restore_field(&mut saved.monitor, overridden.monitor, loaded.monitor);
+            restore_field(&mut saved.present_mode, overridden.present_mode, loaded.present_mode);
+            restore_field(&mut saved.hud.fps_graph, overridden.hud.fps_graph, loaded.hud.fps_graph);
+
+            // The quality's preset replaced the loaded graphics settings, so those go back too
+            if loaded.graphics_quality != overridden.graphics_quality && saved.graphics_quality == overridden.graphics_quality {
+                saved.graphics_quality = loaded.graphics_quality;
+                saved.set_graphics_preset(loaded.graphics_preset());
+            }
+            saved
+        }
+    }
+
+    pub(crate) fn save_settings(settings: Res<Settings>, overrides: Res<CommandLineOverrides>) {
+        let saved = overrides.restore(&settings);
+        let serialized = ron::ser::to_string_pretty(&saved, ron::ser::PrettyConfig::default()).unwrap();
+        match fs::write(SETTINGS_PATH, serialized) {
+            Ok(_) => println!("Saved settings to {}", SETTINGS_PATH),
+            Err(why) => println!("WARNING! FAILED TO SAVE SETTINGS TO {}: {}", SETTINGS_PATH, why),
+        }
+    }
+
+    /// Marks lights that had shadows when they were spawned, so turning shadows back on only
+    /// affects them
+    #[derive(Component)]
+    pub(crate) struct ShadowCaster;
+
+    pub(crate) fn tag_shadow_casters(
+        mut commands: Commands,
+        point_lights: Query<(Entity, &PointLight), Added<PointLight>>,
+        directional_lights: Query<(Entity, &DirectionalLight), Added<DirectionalLight>>,
+    ) {
+        for (entity, light) in &point_lights {
+            if light.shadows_enabled {
+                commands.entity(entity).insert(ShadowCaster);
+            }
+        }
+        for (entity, light) in &directional_lights {
+            if light.shadows_enabled {
+                commands.entity(entity).insert(ShadowCaster);
+            }
+        }
+    }
+
+    /**
+     * Sets the graphics settings from the quality preset when it changes, detecting it for `Auto`
+     */
+    pub(crate) fn apply_graphics_quality(
+        mut settings: ResMut<Settings>,
+        adapter_info: Res<RenderAdapterInfo>,
+        device: Res<RenderDevice>,
+        mut detected: Local<Option<GraphicsQuality>>,
+    ) {
+        if !settings.is_changed() {
+            return
+        }
+
+        let quality = match settings.graphics_quality {
+            GraphicsQuality::Auto => *detected.get_or_insert_with(|| {
+                let quality = GraphicsQuality::detect(&adapter_info, &device.limits());
+                println!("Graphics quality: {:?}, for {} ({:?})", quality, adapter_info.name, adapter_info.device_type);
+                quality
+            }),
+            quality => quality,
+        };
+        if let Some(preset) = quality.preset() {
+            if settings.graphics_preset() != preset {
+                settings.set_graphics_preset(preset);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn apply_graphics_settings(
+        mut commands: Commands,
+        settings: Res<Settings>,
+        mut cameras: Query<(Entity, &mut Camera, &mut Projection, Option<&mut BloomSettings>)>,
+        mut directional_lights: Query<&mut DirectionalLight, With<ShadowCaster>>,
+        mut directional_shadow_map: ResMut<DirectionalLightShadowMap>,
+        mut point_shadow_map: ResMut<PointLightShadowMap>,
+        mut msaa: ResMut<Msaa>,
+        new_casters: Query<(), Added<ShadowCaster>>,
+    ) {
+        // New lights need the current shadow setting too
+        if !settings.is_changed() && new_casters.is_empty() {
+            return
+        }
+
+        for (entity, mut camera, mut projection, bloom) in &mut cameras {
+            if let Projection::Perspective(perspective) = projection.as_mut() {
+                perspective.fov = settings.fov.to_radians();
+            }
+            if camera.hdr != settings.hdr {
+                camera.hdr = settings.hdr;
+            }
+            match (settings.bloom, bloom) {
+                (true, Some(mut bloom)) => bloom.intensity = settings.bloom_intensity,
+                (true, None) => {
+                    commands.entity(entity).insert(BloomSettings { intensity: settings.bloom_intensity, ..default() });
+                },
+                (false, Some(_)) => {
+                    commands.entity(entity).remove::<BloomSettings>();
+                },
+                (false, None) => {},
+            }
+        }
+
+        for mut light in &mut directional_lights {
+            light.shadows_enabled = settings.shadows;
+        }
+        if directional_shadow_map.size != settings.shadow_map_size {
+            directional_shadow_map.size = settings.shadow_map_size;
+        }
+        // Each point light renders six of these
+        let point_size = (settings.shadow_map_size / 2).max(1);
+        if point_shadow_map.size != point_size {
+            point_shadow_map.size = point_size;
+        }
+        let samples = match settings.msaa_samples {
+            0 | 1 => Msaa::Off,
+            2 => Msaa::Sample2,
+            3 | 4 => Msaa::Sample4,
+            _ => Msaa::Sample8,
+        };
+        if *msaa != samples {
+            *msaa = samples;
+        }
+    }
+
+    /**
+     * Gives shadows to the `shadow_lights` point lights nearest the camera, and takes them away
+     * from the rest
+     */
+    pub(crate) fn limit_shadow_lights(
+        settings: Res<Settings>,
+        cameras: Query<&GlobalTransform, With<Camera3d>>,
+        mut point_lights: Query<(Entity, &GlobalTransform, &mut PointLight), With<ShadowCaster>>,
+    ) {
+        let camera = cameras.get_single().map(|camera| camera.translation()).unwrap_or_default();
+        let mut by_distance: Vec<(Entity, f32)> = point_lights.iter()
+            .map(|(entity, transform, _)| (entity, transform.translation().distance_squared(camera)))
+            .collect();
+        by_distance.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let limit = if settings.shadows { settings.shadow_lights } else { 0 };
+        for (i, (entity, _)) in by_distance.into_iter().enumerate() {
+            let Ok((_, _, mut light)) = point_lights.get_mut(entity) else { continue };
+            let shadows = i < limit;
+            // Only touched when it changes, so the light isn't re-extracted every frame
+            if light.shadows_enabled != shadows {
+                light.shadows_enabled = shadows;
+            }
+        }
+    }
+
+    pub(crate) fn apply_window_settings(settings: Res<Settings>, mut windows: Query<&mut Window>) {
+        if !settings.is_changed() {
+            return
+        }
+
+        for mut window in &mut windows {
+            let present_mode = settings.present_mode.present_mode();
+            if window.present_mode != present_mode {
+                window.present_mode = present_mode;
+            }
+            let mode = settings.display_mode.window_mode();
+            if window.mode != mode {
+                window.mode = mode;
+            }
+            let (width, height) = settings.resolution;
+            if window.resolution.width() != width || window.resolution.height() != height {
+                window.resolution.set(width, height);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn adapter(device_type: DeviceType) -> WgpuAdapterInfo {
+            WgpuAdapterInfo {
+                name: "test adapter".to_string(),
+                vendor: 0,
+                device: 0,
+                device_type,
+                driver: String::new(),
+                driver_info: String::new(),
+                backend: wgpu::Backend::Vulkan,
+            }
+        }
+
+        #[test]
+        fn quality_names_ignore_case() {
+            assert_eq!(GraphicsQuality::from_name("software"), Some(GraphicsQuality::Software));
+            assert_eq!(GraphicsQuality::from_name("ULTRA"), Some(GraphicsQuality::Ultra));
+            assert_eq!(GraphicsQuality::from_name("Auto"), Some(GraphicsQuality::Auto));
+        }
+
+        #[test]
+        fn custom_and_unknown_qualities_are_rejected() {
+            // Custom is what the menu switches to, there's nothing to ask for
+            assert_eq!(GraphicsQuality::from_name("custom"), None);
+            assert_eq!(GraphicsQuality::from_name("potato"), None);
+        }
+
+        #[test]
+        fn quality_is_detected_from_the_adapter() {
+            let limits = WgpuLimits::default();
+            assert_eq!(GraphicsQuality::detect(&adapter(DeviceType::Cpu), &limits), GraphicsQuality::Software);
+            assert_eq!(GraphicsQuality::detect(&adapter(DeviceType::DiscreteGpu), &limits), GraphicsQuality::High);
+            assert_eq!(GraphicsQuality::detect(&adapter(DeviceType::IntegratedGpu), &limits), GraphicsQuality::Medium);
+            assert_eq!(GraphicsQuality::detect(&adapter(DeviceType::DiscreteGpu), &WgpuLimits::downlevel_webgl2_defaults()), GraphicsQuality::Low);
+        }
+
+        #[test]
+        fn command_line_overrides_are_not_saved() {
+            let loaded = Settings { graphics_quality: GraphicsQuality::Custom, msaa_samples: 8, ..default() };
+            let mut settings = loaded.clone();
+            settings.resolution = (640.0, 480.0);
+            settings.display_mode = DisplayMode::Fullscreen;
+            settings.graphics_quality = GraphicsQuality::Software;
+            let overrides = CommandLineOverrides::new(loaded.clone(), &settings);
+            // The quality's preset is applied once the game is running
+            settings.set_graphics_preset(GraphicsQuality::Software.preset().unwrap());
+
+            let saved = overrides.restore(&settings);
+            assert_eq!(saved.resolution, loaded.resolution);
+            assert_eq!(saved.display_mode, loaded.display_mode);
+            assert_eq!(saved.graphics_quality, GraphicsQuality::Custom);
+            assert_eq!(saved.msaa_samples, 8);
+        }
+
+        #[test]
+        fn settings_changed_in_the_menu_are_saved() {
+            let loaded = Settings::default();
+            let mut settings = loaded.clone();
+            settings.resolution = (640.0, 480.0);
+            let overrides = CommandLineOverrides::new(loaded, &settings);
+            settings.resolution = (1600.0, 900.0);
+
+            assert_eq!(overrides.restore(&settings).resolution, (1600.0, 900.0));
+        }
+    }
+}
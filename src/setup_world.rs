@@ -1,24 +1,29 @@
 pub(crate) mod setup_objects{
     use bevy_rapier3d::prelude::*;    
     use bevy::{prelude::*, core_pipeline::bloom::BloomSettings};
-    use rand::prelude::*;
 
     #[cfg(feature="use-ray-tracing")]
     use bevy_hikari::HikariSettings;
 
     use smooth_bevy_cameras::{LookTransform, LookTransformBundle, Smoother};
 
-    const WORLD_SIZE:f32 = 300.0;
-
-    #[derive(Component)]
-    pub(crate) struct Moving;
+    use crate::audio::audio::AudioListener;
+    use crate::collisions::collisions::CollisionRole;
+    use crate::hazards::hazards::Turret;
+    use crate::level::level::CurrentLevel;
+    use crate::settings::settings::Settings;
+    use crate::skybox::skybox::RequestSkybox;
+    use crate::surfaces::surfaces::{impact_events, RollingSounds};
 
     // struct that identifies a component for user input.
     #[derive(Component)]
     pub(crate) struct Controlling;
 
+    // Item turns to face the player, at most turn_rate radians per second
     #[derive(Component)]
-    pub(crate) struct PointingAtPlayer;
+    pub(crate) struct PointingAtPlayer {
+        pub(crate) turn_rate: f32,
+    }
 
     #[derive(Component)]
     pub(crate) struct ControllingButWithInfo{
@@ -27,16 +32,20 @@ pub(crate) mod setup_objects{
         pub(crate) has_contacts:bool,
         pub(crate) has_hit_object:bool,
         pub(crate) objects_hit:i32,
+        // Dashing needs a direction to dash in, shown on the HUD
+        pub(crate) can_dash:bool,
     }
     
     pub(crate) fn setup(
         mut commands: Commands,
         mut meshes: ResMut<Assets<Mesh>>,
         mut materials: ResMut<Assets<StandardMaterial>>,
-        asset_server: Res<AssetServer>) {
+        level: Res<CurrentLevel>,
+        settings: Res<Settings>,
+        mut skybox_requests: EventWriter<RequestSkybox>) {
 
-        //Skybox texture
-        let skybox_handle = asset_server.load("textures/space-skybox-k.png");
+        //Skybox texture, put on the camera once it's a cubemap
+        skybox_requests.send(RequestSkybox(level.0.skybox.clone()));
         
         
         //Camera
@@ -49,26 +58,23 @@ pub(crate) mod setup_objects{
         .insert((Camera3dBundle{
             
             camera:Camera { 
-                // HDR (needed for bloom) doesn't seem to work for WASM, so its disabled when on WASM
-                #[cfg(not(target_arch = "wasm32"))]
-                hdr: true, 
+                hdr: settings.hdr, 
                 ..default() 
             },
+            projection: PerspectiveProjection {
+                fov: settings.fov.to_radians(),
+                ..default()
+            }.into(),
             ..default()
         }, BloomSettings{
-            intensity: 0.5,
+            // Removed again by apply_graphics_settings if bloom is turned off
+            intensity: settings.bloom_intensity,
             ..default()
         }, 
         Name::new("Camera"),
-        bevy::core_pipeline::Skybox(skybox_handle.clone())
+        AudioListener,
         ));
     
-        commands.insert_resource(crate::skyboxv2::skyboxv2::Cubemap {
-            is_loaded: false,
-            index: 0,
-            image_handle: skybox_handle,
-        });
-    
 
         // plane
         /*commands.spawn(PbrBundle {
@@ -78,26 +84,6 @@ pub(crate) mod setup_objects{
             ..default()
         }).insert(Collider::cuboid(WORLD_SIZE, 0.1, WORLD_SIZE));*/
 
-        /*commands.spawn(Window {
-            title: "Rust is the future of programming!".to_string(),
-            present_mode: bevy::window::PresentMode::AutoVsync,
-            resolution: (1920., 1080.).into(),
-            ..Default::default()
-        });*/
-
-        //walls 
-        for i in [-1.0,1.0] {
-            commands
-                .spawn_empty()
-                .insert((Collider::cuboid(0.1, 100.0, 100.0), Name::new("Wall")))
-                .insert((TransformBundle::from(Transform::from_xyz(i* WORLD_SIZE, 0.0, 0.0)), Name::new("Wall")));
-            
-            commands
-                .spawn_empty()
-                .insert(Collider::cuboid(100.0, 100.0, 0.1))
-                .insert((TransformBundle::from(Transform::from_xyz(0.0, 0.0, i* WORLD_SIZE)), Name::new("Wall")));
-        }
-
         // player cube
         commands.spawn(PbrBundle {
             mesh: meshes.add(Mesh::from(shape::Cube { size:1.0 })),
@@ -107,18 +93,22 @@ pub(crate) mod setup_objects{
                 metallic: 1.0, 
                 reflectance: 0.9, 
                 ..default()}),
-            transform: Transform::from_xyz(0.0, 5.5, 0.0),
+            transform: Transform::from_translation(level.0.player_spawn),
             ..default()
         })  .insert(Controlling)
             .insert(Name::new("Player Cube"))
-            .insert(ControllingButWithInfo {theta:0.0, v_theta: 0.0, has_contacts:true, has_hit_object:false, objects_hit:0})
+            .insert(ControllingButWithInfo {theta:0.0, v_theta: 0.0, has_contacts:true, has_hit_object:false, objects_hit:0, can_dash:false})
             .insert(Collider::cuboid(0.5, 0.5, 0.5))
+            .insert(CollisionRole::Player)
             .insert(RigidBody::Dynamic)
+            // Enabled once the level has finished loading
+            .insert(RigidBodyDisabled)
             .insert(Velocity {
                 linvel: Vec3::new(1.0, -6.0, 3.0),
                 angvel: Vec3::new(0.2, 0.4, 0.8),
             })
-            .insert(ActiveEvents::COLLISION_EVENTS)
+            .insert(impact_events())
+            .insert(RollingSounds)
             .insert(ExternalForce {
                 force: Vec3{ x: 0.0, y: 0.0, z: 0.0 },
                 torque: Vec3{ x: 0.0, y: 0.0, z: 0.0 },
@@ -139,67 +129,6 @@ pub(crate) mod setup_objects{
                 });
             });
 
-        // moving cube
-        commands.spawn(PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::Cube { size: 0.5 })),
-            material: materials.add(Color::rgb(1.0, 0.0, 0.0).into()),
-            transform: Transform::from_xyz(0.0, 0.5, 1.5),
-            ..default()
-        })  .insert(Name::new("Sin wave cube"))
-            .insert(Moving)
-            .insert(Collider::cuboid(0.25, 0.25, 0.25));
-
-        // balls
-        let mut rng = rand::thread_rng();
-        /*for _ in 0..10 {
-            commands.spawn(PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::UVSphere { radius: 0.5, sectors: 10, stacks:10  })),
-                material: materials.add(StandardMaterial { base_color: Color::GRAY, emissive: Color::DARK_GRAY, unlit:false, ..default() }),
-                transform: Transform::from_xyz(
-                    rng.gen::<f32>() * WORLD_SIZE *2.0 - WORLD_SIZE,
-                    10.0* rng.gen::<f32>(), 
-                    rng.gen::<f32>() * WORLD_SIZE *2.0 - WORLD_SIZE),
-                ..default()
-            })
-                .insert(RigidBody::Dynamic)
-                .insert(Collider::ball(0.5))
-                .insert(Restitution::coefficient(0.7));
-        }*/
-
-        // lights
-        for (x,y,z, h) in [
-                (5.0, -38.0, 13.0, 100.0), 
-                (17.8, -45.1, 30.9, 311.0), 
-                (17.35, -45.1, 39.5, 311.0), 
-                (9.7, -45.1, 54.6, 311.0),
-                (1.35, -45.1, 54.15, 311.0), 
-                (-8.9, -45.1, 39.15, 311.0), 
-                (-8.3, -45.1, 30.3, 311.0)]{
-            commands.spawn(PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::UVSphere { radius: 0.5, sectors: 10, stacks:10  })),
-                material: materials.add(Color::hsla(h, 100.0, 0.05, 0.7).into()),
-                transform: Transform::from_xyz( 
-                    x, 
-                    y, 
-                    z),
-                    ..default()
-            })  .insert(Name::new("Light"))
-                .with_children(|parent|{
-                parent.spawn(PointLightBundle {
-                    point_light: PointLight {
-                        intensity: 10.0,
-                        radius: 1.0,
-                        shadows_enabled: true,
-                        color: Color::hsla(h, 100.0, 0.05, 0.0001),
-                        ..default()
-                    },
-                    
-                    ..default()
-                });
-            });
-            
-        }
-        
         #[cfg(feature="use-ray-tracing")]
         commands.spawn(DirectionalLightBundle {
             directional_light: DirectionalLight {
@@ -219,94 +148,31 @@ pub(crate) mod setup_objects{
             brightness: 0.1,
         });*/
 
-        // Sun
-        commands.spawn(DirectionalLightBundle {
-            directional_light: DirectionalLight {
-                // Configure the projection to better fit the scene
-                /*shadow_projection: OrthographicProjection {
-                    left: -WORLD_SIZE,
-                    right: WORLD_SIZE,
-                    bottom: -WORLD_SIZE,
-                    top: WORLD_SIZE,
-                    near: -10.0 * WORLD_SIZE,
-                    far: 10.0 * WORLD_SIZE,
-                    ..default()
-                },*/
-                illuminance: 80.0,
-                shadows_enabled: true,
-                ..default()
-            },
-            transform: Transform {
-                translation: Vec3::new(0.0, 2.0, 0.0),
-                rotation: Quat::from_rotation_x(-std::f32::consts::FRAC_PI_4),
-                ..default()
-            },
-            ..default()
-        }).insert(Name::new("Sun"));
-
-
-        //platforms 
-        let init_x = rng.gen::<f32>()* 2.0* WORLD_SIZE- WORLD_SIZE;
-
-        for i in 0..3 {
-            commands.spawn(PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Box { 
-                    min_x: -2.5, 
-                    max_x: 2.5, 
-                    min_y: -0.5, 
-                    max_y: 0.5, 
-                    min_z: -2.5, 
-                    max_z: 2.5 })),
-                material: materials.add(Color::rgb(1.0, i as f32 / 3.0, 0.0).into()),
-                transform: Transform::from_xyz(init_x, 3.0 * i as f32, i as f32 * 8.0),
-                ..default()
-            }) //.insert(Moving)
-                .insert(Collider::cuboid(2.5, 0.5, 2.5))
-                .insert(Name::new("floating platform"));
-        }
-
-        commands.spawn(PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::Box { 
-                min_x: -9.0, 
-                max_x: 9.0, 
-                min_y: -2.5, 
-                max_y: 2.5, 
-                min_z: -0.5, 
-                max_z: 0.5 })),
-            material: materials.add(Color::rgb(1.0, 0.0, 1.0).into()),
-            transform: Transform::from_xyz(4.5, 7.0, 8.0),
-            ..default()
-        }) //.insert(Moving)
-            .insert(Collider::cuboid(9.0, 2.5, 0.5));
-
-        commands.spawn(PbrBundle {
-            mesh: meshes.add(Mesh::from(shape::Box { 
-                min_x: -9.0, 
-                max_x: 9.0, 
-                min_y: -2.5, 
-                max_y: 2.5, 
-                min_z: -0.5, 
-                max_z: 0.5 })),
-            material: materials.add(Color::rgb(1.0, 0.0, 1.0).into()),
-            transform: Transform::from_xyz(4.5, 7.0, 15.0),
-            ..default()
-        }) //.insert(Moving)
-            .insert(Collider::cuboid(9.0, 2.5, 0.5));
-
-        
-
-        
+        // Walls, platforms, lights, the sun and the level geometry are spawned from the level file
+        // by crate::level::level::spawn_level
     }
 
     pub(crate) fn point_things_at_player(
-            mut items_to_point: Query<(&mut Transform, With<PointingAtPlayer>, Without<Controlling>)>, 
-            player: Query<&mut Transform, With<Controlling>>){
-
-        let direction = player.single().translation;
-        for mut item in items_to_point.iter_mut() {
-            //let mut item = item.as_mut();
-            let x  = item.0.looking_at(direction.clone(), Vec3::new(0.0,1.0,0.0));
-            item.0.rotation = x.rotation;
+            time: Res<Time>,
+            mut items_to_point: Query<(&mut Transform, &PointingAtPlayer, Option<&Turret>), Without<Controlling>>, 
+            player: Query<&Transform, With<Controlling>>){
+
+        let Ok(player) = player.get_single() else { return };
+        let direction = player.translation;
+        for (mut transform, pointing, turret) in items_to_point.iter_mut() {
+            // Turrets only track the player while they can see them
+            if turret.map_or(false, |turret| !turret.sees_player) {
+                continue
+            }
+
+            let x  = transform.looking_at(direction, Vec3::new(0.0,1.0,0.0));
+            let angle = transform.rotation.angle_between(x.rotation);
+            let max_turn = pointing.turn_rate * time.delta_seconds();
+            transform.rotation = if angle <= max_turn {
+                x.rotation
+            } else {
+                transform.rotation.slerp(x.rotation, max_turn / angle)
+            };
         }
     }
-}
\ No newline at end of file
+}
\ No newline at end of file
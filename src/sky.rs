@@ -0,0 +1,209 @@
+pub(crate) mod sky {
+    //! An optional procedural sky with a day/night cycle.
+    //!
+    //! Levels with a `sky` get a big sphere around the camera drawn with [`SkyMaterial`]: a
+    //! gradient, the sun and stars. The level's sun light follows [`TimeOfDay`], going orange at
+    //! sunrise and sunset, and at night a dim blue moonlight comes from the other side. At night
+    //! the sky fades out to show the skybox behind it, unless the level asks for it to be
+    //! replaced, in which case the sky draws its own stars.
+    //!
+    //! `TimeOfDay` is reflected, so with the `debug` argument the time can be scrubbed or paused
+    //! from the world inspector.
+
+    use std::f32::consts::PI;
+    use bevy::{
+        pbr::{MaterialPipeline, MaterialPipelineKey, NotShadowCaster, NotShadowReceiver},
+        prelude::*,
+        reflect::{TypePath, TypeUuid},
+        render::{
+            mesh::MeshVertexBufferLayout,
+            render_resource::{AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError},
+        },
+    };
+    use serde::{Serialize, Deserialize};
+
+    use crate::level::level::CurrentLevel;
+
+    pub(crate) const SKY_SHADER_HANDLE: HandleUntyped = HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x5c4b_1e2d_9a7f_3e01);
+
+    // Has to be inside the camera's far plane (1000 by default)
+    const SKY_RADIUS:f32 = 900.0;
+    // How far the sun's path is tilted from straight overhead, in degrees
+    const SUN_TILT:f32 = 30.0;
+    const SUNSET_COLOR:Color = Color::rgb(1.0, 0.55, 0.3);
+    const MOON_COLOR:Color = Color::rgb(0.55, 0.65, 1.0);
+    // Moonlight is this fraction of the sun's illuminance
+    const MOON_BRIGHTNESS:f32 = 0.08;
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct SkyDef {
+        /// Real seconds in a whole day
+        #[serde(default = "default_day_length")]
+        pub(crate) day_length: f32,
+        /// Hour the level starts at, from 0 to 24
+        #[serde(default = "default_start_hour")]
+        pub(crate) start_hour: f32,
+        /// Hide the skybox completely, instead of showing it through the sky at night
+        #[serde(default)]
+        pub(crate) replace_skybox: bool,
+    }
+
+    fn default_day_length() -> f32 { 600.0 }
+    fn default_start_hour() -> f32 { 10.0 }
+
+    /// The clock the sky and sun follow. Only ticks while playing.
+    #[derive(Resource, Reflect)]
+    #[reflect(Resource)]
+    pub(crate) struct TimeOfDay {
+        /// From 0 to 24
+        pub(crate) hour: f32,
+        /// Real seconds in a whole day
+        pub(crate) day_length: f32,
+        pub(crate) paused: bool,
+    }
+
+    impl TimeOfDay {
+        /// Direction towards the sun. It rises in the east (+x) at 6 and is highest at 12.
+        fn sun_direction(&self) -> Vec3 {
+            let angle = (self.hour - 6.0) / 24.0 * 2.0 * PI;
+            let tilt = SUN_TILT.to_radians();
+            Vec3::new(angle.cos(), angle.sin() * tilt.cos(), angle.sin() * tilt.sin())
+        }
+    }
+
+    /// The level's sun, which the day/night cycle moves
+    #[derive(Component)]
+    pub(crate) struct Sun {
+        /// Illuminance at noon
+        pub(crate) illuminance: f32,
+    }
+
+    #[derive(AsBindGroup, TypeUuid, TypePath, Debug, Clone)]
+    #[uuid = "8f4a3c52-7d1e-4b9a-a6c3-2e5f0d9b7a14"]
+    pub(crate) struct SkyMaterial {
+        #[uniform(0)]
+        sun_direction: Vec3,
+        /// 0 at night, 1 during the day
+        #[uniform(0)]
+        day: f32,
+        /// 0 shows the skybox through the sky at night, 1 hides it
+        #[uniform(0)]
+        night_opacity: f32,
+    }
+
+    impl Material for SkyMaterial {
+        fn fragment_shader() -> ShaderRef {
+            SKY_SHADER_HANDLE.typed().into()
+        }
+
+        fn alpha_mode(&self) -> AlphaMode {
+            AlphaMode::Blend
+        }
+
+        // Transparent things are drawn back to front, and the sky has to come first
+        fn depth_bias(&self) -> f32 {
+            -10.0 * SKY_RADIUS
+        }
+
+        fn specialize(
+            _pipeline: &MaterialPipeline<Self>,
+            descriptor: &mut RenderPipelineDescriptor,
+            _layout: &MeshVertexBufferLayout,
+            _key: MaterialPipelineKey<Self>,
+        ) -> Result<(), SpecializedMeshPipelineError> {
+            // Seen from the inside
+            descriptor.primitive.cull_mode = None;
+            Ok(())
+        }
+    }
+
+    #[derive(Component)]
+    pub(crate) struct SkyDome;
+
+    pub(crate) fn load_sky_shader(mut shaders: ResMut<Assets<Shader>>) {
+        shaders.set_untracked(SKY_SHADER_HANDLE, Shader::from_wgsl(include_str!("sky.wgsl"), "sky.wgsl"));
+    }
+
+    pub(crate) fn setup_sky(
+        mut commands: Commands,
+        level: Res<CurrentLevel>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut materials: ResMut<Assets<SkyMaterial>>,
+    ) {
+        let Some(sky) = &level.0.sky else { return };
+        let time = TimeOfDay { hour: sky.start_hour.rem_euclid(24.0), day_length: sky.day_length, paused: false };
+
+        commands.spawn(MaterialMeshBundle {
+            mesh: meshes.add(Mesh::from(shape::UVSphere { radius: SKY_RADIUS, sectors: 32, stacks: 16 })),
+            material: materials.add(SkyMaterial {
+                sun_direction: time.sun_direction(),
+                day: 1.0,
+                night_opacity: if sky.replace_skybox { 1.0 } else { 0.0 },
+            }),
+            ..default()
+        })  .insert((NotShadowCaster, NotShadowReceiver))
+            .insert(SkyDome)
+            .insert(Name::new("Sky"));
+
+        commands.insert_resource(time);
+    }
+
+    pub(crate) fn advance_time_of_day(time: Res<Time>, mut time_of_day: ResMut<TimeOfDay>) {
+        if time_of_day.paused {
+            return
+        }
+        let hours = 24.0 * time.delta_seconds() / time_of_day.day_length.max(1.0);
+        time_of_day.hour = (time_of_day.hour + hours).rem_euclid(24.0);
+    }
+
+    /**
+     * Moves the sun and updates the sky to match the time of day, and keeps the sky around the camera
+     */
+    pub(crate) fn update_sky(
+        time_of_day: Res<TimeOfDay>,
+        mut materials: ResMut<Assets<SkyMaterial>>,
+        mut suns: Query<(&mut Transform, &mut DirectionalLight, &Sun)>,
+        mut domes: Query<(&mut Transform, &Handle<SkyMaterial>), (With<SkyDome>, Without<Sun>)>,
+        cameras: Query<&GlobalTransform, With<Camera3d>>,
+    ) {
+        let sun_direction = time_of_day.sun_direction();
+        let day = smoothstep(-0.1, 0.15, sun_direction.y);
+
+        for (mut transform, mut light, sun) in &mut suns {
+            // The light points away from the sun, or from the moon at night
+            let (light_direction, color, illuminance) = if sun_direction.y >= 0.0 {
+                let color = lerp_color(SUNSET_COLOR, Color::WHITE, smoothstep(0.0, 0.4, sun_direction.y));
+                (-sun_direction, color, sun.illuminance * day)
+            } else {
+                (sun_direction, MOON_COLOR, sun.illuminance * MOON_BRIGHTNESS * smoothstep(0.0, 0.2, -sun_direction.y))
+            };
+            transform.look_to(light_direction, Vec3::Y);
+            light.color = color;
+            light.illuminance = illuminance;
+        }
+
+        let camera = cameras.get_single().map(|camera| camera.translation()).unwrap_or_default();
+        for (mut transform, material) in &mut domes {
+            transform.translation = camera;
+            if let Some(material) = materials.get_mut(material) {
+                material.sun_direction = sun_direction;
+                material.day = day;
+            }
+        }
+    }
+
+    fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+        let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+        let (from, to) = (from.as_rgba_f32(), to.as_rgba_f32());
+        Color::rgba(
+            from[0] + (to[0] - from[0]) * t,
+            from[1] + (to[1] - from[1]) * t,
+            from[2] + (to[2] - from[2]) * t,
+            1.0,
+        )
+    }
+}
@@ -0,0 +1,59 @@
+// The procedural sky: a gradient, a sun disk with a glow that turns orange near the horizon,
+// and stars at night. Drawn on the inside of a sphere around the camera.
+
+#import bevy_pbr::mesh_vertex_output MeshVertexOutput
+
+struct SkyMaterial {
+    // Towards the sun
+    sun_direction: vec3<f32>,
+    // 0 at night, 1 during the day
+    day: f32,
+    // How much of the skybox is hidden at night. 0 shows it through, 1 replaces it.
+    night_opacity: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> material: SkyMaterial;
+
+const DAY_ZENITH: vec3<f32> = vec3<f32>(0.15, 0.35, 0.75);
+const DAY_HORIZON: vec3<f32> = vec3<f32>(0.6, 0.75, 0.9);
+const NIGHT_ZENITH: vec3<f32> = vec3<f32>(0.002, 0.004, 0.012);
+const NIGHT_HORIZON: vec3<f32> = vec3<f32>(0.015, 0.02, 0.045);
+const SUNSET: vec3<f32> = vec3<f32>(1.0, 0.45, 0.2);
+
+fn hash(p: vec3<f32>) -> f32 {
+    let q = fract(p * 0.3183099 + vec3<f32>(0.1, 0.2, 0.3)) * 17.0;
+    return fract(q.x * q.y * q.z * (q.x + q.y + q.z));
+}
+
+@fragment
+fn fragment(mesh: MeshVertexOutput) -> @location(0) vec4<f32> {
+    // The sphere is centered on the camera, so its normals point along the view ray
+    let dir = normalize(mesh.world_normal);
+    let sun = normalize(material.sun_direction);
+    let up = max(dir.y, 0.0);
+
+    let day_sky = mix(DAY_HORIZON, DAY_ZENITH, sqrt(up));
+    let night_sky = mix(NIGHT_HORIZON, NIGHT_ZENITH, sqrt(up));
+    var color = mix(night_sky, day_sky, material.day);
+
+    // Glow around the sun, strongest when it's low
+    let towards_sun = max(dot(dir, sun), 0.0);
+    let low_sun = 1.0 - smoothstep(0.0, 0.4, abs(sun.y));
+    color += SUNSET * pow(towards_sun, 6.0) * low_sun * 0.8;
+    color += vec3<f32>(1.0, 0.9, 0.7) * pow(towards_sun, 64.0) * material.day * 0.5;
+
+    // The sun itself, bright enough to bloom
+    let disk = smoothstep(0.9995, 0.9998, dot(dir, sun));
+    color += vec3<f32>(20.0, 18.0, 14.0) * disk;
+
+    // Stars, only above the horizon and only at night
+    let star = step(0.9985, hash(floor(dir * 300.0)));
+    color += vec3<f32>(1.5) * star * (1.0 - material.day) * smoothstep(0.0, 0.1, dir.y);
+
+    // Darker below the horizon, like there's ground there
+    color *= mix(0.3, 1.0, smoothstep(-0.2, 0.0, dir.y));
+
+    let alpha = mix(material.night_opacity, 1.0, material.day);
+    return vec4<f32>(color, alpha);
+}
@@ -1,261 +1,409 @@
-pub(crate) mod skybox {
-    //! Load a cubemap texture onto a cube like a skybox and cycle through different compressed texture formats
-    use bevy::{
-        asset::LoadState,
-        pbr::{MaterialPipeline, MaterialPipelineKey},
-        prelude::*,
-        reflect::TypeUuid,
-        render::{
-            mesh::MeshVertexBufferLayout,
-            render_asset::RenderAssets,
-            render_resource::{
-                AsBindGroup, AsBindGroupError, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
-                BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType,
-                OwnedBindingResource, PreparedBindGroup, RenderPipelineDescriptor, SamplerBindingType,
-                ShaderRef, ShaderStages, SpecializedMeshPipelineError, TextureSampleType,
-                TextureViewDescriptor, TextureViewDimension,
-            },
-            renderer::RenderDevice,
-            texture::{CompressedImageFormats, FallbackImage},
-        },
-    };
-
-    const CUBEMAPS: &[(&str, CompressedImageFormats)] = &[
-        (
-            "textures/space-skybox-k.png",
-            CompressedImageFormats::NONE,
-        )
-    ];
-
-/*fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .add_plugin(MaterialPlugin::<CubemapMaterial>::default())
-        .add_startup_system(setup_cubebox)
-        .add_system(cycle_cubemap_asset)
-        .add_system(asset_loaded.after(cycle_cubemap_asset))
-        .add_system(camera_controller)
-        .add_system(animate_light_direction)
-        .run();
-}*/
-    #[derive(Resource)]
-    pub(crate) struct Cubemap {
-        is_loaded: bool,
-        index: usize,
-        image_handle: Handle<Image>,
-    }
-
-    pub(crate) fn setup_cubebox(mut commands: Commands, asset_server: Res<AssetServer>) {
-
-        let skybox_handle = asset_server.load(CUBEMAPS[0].0);
-
-        commands.insert_resource(Cubemap {
-            is_loaded: false,
-            index: 0,
-            image_handle: skybox_handle,
-        });
-    }
-
-    const CUBEMAP_SWAP_DELAY: f32 = 3.0;
-
-    pub(crate) fn cycle_cubemap_asset(
-        time: Res<Time>,
-        mut next_swap: Local<f32>,
-        mut cubemap: ResMut<Cubemap>,
-        asset_server: Res<AssetServer>,
-        render_device: Res<RenderDevice>,
-    ) {
-        let now = time.delta_seconds();
-        if *next_swap == 0.0 {
-            *next_swap = now + CUBEMAP_SWAP_DELAY;
-            return;
-        } else if now < *next_swap {
-            return;
-        }
-        *next_swap += CUBEMAP_SWAP_DELAY;
-
-        let supported_compressed_formats =
-            CompressedImageFormats::from_features(render_device.features());
-
-        let mut new_index = cubemap.index;
-        for _ in 0..CUBEMAPS.len() {
-            new_index = (new_index + 1) % CUBEMAPS.len();
-            if supported_compressed_formats.contains(CUBEMAPS[new_index].1) {
-                break;
-            }
-            info!("Skipping unsupported format: {:?}", CUBEMAPS[new_index]);
-        }
-
-        // Skip swapping to the same texture. Useful for when ktx2, zstd, or compressed texture support
-        // is missing
-        if new_index == cubemap.index {
-            return;
-        }
-
-        cubemap.index = new_index;
-        cubemap.image_handle = asset_server.load(CUBEMAPS[cubemap.index].0);
-        cubemap.is_loaded = false;
-    }
-
-    pub(crate) fn asset_loaded(
-        mut commands: Commands,
-        asset_server: Res<AssetServer>,
-        mut images: ResMut<Assets<Image>>,
-        mut meshes: ResMut<Assets<Mesh>>,
-        mut cubemap_materials: ResMut<Assets<CubemapMaterial>>,
-        mut cubemap: ResMut<Cubemap>,
-        cubes: Query<&Handle<CubemapMaterial>>,
-    ) {
-        if !cubemap.is_loaded
-            && asset_server.get_load_state(cubemap.image_handle.clone_weak()) == LoadState::Loaded
-        {
-            info!("Swapping to {}...", CUBEMAPS[cubemap.index].0);
-            let mut image = images.get_mut(&cubemap.image_handle).unwrap();
-            // NOTE: PNGs do not have any metadata that could indicate they contain a cubemap texture,
-            // so they appear as one texture. The following code reconfigures the texture as necessary.
-            if image.texture_descriptor.array_layer_count() == 1 {
-                image.reinterpret_stacked_2d_as_array(
-                    image.texture_descriptor.size.height / image.texture_descriptor.size.width,
-                );
-                image.texture_view_descriptor = Some(TextureViewDescriptor {
-                    dimension: Some(TextureViewDimension::Cube),
-                    ..default()
-                });
-            }
-
-            // spawn cube
-            let mut updated = false;
-            for handle in cubes.iter() {
-                if let Some(material) = cubemap_materials.get_mut(handle) {
-                    updated = true;
-                    material.base_color_texture = Some(cubemap.image_handle.clone_weak());
-                }
-            }
-            if !updated {
-                commands.spawn(MaterialMeshBundle::<CubemapMaterial> {
-                    mesh: meshes.add(Mesh::from(shape::Cube { size: 10000.0 })),
-                    material: cubemap_materials.add(CubemapMaterial {
-                        base_color_texture: Some(cubemap.image_handle.clone_weak()),
-                    }),
-                    ..default()
-                });
-            }
-
-            cubemap.is_loaded = true;
-        }
-    }
-
-    #[derive(Debug, Clone, TypeUuid)]
-    #[uuid = "9509a0f8-3c05-48ee-a13e-a93226c7f488"]
-    pub(crate) struct CubemapMaterial {
-        base_color_texture: Option<Handle<Image>>,
-    }
-
-    impl bevy::reflect::TypePath for CubemapMaterial {
-        fn type_path() -> &'static str {
-            todo!()
-        }
-
-        fn type_ident() -> Option<&'static str> {
-            None
-        }
-
-        fn crate_name() -> Option<&'static str> {
-            None
-        }
-
-        fn module_path() -> Option<&'static str> {
-            None
-        }
-
-        fn short_type_path() -> &'static str {
-            todo!()
-        }
-    }
-
-    impl Material for CubemapMaterial {
-        fn fragment_shader() -> ShaderRef {
-            "shaders/cubemap_unlit.wgsl".into()
-        }
-
-        fn specialize(
-            _pipeline: &MaterialPipeline<Self>,
-            descriptor: &mut RenderPipelineDescriptor,
-            _layout: &MeshVertexBufferLayout,
-            _key: MaterialPipelineKey<Self>,
-        ) -> Result<(), SpecializedMeshPipelineError> {
-            descriptor.primitive.cull_mode = None;
-            Ok(())
-        }
-    }
-
-    impl AsBindGroup for CubemapMaterial {
-        type Data = ();
-
-        fn as_bind_group(
-            &self,
-            layout: &BindGroupLayout,
-            render_device: &RenderDevice,
-            images: &RenderAssets<Image>,
-            _fallback_image: &FallbackImage,
-        ) -> Result<PreparedBindGroup<()>, AsBindGroupError> {
-            let base_color_texture = self
-                .base_color_texture
-                .as_ref()
-                .ok_or(AsBindGroupError::RetryNextUpdate)?;
-            let image = images
-                .get(base_color_texture)
-                .ok_or(AsBindGroupError::RetryNextUpdate)?;
-            let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-                entries: &[
-                    BindGroupEntry {
-                        binding: 0,
-                        resource: BindingResource::TextureView(&image.texture_view),
-                    },
-                    BindGroupEntry {
-                        binding: 1,
-                        resource: BindingResource::Sampler(&image.sampler),
-                    },
-                ],
-                label: Some("cubemap_texture_material_bind_group"),
-                layout,
-            });
-
-            Ok(PreparedBindGroup {
-                bind_group,
-                bindings: vec![
-                    OwnedBindingResource::TextureView(image.texture_view.clone()),
-                    OwnedBindingResource::Sampler(image.sampler.clone()),
-                ],
-                data: (),
-            })
-        }
-
-        fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
-            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[
-                    // Cubemap Base Color Texture
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Texture {
-                            multisampled: false,
-                            sample_type: TextureSampleType::Float { filterable: true },
-                            view_dimension: TextureViewDimension::Cube,
-                        },
-                        count: None,
-                    },
-                    // Cubemap Base Color Texture Sampler
-                    BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: ShaderStages::FRAGMENT,
-                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: None,
-            })
-        }    
-    }
-
-}
\ No newline at end of file
+pub(crate) mod skybox {
+    //! Skyboxes for any camera, from whatever layout the image is in.
+    //!
+    //! [`SkyboxPlugin`] does everything: send a [`RequestSkybox`] with an asset path and the image
+    //! is loaded, turned into a cubemap and put behind every 3D camera. The layout is worked out
+    //! from the loaded image rather than the file name:
+    //! - KTX2 (and other formats that know about cubemaps) load with six layers, ready to use.
+    //! - Images six times taller than they are wide are the faces stacked on top of each other,
+    //!   and only need to be reinterpreted as an array.
+    //! - Images twice as wide as they are tall are equirectangular (usually `.hdr`). They're
+    //!   projected onto the six faces on a background thread, so loading one doesn't stall a frame.
+    //!
+    //! The camera keeps showing the old skybox until the new one is ready, so swapping is seamless,
+    //! and saving the image while the game runs swaps it the same way.
+    //!
+    //! Where bevy's `Skybox` isn't supported (WebGL2), the cubemap is drawn with
+    //! [`CubemapMaterial`] on a cube around the camera instead.
+
+    use std::f32::consts::PI;
+    use bevy::{
+        asset::LoadState,
+        core_pipeline::Skybox,
+        pbr::{MaterialPipeline, MaterialPipelineKey, NotShadowCaster, NotShadowReceiver},
+        prelude::*,
+        reflect::{TypePath, TypeUuid},
+        render::{
+            mesh::MeshVertexBufferLayout,
+            render_resource::{AsBindGroup, Extent3d, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+                TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension},
+            view::NoFrustumCulling,
+        },
+        tasks::{AsyncComputeTaskPool, Task},
+    };
+    use futures_lite::future;
+
+    pub(crate) const CUBEMAP_SHADER_HANDLE: HandleUntyped = HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x2e91_7c3a_b04d_6f15);
+
+    // Faces made from equirectangular images are at most this many pixels across
+    const MAX_FACE_SIZE:u32 = 1024;
+
+    pub(crate) struct SkyboxPlugin {
+        /// Draw skyboxes with [`CubemapMaterial`] instead of bevy's `Skybox`
+        pub(crate) use_material: bool,
+    }
+
+    impl Default for SkyboxPlugin {
+        fn default() -> Self {
+            SkyboxPlugin { use_material: cfg!(target_arch = "wasm32") }
+        }
+    }
+
+    impl Plugin for SkyboxPlugin {
+        fn build(&self, app: &mut App) {
+            app
+                .insert_resource(SkyboxFallback(self.use_material))
+                .init_resource::<Cubemap>()
+                .add_event::<RequestSkybox>()
+                .add_plugins(MaterialPlugin::<CubemapMaterial>::default())
+                .add_systems(Startup, load_cubemap_shader)
+                .add_systems(Update, (request_skybox, reload_skybox, asset_loaded).chain());
+        }
+    }
+
+    /// Shows the image at this asset path as the skybox, once it's loaded
+    #[derive(Event)]
+    pub(crate) struct RequestSkybox(pub(crate) String);
+
+    #[derive(Resource)]
+    struct SkyboxFallback(bool);
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum SkyboxLayout {
+        Cube,
+        Stacked,
+        Equirectangular,
+    }
+
+    impl SkyboxLayout {
+        fn detect(image: &Image) -> Option<SkyboxLayout> {
+            let size = image.texture_descriptor.size;
+            if size.depth_or_array_layers == 6 {
+                Some(SkyboxLayout::Cube)
+            } else if size.height == 6 * size.width {
+                Some(SkyboxLayout::Stacked)
+            } else if size.width == 2 * size.height {
+                Some(SkyboxLayout::Equirectangular)
+            } else {
+                None
+            }
+        }
+    }
+
+    #[derive(Resource, Default)]
+    pub(crate) struct Cubemap {
+        /// Whether `source` has been turned into a cubemap and put on the camera
+        pub(crate) is_loaded: bool,
+        /// The image that was asked for
+        pub(crate) source: Option<Handle<Image>>,
+        /// The cubemap on the camera. The same as `source`, unless it had to be converted.
+        pub(crate) image_handle: Option<Handle<Image>>,
+        /// Goes up every time a cubemap is put on the camera, reloads included
+        pub(crate) version: u32,
+        conversion: Option<Task<Image>>,
+        /// `asset_loaded` changed `source` itself, so the next change to it isn't a reload
+        edited_source: bool,
+    }
+
+    impl Cubemap {
+        /// Starts loading a different skybox. The current one stays up until it's ready.
+        fn request(&mut self, source: Handle<Image>) {
+            self.is_loaded = false;
+            self.source = Some(source);
+            self.edited_source = false;
+            // Dropping a task cancels it
+            self.conversion = None;
+        }
+    }
+
+    /// Draws a cubemap on a cube centered on the camera, behind everything else
+    #[derive(AsBindGroup, TypeUuid, TypePath, Debug, Clone)]
+    #[uuid = "9509a0f8-3c05-48ee-a13e-a93226c7f488"]
+    pub(crate) struct CubemapMaterial {
+        #[texture(0, dimension = "cube")]
+        #[sampler(1)]
+        cubemap: Handle<Image>,
+    }
+
+    impl Material for CubemapMaterial {
+        fn vertex_shader() -> ShaderRef {
+            CUBEMAP_SHADER_HANDLE.typed().into()
+        }
+
+        fn fragment_shader() -> ShaderRef {
+            CUBEMAP_SHADER_HANDLE.typed().into()
+        }
+
+        fn specialize(
+            _pipeline: &MaterialPipeline<Self>,
+            descriptor: &mut RenderPipelineDescriptor,
+            _layout: &MeshVertexBufferLayout,
+            _key: MaterialPipelineKey<Self>,
+        ) -> Result<(), SpecializedMeshPipelineError> {
+            // Seen from the inside
+            descriptor.primitive.cull_mode = None;
+            Ok(())
+        }
+    }
+
+    #[derive(Component)]
+    struct SkyboxCube;
+
+    fn load_cubemap_shader(mut shaders: ResMut<Assets<Shader>>) {
+        shaders.set_untracked(CUBEMAP_SHADER_HANDLE, Shader::from_wgsl(include_str!("skybox.wgsl"), "skybox.wgsl"));
+    }
+
+    fn request_skybox(
+        asset_server: Res<AssetServer>,
+        mut cubemap: ResMut<Cubemap>,
+        mut requests: EventReader<RequestSkybox>,
+    ) {
+        // Only the latest request matters
+        if let Some(RequestSkybox(path)) = requests.iter().last() {
+            cubemap.request(asset_server.load(path));
+        }
+    }
+
+    /**
+     * Starts over when the skybox's file changes on disk
+     */
+    fn reload_skybox(mut cubemap: ResMut<Cubemap>, mut image_events: EventReader<AssetEvent<Image>>) {
+        for ev in image_events.iter() {
+            let AssetEvent::Modified { handle } = ev else { continue };
+            if cubemap.source.as_ref() != Some(handle) {
+                continue
+            }
+            if cubemap.edited_source {
+                cubemap.edited_source = false;
+                continue
+            }
+            info!("Reloading skybox");
+            cubemap.is_loaded = false;
+            cubemap.conversion = None;
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn asset_loaded(
+        mut commands: Commands,
+        asset_server: Res<AssetServer>,
+        fallback: Res<SkyboxFallback>,
+        mut images: ResMut<Assets<Image>>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut materials: ResMut<Assets<CubemapMaterial>>,
+        mut cubemap: ResMut<Cubemap>,
+        cameras: Query<Entity, With<Camera3d>>,
+        skybox_cubes: Query<&Handle<CubemapMaterial>, With<SkyboxCube>>,
+    ) {
+        if cubemap.is_loaded {
+            return
+        }
+        let Some(source) = cubemap.source.clone() else { return };
+
+        let cube = if let Some(task) = &mut cubemap.conversion {
+            let Some(image) = future::block_on(future::poll_once(task)) else { return };
+            cubemap.conversion = None;
+            images.add(image)
+        } else {
+            if asset_server.get_load_state(source.id()) != LoadState::Loaded {
+                return
+            }
+            let path = asset_server.get_handle_path(&source);
+            let Some(image) = images.get(&source) else { return };
+            let layout = SkyboxLayout::detect(image);
+            match layout {
+                Some(SkyboxLayout::Cube) | Some(SkyboxLayout::Stacked) => {},
+                Some(SkyboxLayout::Equirectangular) if !matches!(image.texture_descriptor.format,
+                    TextureFormat::Rgba32Float | TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm) => {
+                    println!("WARNING! CAN'T CONVERT SKYBOX {:?} FROM {:?}, KEEPING THE OLD ONE", path, image.texture_descriptor.format);
+                    cubemap.is_loaded = true;
+                    return
+                },
+                Some(SkyboxLayout::Equirectangular) => {
+                    let image = image.clone();
+                    cubemap.conversion = Some(AsyncComputeTaskPool::get().spawn(async move { equirectangular_to_cube(&image) }));
+                    return
+                },
+                None => {
+                    println!("WARNING! SKYBOX {:?} ISN'T A CUBEMAP, A STACKED STRIP OR EQUIRECTANGULAR, KEEPING THE OLD ONE", path);
+                    cubemap.is_loaded = true;
+                    return
+                },
+            }
+
+            // Changing the image sends a modified event, which mustn't be taken for a reload
+            cubemap.edited_source = true;
+            let Some(image) = images.get_mut(&source) else { return };
+            if layout == Some(SkyboxLayout::Stacked) {
+                // NOTE: PNGs do not have any metadata that could indicate they contain a cubemap texture,
+                // so they appear as one texture. The following code reconfigures the texture as necessary.
+                image.reinterpret_stacked_2d_as_array(6);
+            }
+            image.texture_view_descriptor = Some(TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::Cube),
+                ..default()
+            });
+            source
+        };
+
+        info!("Swapping skybox");
+        if fallback.0 {
+            if let Ok(material) = skybox_cubes.get_single() {
+                if let Some(material) = materials.get_mut(material) {
+                    material.cubemap = cube.clone();
+                }
+            } else {
+                commands.spawn(MaterialMeshBundle {
+                    mesh: meshes.add(Mesh::from(shape::Cube { size: 2.0 })),
+                    material: materials.add(CubemapMaterial { cubemap: cube.clone() }),
+                    ..default()
+                })  .insert((NotShadowCaster, NotShadowReceiver))
+                    // The shader moves it to the camera, wherever the entity is
+                    .insert(NoFrustumCulling)
+                    .insert(SkyboxCube)
+                    .insert(Name::new("Skybox"));
+            }
+        } else {
+            for camera in &cameras {
+                commands.entity(camera).insert(Skybox(cube.clone()));
+            }
+        }
+        cubemap.image_handle = Some(cube);
+        cubemap.version += 1;
+        cubemap.is_loaded = true;
+    }
+
+    /// Direction through a point on a cube face, with `u` and `v` from -1 to 1. Faces are in
+    /// wgpu's order: +X, -X, +Y, -Y, +Z, -Z.
+    pub(crate) fn face_direction(face: usize, u: f32, v: f32) -> Vec3 {
+        match face {
+            0 => Vec3::new(1.0, -v, -u),
+            1 => Vec3::new(-1.0, -v, u),
+            2 => Vec3::new(u, 1.0, v),
+            3 => Vec3::new(u, -1.0, -v),
+            4 => Vec3::new(u, -v, 1.0),
+            _ => Vec3::new(-u, -v, -1.0),
+        }
+    }
+
+    fn read_texel(image: &Image, x: u32, y: u32) -> Vec4 {
+        let width = image.texture_descriptor.size.width;
+        match image.texture_descriptor.format {
+            TextureFormat::Rgba32Float => {
+                let start = 16 * (y * width + x) as usize;
+                let bytes = &image.data[start..start + 16];
+                let channel = |i: usize| f32::from_le_bytes([bytes[4 * i], bytes[4 * i + 1], bytes[4 * i + 2], bytes[4 * i + 3]]);
+                Vec4::new(channel(0), channel(1), channel(2), channel(3))
+            },
+            // 8 bit formats are kept as they are, sRGB or not
+            _ => {
+                let start = 4 * (y * width + x) as usize;
+                let bytes = &image.data[start..start + 4];
+                Vec4::new(bytes[0] as f32, bytes[1] as f32, bytes[2] as f32, bytes[3] as f32) / 255.0
+            },
+        }
+    }
+
+    /// Bilinear sample of an equirectangular image in direction `dir`
+    fn sample_equirectangular(image: &Image, dir: Vec3) -> Vec4 {
+        let size = image.texture_descriptor.size;
+        let longitude = dir.z.atan2(dir.x);
+        let latitude = dir.y.clamp(-1.0, 1.0).acos();
+        let x = (0.5 + longitude / (2.0 * PI)) * size.width as f32 - 0.5;
+        let y = (latitude / PI * size.height as f32 - 0.5).clamp(0.0, (size.height - 1) as f32);
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        // Wraps around horizontally, clamps at the poles
+        let column = |x: f32| (x as i32).rem_euclid(size.width as i32) as u32;
+        let (left, right) = (column(x0), column(x0 + 1.0));
+        let (top, bottom) = (y0 as u32, (y0 as u32 + 1).min(size.height - 1));
+
+        let upper = read_texel(image, left, top).lerp(read_texel(image, right, top), tx);
+        let lower = read_texel(image, left, bottom).lerp(read_texel(image, right, bottom), tx);
+        upper.lerp(lower, ty)
+    }
+
+    /// Packs an HDR color into `Rgb9e5Ufloat`, which is a quarter of the size of `Rgba32Float`
+    pub(crate) fn pack_rgb9e5(color: Vec3) -> u32 {
+        // Largest value the format can hold: (2^9 - 1) / 2^9 * 2^15
+        const MAX:f32 = 65408.0;
+        let color = color.clamp(Vec3::ZERO, Vec3::splat(MAX));
+        let max_channel = color.max_element();
+
+        let mut exponent = (max_channel.log2().floor() as i32).max(-16) + 16;
+        let mut scale = 2f32.powi(exponent - 24);
+        if (max_channel / scale + 0.5).floor() as u32 == 512 {
+            exponent += 1;
+            scale *= 2.0;
+        }
+        let mantissa = |channel: f32| ((channel / scale + 0.5).floor() as u32).min(511);
+
+        (exponent as u32) << 27 | mantissa(color.z) << 18 | mantissa(color.y) << 9 | mantissa(color.x)
+    }
+
+    /**
+     * Projects an equirectangular image onto the six faces of a cubemap. Float images come out
+     * as `Rgb9e5Ufloat`, 8 bit ones in their own format.
+     */
+    fn equirectangular_to_cube(image: &Image) -> Image {
+        let face_size = (image.texture_descriptor.size.height / 2).clamp(1, MAX_FACE_SIZE);
+        let hdr = image.texture_descriptor.format == TextureFormat::Rgba32Float;
+        let format = if hdr { TextureFormat::Rgb9e5Ufloat } else { image.texture_descriptor.format };
+
+        let mut data = Vec::with_capacity(4 * 6 * (face_size * face_size) as usize);
+        for face in 0..6 {
+            for y in 0..face_size {
+                for x in 0..face_size {
+                    let u = 2.0 * (x as f32 + 0.5) / face_size as f32 - 1.0;
+                    let v = 2.0 * (y as f32 + 0.5) / face_size as f32 - 1.0;
+                    let color = sample_equirectangular(image, face_direction(face, u, v).normalize());
+                    if hdr {
+                        data.extend_from_slice(&pack_rgb9e5(color.truncate()).to_le_bytes());
+                    } else {
+                        data.extend((color * 255.0).round().to_array().map(|channel| channel as u8));
+                    }
+                }
+            }
+        }
+
+        let mut cube = Image::new(
+            Extent3d { width: face_size, height: face_size, depth_or_array_layers: 6 },
+            TextureDimension::D2,
+            data,
+            format,
+        );
+        cube.texture_view_descriptor = Some(TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::Cube),
+            ..default()
+        });
+        cube
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn rgb9e5_packs_exact_values() {
+            assert_eq!(pack_rgb9e5(Vec3::ZERO), 0);
+            // 256 * 2^(16 - 24) = 1
+            assert_eq!(pack_rgb9e5(Vec3::X), 16 << 27 | 256);
+            assert_eq!(pack_rgb9e5(Vec3::new(0.0, 0.5, 1.0)), 16 << 27 | 256 << 18 | 128 << 9);
+        }
+
+        #[test]
+        fn rgb9e5_clamps_out_of_range_colors() {
+            assert_eq!(pack_rgb9e5(Vec3::new(-1.0, -5.0, 0.0)), 0);
+            assert_eq!(pack_rgb9e5(Vec3::splat(1.0e9)), pack_rgb9e5(Vec3::splat(65408.0)));
+        }
+
+        #[test]
+        fn face_centers_point_along_the_axes() {
+            let axes = [Vec3::X, Vec3::NEG_X, Vec3::Y, Vec3::NEG_Y, Vec3::Z, Vec3::NEG_Z];
+            for (face, axis) in axes.into_iter().enumerate() {
+                assert_eq!(face_direction(face, 0.0, 0.0), axis);
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+// The skybox for backends without bevy's Skybox: a cube centered on the camera, pushed onto the
+// far plane so it's behind everything, showing a cubemap.
+
+#import bevy_pbr::mesh_view_bindings view
+
+@group(1) @binding(0)
+var cubemap: texture_cube<f32>;
+@group(1) @binding(1)
+var cubemap_sampler: sampler;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    // Around the camera whatever the entity's transform is, so only the direction matters
+    var clip_position = view.view_proj * vec4<f32>(view.world_position + vertex.position, 1.0);
+    // Depth is reversed, 0 is infinitely far away
+    clip_position.z = 0.0;
+
+    var out: VertexOutput;
+    out.clip_position = clip_position;
+    out.direction = vertex.position;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Cubemaps are left-handed, the same flip bevy's skybox does
+    return textureSample(cubemap, cubemap_sampler, in.direction * vec3<f32>(1.0, 1.0, -1.0));
+}
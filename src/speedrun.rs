@@ -0,0 +1,234 @@
+pub(crate) mod speedrun {
+    //! Goal zones, the run timer and personal bests.
+    //!
+    //! The timer starts on the first input and stops when the player touches the [`Goal`].
+    //! Each checkpoint reached on the way records a split. Personal bests are kept in
+    //! `records/<level name>.ron` (`records/<level name>-seed-<seed>.ron` for levels with generated
+    //! platforms), and compared against on the results screen. Leaving the results screen resets
+    //! the run.
+
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use bevy::input::gamepad::GamepadButtonChangedEvent;
+    use bevy::prelude::*;
+    use serde::{Serialize, Deserialize};
+
+    use crate::checkpoints::checkpoints::{CheckpointReached, KillPlayer, RespawnPoint};
+    use crate::collisions::collisions::{CollisionRole, PlayerTouched};
+    use crate::game_state::game_state::GameState;
+    use crate::level::level::{CurrentLevel, LevelDefinition};
+    use crate::menus::menus::{screen_root, spawn_button, MenuAction};
+    use crate::procgen::procgen::WorldSeed;
+
+    const RECORDS_DIR:&str = "records";
+
+    /// Sensor that ends the run when the player touches it
+    #[derive(Component)]
+    pub(crate) struct Goal;
+
+    #[derive(Default, PartialEq, Clone, Copy, Debug)]
+    pub(crate) enum RunState {
+        #[default]
+        NotStarted,
+        Running,
+        Finished,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+    pub(crate) struct Split {
+        pub(crate) checkpoint: usize,
+        pub(crate) time: f32,
+    }
+
+    #[derive(Resource, Default)]
+    pub(crate) struct RunTimer {
+        pub(crate) state: RunState,
+        /// Seconds since the first input
+        pub(crate) elapsed: f32,
+        pub(crate) splits: Vec<Split>,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub(crate) struct PersonalBest {
+        pub(crate) time: f32,
+        pub(crate) splits: Vec<Split>,
+    }
+
+    /// The personal best for the current level, if there is one
+    #[derive(Resource, Default)]
+    pub(crate) struct PersonalBests {
+        pub(crate) best: Option<PersonalBest>,
+        /// The best before the last run, so the results screen can compare against it
+        pub(crate) previous: Option<PersonalBest>,
+    }
+
+    /// Sent when the player reaches the goal
+    #[derive(Event)]
+    pub(crate) struct RunFinished {
+        pub(crate) time: f32,
+        pub(crate) new_best: bool,
+    }
+
+    #[derive(Component)]
+    pub(crate) struct ResultsScreen;
+
+    /// Formats seconds as m:ss.cc
+    pub(crate) fn format_time(seconds: f32) -> String {
+        let minutes = (seconds / 60.0).floor();
+        format!("{}:{:05.2}", minutes, seconds - minutes * 60.0)
+    }
+
+    /// Generated levels are a different layout for every seed, so each seed gets its own record
+    fn record_path(level: &LevelDefinition, seed: &WorldSeed) -> PathBuf {
+        let name = match level.procedural_platforms {
+            Some(_) => format!("{}-seed-{}", level.name, seed.0),
+            None => level.name.clone(),
+        };
+        PathBuf::from(RECORDS_DIR).join(format!("{}.ron", name))
+    }
+
+    pub(crate) fn load_personal_best(mut commands: Commands, level: Res<CurrentLevel>, seed: Res<WorldSeed>) {
+        let path = record_path(&level.0, &seed);
+        let best = match fs::read_to_string(&path) {
+            Ok(text) => ron::from_str(&text).ok(),
+            Err(_) => None,
+        };
+        commands.insert_resource(PersonalBests { best, previous: None });
+    }
+
+    fn save_personal_best(path: &Path, best: &PersonalBest) {
+        let serialized = ron::ser::to_string_pretty(best, ron::ser::PrettyConfig::default()).unwrap();
+
+        let written = fs::create_dir_all(RECORDS_DIR).and_then(|_| fs::write(path, serialized));
+        match written {
+            Ok(_) => println!("Saved new personal best to {}", path.display()),
+            Err(why) => println!("WARNING! FAILED TO SAVE PERSONAL BEST TO {}: {}", path.display(), why),
+        }
+    }
+
+    pub(crate) fn start_run_timer(
+        mut timer: ResMut<RunTimer>,
+        keyboard_input: Res<Input<KeyCode>>,
+        mouse: Res<Input<MouseButton>>,
+        mut gamepad_buttons: EventReader<GamepadButtonChangedEvent>,
+    ) {
+        let any_input = keyboard_input.get_just_pressed().next().is_some()
+            || mouse.get_just_pressed().next().is_some()
+            || gamepad_buttons.iter().any(|ev| ev.value > 0.5);
+
+        if timer.state == RunState::NotStarted && any_input {
+            timer.state = RunState::Running;
+        }
+    }
+
+    pub(crate) fn tick_run_timer(time: Res<Time>, mut timer: ResMut<RunTimer>) {
+        if timer.state == RunState::Running {
+            timer.elapsed += time.delta_seconds();
+        }
+    }
+
+    pub(crate) fn record_splits(mut timer: ResMut<RunTimer>, mut checkpoint_reached: EventReader<CheckpointReached>) {
+        for ev in checkpoint_reached.iter() {
+            if timer.state == RunState::Running {
+                let time = timer.elapsed;
+                timer.splits.push(Split { checkpoint: ev.index, time });
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn goal_collisions(
+        mut touched: EventReader<PlayerTouched>,
+        goals: Query<(), With<Goal>>,
+        mut timer: ResMut<RunTimer>,
+        mut bests: ResMut<PersonalBests>,
+        level: Res<CurrentLevel>,
+        seed: Res<WorldSeed>,
+        mut run_finished: EventWriter<RunFinished>,
+        mut next_state: ResMut<NextState<GameState>>,
+    ) {
+        for touch in touched.iter() {
+            if touch.kind != CollisionRole::Trigger || !goals.contains(touch.other) || timer.state != RunState::Running {
+                continue
+            }
+
+            timer.state = RunState::Finished;
+            let time = timer.elapsed;
+            info!("finished {} in {}", level.0.name, format_time(time));
+
+            bests.previous = bests.best.clone();
+            let new_best = bests.best.as_ref().map_or(true, |best| time < best.time);
+            if new_best {
+                let best = PersonalBest { time, splits: timer.splits.clone() };
+                save_personal_best(&record_path(&level.0, &seed), &best);
+                bests.best = Some(best);
+            }
+
+            run_finished.send(RunFinished { time, new_best });
+            next_state.set(GameState::Results);
+        }
+    }
+
+    pub(crate) fn show_results(
+        mut commands: Commands,
+        timer: Res<RunTimer>,
+        bests: Res<PersonalBests>,
+    ) {
+        let time = timer.elapsed;
+        let new_best = bests.previous.as_ref().map_or(true, |previous| time < previous.time);
+        let mut lines = vec![format!("Finished in {}", format_time(time))];
+
+        match (&bests.previous, new_best) {
+            (Some(previous), true) => lines.push(format!("New personal best! ({} faster)", format_time(previous.time - time))),
+            (None, _) => lines.push("New personal best!".to_string()),
+            (Some(previous), false) => lines.push(format!("Personal best {} (+{})", format_time(previous.time), format_time(time - previous.time))),
+        }
+
+        for split in &timer.splits {
+            let best_split = bests.previous.as_ref()
+                .and_then(|best| best.splits.iter().find(|s| s.checkpoint == split.checkpoint));
+            let line = match best_split {
+                Some(best_split) if split.time <= best_split.time => format!("Checkpoint {}: {} (-{})", split.checkpoint + 1, format_time(split.time), format_time(best_split.time - split.time)),
+                Some(best_split) => format!("Checkpoint {}: {} (+{})", split.checkpoint + 1, format_time(split.time), format_time(split.time - best_split.time)),
+                None => format!("Checkpoint {}: {}", split.checkpoint + 1, format_time(split.time)),
+            };
+            lines.push(line);
+        }
+        lines.push("Press R to run again".to_string());
+
+        commands.spawn(screen_root(Color::rgba(0.0, 0.0, 0.0, 0.6)))
+            .insert(ResultsScreen)
+            .insert(Name::new("Results screen"))
+            .with_children(|parent| {
+                for (i, line) in lines.into_iter().enumerate() {
+                    parent.spawn(TextBundle::from_section(line, TextStyle {
+                        font_size: if i == 0 { 48.0 } else { 24.0 },
+                        color: Color::WHITE,
+                        ..default()
+                    }));
+                }
+                spawn_button(parent, "Run again", MenuAction::RunAgain);
+                spawn_button(parent, "Main menu", MenuAction::MainMenu);
+            });
+    }
+
+    pub(crate) fn run_again_on_r(keyboard_input: Res<Input<KeyCode>>, mut next_state: ResMut<NextState<GameState>>) {
+        if keyboard_input.just_pressed(KeyCode::R) {
+            next_state.set(GameState::Playing);
+        }
+    }
+
+    /**
+     * Starts a new run from the level spawn. Runs when leaving the results screen.
+     */
+    pub(crate) fn restart_run(
+        mut timer: ResMut<RunTimer>,
+        level: Res<CurrentLevel>,
+        mut respawn_point: ResMut<RespawnPoint>,
+        mut kill_player: EventWriter<KillPlayer>,
+    ) {
+        *timer = RunTimer::default();
+        respawn_point.0 = level.0.player_spawn;
+        kill_player.send_default();
+    }
+}
@@ -0,0 +1,257 @@
+pub(crate) mod surfaces {
+    //! What things are made of: how slippery and bouncy they are, and the sounds they make.
+    //!
+    //! A [`SurfaceMaterial`] can be set on a level primitive, and glTF meshes get one from their
+    //! material's name when their colliders are built (a material called "Ice_floor" is ice).
+    //! `apply_surface_physics` turns it into Rapier `Friction` and `Restitution`, with combine
+    //! rules that let ice stay slippery and bouncy pads stay bouncy whatever touches them.
+    //!
+    //! Dynamic bodies get a [`ContactForceEventThreshold`] of `MIN_IMPACT_FORCE`, so Rapier only
+    //! reports contacts that push harder than resting on the ground does. `impact_sounds` turns
+    //! each report into a sound picked by the surface that was hit, louder and lower the harder
+    //! the hit. Bodies with [`RollingSounds`] also loop a rolling or sliding sound for whatever
+    //! they're touching, louder the faster they go.
+
+    use bevy::prelude::*;
+    use bevy_kira_audio::AudioChannel;
+    use bevy_rapier3d::prelude::*;
+    use serde::{Serialize, Deserialize};
+
+    use crate::audio::audio::{AudioEmitter, PlaySound, Sfx, Sound, SoundBank};
+
+    // Contacts weaker than this don't make a sound. Resting contact is about 10 (gravity on the player).
+    pub(crate) const MIN_IMPACT_FORCE:f32 = 60.0;
+    // Contacts this strong or stronger play at full volume
+    const MAX_IMPACT_FORCE:f32 = 1500.0;
+    // Stone impacts stronger than this (from 0 to 1) use the heavy sound
+    const HARD_IMPACT:f32 = 0.5;
+    const MIN_IMPACT_VOLUME:f32 = 0.2;
+    // Rolling sounds are at full volume from this speed
+    const ROLL_FULL_SPEED:f32 = 12.0;
+    const ROLL_VOLUME:f32 = 0.6;
+
+    /// What a collider is made of. Colliders without one are stone.
+    #[derive(Component, Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+    pub(crate) enum SurfaceMaterial {
+        #[default]
+        Stone,
+        Metal,
+        Ice,
+        Rubber,
+        /// Launches whatever lands on it back up
+        Bouncy,
+    }
+
+    impl SurfaceMaterial {
+        /// Guesses the surface from a glTF material name, like "Ice_floor", "metal.002" or
+        /// "BouncePad". Only whole words count, so "Office" isn't ice and "Metallic" isn't metal.
+        pub(crate) fn from_material_name(name: &str) -> Option<SurfaceMaterial> {
+            let words = material_name_words(name);
+            let has = |word: &str| words.iter().any(|w| w == word);
+            if has("bouncy") || has("bounce") {
+                Some(SurfaceMaterial::Bouncy)
+            } else if has("ice") {
+                Some(SurfaceMaterial::Ice)
+            } else if has("rubber") {
+                Some(SurfaceMaterial::Rubber)
+            } else if has("metal") {
+                Some(SurfaceMaterial::Metal)
+            } else {
+                None
+            }
+        }
+
+        fn friction(&self) -> Friction {
+            match self {
+                SurfaceMaterial::Stone => Friction::default(),
+                SurfaceMaterial::Metal => Friction::coefficient(0.3),
+                // Min, so even grippy things slide on ice
+                SurfaceMaterial::Ice => Friction { coefficient: 0.02, combine_rule: CoefficientCombineRule::Min },
+                SurfaceMaterial::Rubber => Friction { coefficient: 1.5, combine_rule: CoefficientCombineRule::Max },
+                SurfaceMaterial::Bouncy => Friction::default(),
+            }
+        }
+
+        fn restitution(&self) -> Restitution {
+            match self {
+                SurfaceMaterial::Stone => Restitution::default(),
+                SurfaceMaterial::Metal => Restitution::coefficient(0.1),
+                SurfaceMaterial::Ice => Restitution::coefficient(0.05),
+                SurfaceMaterial::Rubber => Restitution { coefficient: 0.6, combine_rule: CoefficientCombineRule::Max },
+                // More than 1, so bounces get higher
+                SurfaceMaterial::Bouncy => Restitution { coefficient: 1.2, combine_rule: CoefficientCombineRule::Max },
+            }
+        }
+
+        fn impact_sound(&self, strength: f32) -> Sound {
+            match self {
+                SurfaceMaterial::Stone if strength > HARD_IMPACT => Sound::ImpactHard,
+                SurfaceMaterial::Stone => Sound::Thump,
+                SurfaceMaterial::Metal => Sound::ImpactMetal,
+                SurfaceMaterial::Ice => Sound::ImpactIce,
+                SurfaceMaterial::Rubber => Sound::ImpactRubber,
+                SurfaceMaterial::Bouncy => Sound::Boing,
+            }
+        }
+
+        /// The sound of rolling or sliding along this surface. Rubber and bouncy pads are quiet.
+        fn rolling_sound(&self) -> Option<Sound> {
+            match self {
+                SurfaceMaterial::Stone => Some(Sound::RollStone),
+                SurfaceMaterial::Metal => Some(Sound::RollMetal),
+                SurfaceMaterial::Ice => Some(Sound::RollIce),
+                SurfaceMaterial::Rubber | SurfaceMaterial::Bouncy => None,
+            }
+        }
+    }
+
+    /// Lowercase words of a material name, split on punctuation, digits and camelCase
+    fn material_name_words(name: &str) -> Vec<String> {
+        let mut words = vec![];
+        let mut word = String::new();
+        let mut previous: Option<char> = None;
+        for c in name.chars() {
+            let new_word = match previous {
+                _ if !c.is_alphabetic() => true,
+                Some(previous) => !previous.is_alphabetic() || (previous.is_lowercase() && c.is_uppercase()),
+                None => false,
+            };
+            if new_word && !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            if c.is_alphabetic() {
+                word.extend(c.to_lowercase());
+            }
+            previous = Some(c);
+        }
+        if !word.is_empty() {
+            words.push(word);
+        }
+        words
+    }
+
+    const ROLLING_SOUNDS: [Sound; 3] = [Sound::RollStone, Sound::RollMetal, Sound::RollIce];
+
+    /// Loops a rolling sound for the surface this body is touching. Needs a `Velocity`.
+    #[derive(Component)]
+    pub(crate) struct RollingSounds;
+
+    /// Components for dynamic bodies that should make impact sounds
+    pub(crate) fn impact_events() -> (ActiveEvents, ContactForceEventThreshold) {
+        (ActiveEvents::COLLISION_EVENTS | ActiveEvents::CONTACT_FORCE_EVENTS, ContactForceEventThreshold(MIN_IMPACT_FORCE))
+    }
+
+    pub(crate) fn apply_surface_physics(
+        mut commands: Commands,
+        surfaces: Query<(Entity, &SurfaceMaterial), Changed<SurfaceMaterial>>,
+    ) {
+        for (entity, surface) in &surfaces {
+            commands.entity(entity).insert((surface.friction(), surface.restitution()));
+        }
+    }
+
+    pub(crate) fn impact_sounds(
+        mut contact_forces: EventReader<ContactForceEvent>,
+        bodies: Query<&GlobalTransform, With<ContactForceEventThreshold>>,
+        surfaces: Query<&SurfaceMaterial>,
+        mut play_sound: EventWriter<PlaySound>,
+    ) {
+        for ev in contact_forces.iter() {
+            // The sound comes from the body that asked for impact events, and is picked by what it hit
+            let (body, other) = if bodies.contains(ev.collider1) { (ev.collider1, ev.collider2) } else { (ev.collider2, ev.collider1) };
+            let Ok(transform) = bodies.get(body) else { continue };
+            let surface = surfaces.get(other).copied().unwrap_or_default();
+            let strength = ((ev.max_force_magnitude - MIN_IMPACT_FORCE) / (MAX_IMPACT_FORCE - MIN_IMPACT_FORCE)).clamp(0.0, 1.0);
+
+            play_sound.send(PlaySound {
+                volume: MIN_IMPACT_VOLUME + (1.0 - MIN_IMPACT_VOLUME) * strength,
+                // Harder hits sound heavier
+                playback_rate: 1.15 - 0.3 * strength,
+                ..PlaySound::at(surface.impact_sound(strength), transform.translation())
+            });
+        }
+    }
+
+    /// Starts every rolling sound silently, so `update_rolling_sounds` only has to change volumes
+    pub(crate) fn start_rolling_sounds(
+        mut commands: Commands,
+        bank: Res<SoundBank>,
+        sfx: Res<AudioChannel<Sfx>>,
+        bodies: Query<Entity, Added<RollingSounds>>,
+    ) {
+        for entity in &bodies {
+            commands.entity(entity).insert(AudioEmitter::looping(&ROLLING_SOUNDS, 0.0, &bank, &sfx));
+        }
+    }
+
+    pub(crate) fn update_rolling_sounds(
+        rapier_context: Res<RapierContext>,
+        mut bodies: Query<(Entity, &Velocity, &mut AudioEmitter), With<RollingSounds>>,
+        surfaces: Query<&SurfaceMaterial>,
+    ) {
+        for (entity, velocity, mut emitter) in &mut bodies {
+            // Whatever it's touching, if it's touching anything. Sensors don't have contacts.
+            let touching = rapier_context.contacts_with(entity)
+                .find(|contact| contact.has_any_active_contacts())
+                .map(|contact| if contact.collider1() == entity { contact.collider2() } else { contact.collider1() });
+            let rolling_sound = touching.and_then(|other| surfaces.get(other).copied().unwrap_or_default().rolling_sound());
+            let volume = ROLL_VOLUME * (velocity.linvel.length() / ROLL_FULL_SPEED).min(1.0);
+            set_rolling_volumes(&mut emitter, rolling_sound, volume);
+        }
+    }
+
+    /// Plays `rolling_sound` at `volume` and silences the other rolling sounds still playing
+    fn set_rolling_volumes(emitter: &mut AudioEmitter, rolling_sound: Option<Sound>, volume: f32) {
+        for (sound, instance_volume) in emitter.volumes_mut() {
+            *instance_volume = if rolling_sound == Some(sound) { volume } else { 0.0 };
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn surfaces_from_material_names() {
+            assert_eq!(SurfaceMaterial::from_material_name("Ice_floor"), Some(SurfaceMaterial::Ice));
+            assert_eq!(SurfaceMaterial::from_material_name("metal.002"), Some(SurfaceMaterial::Metal));
+            assert_eq!(SurfaceMaterial::from_material_name("RUBBER"), Some(SurfaceMaterial::Rubber));
+            assert_eq!(SurfaceMaterial::from_material_name("BouncePad"), Some(SurfaceMaterial::Bouncy));
+            assert_eq!(SurfaceMaterial::from_material_name("Material.001"), None);
+            assert_eq!(SurfaceMaterial::from_material_name("IceFloor"), Some(SurfaceMaterial::Ice));
+            assert_eq!(SurfaceMaterial::from_material_name("floor-ice2"), Some(SurfaceMaterial::Ice));
+        }
+
+        #[test]
+        fn surfaces_only_match_whole_words() {
+            for name in ["Office", "Office_wall", "Lattice", "Device", "Police_tape", "Slice", "Metallic", "Metallic_paint", "Rubbery", "Bouncer"] {
+                assert_eq!(SurfaceMaterial::from_material_name(name), None, "{name}");
+            }
+        }
+
+        fn rolling_volumes(emitter: &mut AudioEmitter) -> Vec<(Sound, f32)> {
+            emitter.volumes_mut().map(|(sound, volume)| (sound, *volume)).collect()
+        }
+
+        #[test]
+        fn rolling_volumes_follow_sounds_when_one_is_missing() {
+            // roll_stone.wav didn't load, so its instance was dropped
+            let mut emitter = AudioEmitter::started(&[Sound::RollMetal, Sound::RollIce]);
+
+            set_rolling_volumes(&mut emitter, SurfaceMaterial::Metal.rolling_sound(), 0.5);
+            assert_eq!(rolling_volumes(&mut emitter), vec![(Sound::RollMetal, 0.5), (Sound::RollIce, 0.0)]);
+
+            set_rolling_volumes(&mut emitter, SurfaceMaterial::Ice.rolling_sound(), 0.3);
+            assert_eq!(rolling_volumes(&mut emitter), vec![(Sound::RollMetal, 0.0), (Sound::RollIce, 0.3)]);
+
+            set_rolling_volumes(&mut emitter, SurfaceMaterial::Stone.rolling_sound(), 0.3);
+            assert_eq!(rolling_volumes(&mut emitter), vec![(Sound::RollMetal, 0.0), (Sound::RollIce, 0.0)]);
+        }
+
+        #[test]
+        fn bouncy_wins_over_other_surfaces() {
+            assert_eq!(SurfaceMaterial::from_material_name("bouncy_metal"), Some(SurfaceMaterial::Bouncy));
+            assert_eq!(SurfaceMaterial::from_material_name("ice_rubber"), Some(SurfaceMaterial::Ice));
+        }
+    }
+}